@@ -0,0 +1,284 @@
+// checkconfig: Handle checking a configuration file for the check-config sub
+// command.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use clap::ArgMatches;
+use crate::cli;
+use crate::config::Config;
+use crate::errors::ExporterError;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use tracing::debug;
+
+#[cfg(feature = "auth")]
+use crate::config::ConfigValue;
+
+#[cfg(feature = "auth")]
+use crate::httpd::auth::BasicAuthConfig;
+
+// A problem found in the configuration file, with its position if known.
+#[derive(Debug, PartialEq)]
+struct Problem {
+    line:    Option<usize>,
+    column:  Option<usize>,
+    message: String,
+}
+
+impl Problem {
+    // Creates a problem with the keys leading to it, such as `web` and
+    // `listen-address`, which are looked up in the file for the line.
+    fn at_keys<S: AsRef<str>>(contents: &str, keys: &[S], message: String) -> Self {
+        Self {
+            line:    locate(contents, keys),
+            column:  None,
+            message: message,
+        }
+    }
+}
+
+// Handles checking the configuration file for the check-config sub command.
+// Every problem is printed as `file:line: message`, and an error is returned
+// if there were any.
+pub fn check_from(matches: &ArgMatches) -> Result<(), ExporterError> {
+    let path = matches.get_one::<PathBuf>("FILE")
+        .expect("no configuration file given");
+
+    let problems = check(path)?;
+
+    if problems.is_empty() {
+        println!("{}: OK", path.display());
+
+        return Ok(());
+    }
+
+    for problem in &problems {
+        let position = match (problem.line, problem.column) {
+            (Some(line), Some(column)) => format!(":{line}:{column}"),
+            (Some(line), None)         => format!(":{line}"),
+            (None, _)                  => String::new(),
+        };
+
+        eprintln!("{}{}: {}", path.display(), position, problem.message);
+    }
+
+    Err(ExporterError::ConfigCheckFailed(problems.len()))
+}
+
+// Checks the configuration file at the given path, returning the problems
+// found ordered by line. Problems without a line come last.
+fn check(path: &Path) -> Result<Vec<Problem>, ExporterError> {
+    debug!("Checking configuration file: {}", path.display());
+
+    let contents = fs::read_to_string(path)?;
+
+    // A file that can't be parsed has a single problem, at the position the
+    // parser gave up.
+    let config = match Config::parse(path, &contents) {
+        Ok(config) => config,
+        Err(err)   => return Ok(vec![parse_problem(&contents, err)]),
+    };
+
+    let mut problems: Vec<Problem> = config
+        .check()
+        .into_iter()
+        .map(|issue| {
+            Problem::at_keys(&contents, &issue.keys, issue.error.to_string())
+        })
+        .collect();
+
+    for (name, message) in cli::check_options(&config) {
+        let keys: Vec<&str> = name
+            .as_deref()
+            .map(|name| name.split('.').collect())
+            .unwrap_or_default();
+
+        problems.push(Problem::at_keys(&contents, &keys, message));
+    }
+
+    #[cfg(feature = "auth")]
+    // The auth config file is read as the exporter would, rather than only
+    // checking that it exists.
+    if let Some(ConfigValue::String(auth)) = config.options().get("web.auth-config") {
+        if let Err(err) = BasicAuthConfig::from_yaml(Path::new(auth)) {
+            let message = format!("web.auth-config {auth}: {err}");
+
+            problems.push(Problem::at_keys(&contents, &["web", "auth-config"], message));
+        }
+    }
+
+    problems.sort_by_key(|problem| problem.line.unwrap_or(usize::MAX));
+
+    Ok(problems)
+}
+
+// Returns the problem for a file that couldn't be parsed.
+fn parse_problem(contents: &str, err: ExporterError) -> Problem {
+    let (line, column) = match &err {
+        ExporterError::TomlError(err) => {
+            err.span()
+                .map(|span| position(contents, span.start))
+                .unzip()
+        },
+        ExporterError::YamlError(err) => {
+            err.location()
+                .map(|location| (location.line(), location.column()))
+                .unzip()
+        },
+        _ => (None, None),
+    };
+
+    // The TOML error display includes a snippet of the file, the message is
+    // enough alongside the position.
+    let message = match &err {
+        ExporterError::TomlError(err) => {
+            err.message().lines().collect::<Vec<&str>>().join(", ")
+        },
+        err => err.to_string(),
+    };
+
+    Problem {
+        line:    line,
+        column:  column,
+        message: message,
+    }
+}
+
+// Returns the line and column, both counted from 1, of the byte offset in
+// the contents.
+fn position(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map_or(before.len(), |newline| before.len() - newline - 1) + 1;
+
+    (line, column)
+}
+
+// Finds the line, counted from 1, that the given keys are on. Each key is
+// looked for from the line of the key before it, so that `web` and then
+// `listen-address` finds the listen address in the web section. Keys on the
+// same line, such as TOML's dotted keys, are also found. This is a best
+// effort, and None is returned if a key can't be found.
+fn locate<S: AsRef<str>>(contents: &str, keys: &[S]) -> Option<usize> {
+    if keys.is_empty() {
+        return None;
+    }
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let mut current = 0;
+
+    for key in keys {
+        let key = key.as_ref();
+
+        current += lines[current..]
+            .iter()
+            .position(|line| has_key(line, key))?;
+    }
+
+    Some(current + 1)
+}
+
+// Returns true if the line has the key in a key position, rather than as part
+// of a value. The key may be quoted, and is followed by one of the characters
+// that ends a key in YAML or TOML.
+fn has_key(line: &str, key: &str) -> bool {
+    // Comments can't hold keys.
+    let line = line.split('#').next().unwrap_or_default();
+
+    line.match_indices(key).any(|(start, _)| {
+        let before = line[..start].chars().next_back();
+        let after = line[start + key.len()..]
+            .trim_start_matches(['"', '\''])
+            .trim_start()
+            .chars()
+            .next();
+
+        let starts_key = before.map_or(true, |c| {
+            c.is_whitespace() || matches!(c, '.' | '[' | '{' | ',' | '"' | '\'')
+        });
+
+        let ends_key = matches!(after, Some(':' | '=' | '.' | ']'));
+
+        starts_key && ends_key
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn locate_nested_keys() {
+        let contents = indoc!(r#"
+            ---
+            jail:
+                listen-address: '^web'
+            web:
+                telemetry-path: /metrics
+                listen-address: '127.0.0.1:9452'
+        "#);
+
+        assert_eq!(locate(contents, &["web", "listen-address"]), Some(6));
+        assert_eq!(locate(contents, &["web", "nope"]), None);
+    }
+
+    #[test]
+    fn locate_toml_dotted_keys() {
+        let contents = indoc!(r#"
+            [web]
+            listen-address = "127.0.0.1:9452"
+
+            [collector]
+            cputime.smoothing = 0.5
+        "#);
+
+        assert_eq!(locate(contents, &["collector", "cputime", "smoothing"]), Some(5));
+    }
+
+    #[test]
+    fn position_of_offset() {
+        let contents = "a\nbc\nd";
+
+        assert_eq!(position(contents, 0), (1, 1));
+        assert_eq!(position(contents, 3), (2, 2));
+        assert_eq!(position(contents, 5), (3, 1));
+    }
+
+    #[test]
+    fn check_reports_every_problem() {
+        let path = Path::new("test-data/config_check_errors.yaml");
+        let problems = check(path).unwrap();
+        let lines: Vec<Option<usize>> = problems
+            .iter()
+            .map(|problem| problem.line)
+            .collect();
+
+        assert_eq!(lines, vec![Some(3), Some(5), Some(7), Some(9), Some(11)]);
+        assert_eq!(problems[0].message, "unknown collector: nope");
+        assert_eq!(problems[4].message, "unknown configuration option: web.listen-adress");
+    }
+
+    #[test]
+    fn check_reports_syntax_error_position() {
+        let path = Path::new("test-data/config_check_syntax.toml");
+        let problems = check(path).unwrap();
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, Some(3));
+    }
+
+    #[cfg(feature = "auth")]
+    #[test]
+    fn check_ok() {
+        let path = Path::new("test-data/config_unified.toml");
+        let problems = check(path).unwrap();
+
+        assert_eq!(problems, vec![]);
+    }
+}
@@ -1,13 +1,31 @@
 // Command line interface parsing validators
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
+#[cfg(feature = "remote_write")]
+use crate::exporter::sanitise_label_name;
+#[cfg(feature = "graphite")]
+use crate::push::{
+    is_path_safe,
+    Protocol,
+};
 use crate::file::FileExporterOutput;
+use crate::httpd::{
+    INFLUX_API_PATH,
+    JAILS_API_PATH,
+};
+use crate::user;
+use regex::Regex;
+#[cfg(feature = "push")]
+use reqwest::Url;
+#[cfg(feature = "push_http")]
+use std::fs;
 use std::net::SocketAddr;
 use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 use tracing::debug;
+use uzers::UsersCache;
 
-#[cfg(feature = "auth")]
 use std::path::PathBuf;
 
 #[cfg(feature = "auth")]
@@ -25,6 +43,19 @@ pub fn is_valid_basic_auth_config_path(s: &str) -> Result<PathBuf, String> {
     Ok(path.to_path_buf())
 }
 
+// Basic checks for valid filesystem path for config.file existing.
+pub fn is_valid_config_file_path(s: &str) -> Result<PathBuf, String> {
+    debug!("Ensuring that config.file is valid");
+
+    let path = Path::new(&s);
+
+    if !path.is_file() {
+        return Err("config.file doesn't exist".to_owned());
+    }
+
+    Ok(path.to_path_buf())
+}
+
 #[cfg(feature = "bcrypt_cmd")]
 // Ensures that a given bcrypt cost is valid
 pub fn is_valid_bcrypt_cost(s: &str) -> Result<u32, String> {
@@ -61,7 +92,33 @@ pub fn is_valid_length(s: &str) -> Result<usize, String> {
     Ok(length)
 }
 
-// Basic checks for valid filesystem path for .prom output file
+// Checks that a CPU smoothing factor is at least 0 and less than 1. A factor
+// of 1 would never move away from the first utilisation.
+pub fn is_valid_cpu_smoothing(s: &str) -> Result<f64, String> {
+    debug!("Ensuring that collector.cputime.smoothing is valid");
+
+    let smoothing = match s.parse::<f64>() {
+        Ok(smoothing) => smoothing,
+        Err(_)        => return Err(format!("could not parse '{s}' as a number")),
+    };
+
+    if !(0.0..1.0).contains(&smoothing) {
+        return Err("smoothing must be at least 0 and less than 1".to_owned());
+    }
+
+    Ok(smoothing)
+}
+
+// Checks that a jail name pattern is a valid regex. Patterns are anchored so
+// that they must match the whole jail name.
+pub fn is_valid_jail_name_regex(s: &str) -> Result<Regex, String> {
+    debug!("Ensuring that jail name regex is valid");
+
+    Regex::new(&format!("^(?:{s})$"))
+        .map_err(|e| format!("'{s}' is not a valid regex: {e}"))
+}
+
+// Basic checks for valid filesystem path for .prom or .json output file
 pub fn is_valid_output_file_path(s: &str) -> Result<FileExporterOutput, String> {
     debug!("Ensuring that output.file-path is valid");
 
@@ -83,16 +140,17 @@ pub fn is_valid_output_file_path(s: &str) -> Result<FileExporterOutput, String>
         return Err("output.file-path must not point at a directory".to_owned());
     }
 
-    // Node Exporter textfiles must end with .prom
+    // Node Exporter textfiles must end with .prom, while JSON output is
+    // written to .json files and line protocol to .lp files.
     if let Some(ext) = path.extension() {
-        // Got an extension, ensure that it's .prom
-        if ext != "prom" {
-            return Err("output.file-path must have .prom extension".to_owned());
+        // Got an extension, ensure that it's .prom, .json or .lp
+        if ext != "prom" && ext != "json" && ext != "lp" {
+            return Err("output.file-path must have .prom, .json or .lp extension".to_owned());
         }
     }
     else {
         // Didn't find an extension at all
-        return Err("output.file-path must have .prom extension".to_owned());
+        return Err("output.file-path must have .prom, .json or .lp extension".to_owned());
     }
 
     // Check that the directory exists
@@ -110,6 +168,160 @@ pub fn is_valid_output_file_path(s: &str) -> Result<FileExporterOutput, String>
     Ok(FileExporterOutput::File(path.to_path_buf()))
 }
 
+// Checks that the output file mode is an octal permission mode, such as 644
+// or 0640.
+pub fn is_valid_output_file_mode(s: &str) -> Result<u32, String> {
+    debug!("Ensuring that output.file-mode is valid");
+
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("'{s}' is not a valid octal file mode")),
+    }
+}
+
+// Checks that the output file owner is a known user or a numeric uid.
+pub fn is_valid_output_file_owner(s: &str) -> Result<u32, String> {
+    debug!("Ensuring that output.file-owner is valid");
+
+    user::uid_by_name(&UsersCache::new(), s)
+        .ok_or_else(|| format!("'{s}' is not a known user"))
+}
+
+// Checks that the output file group is a known group or a numeric gid.
+pub fn is_valid_output_file_group(s: &str) -> Result<u32, String> {
+    debug!("Ensuring that output.file-group is valid");
+
+    user::gid_by_name(&UsersCache::new(), s)
+        .ok_or_else(|| format!("'{s}' is not a known group"))
+}
+
+#[cfg(feature = "remote_write")]
+// Checks that an external label is a name=value pair with a valid label name.
+// Unlike static labels, the name isn't sanitised, since the receiver may
+// rely on the exact name, such as a tenant or replica label.
+pub fn is_valid_external_label(s: &str) -> Result<(String, String), String> {
+    debug!("Ensuring that external label is valid");
+
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("'{s}' is not a name=value pair"))?;
+
+    if sanitise_label_name(name).as_deref() != Some(name) {
+        return Err(format!("'{name}' is not a valid label name"));
+    }
+
+    Ok((name.to_string(), value.to_string()))
+}
+
+#[cfg(feature = "remote_write")]
+// Checks that a queue size is a positive number.
+pub fn is_valid_queue_size(s: &str) -> Result<usize, String> {
+    debug!("Ensuring that queue size is valid");
+
+    match s.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size),
+        _                    => Err(format!("'{s}' is not a positive number")),
+    }
+}
+
+#[cfg(feature = "graphite")]
+// Checks that a Graphite URL is a tcp URL, for the plaintext protocol, or a
+// udp URL, for StatsD, with a host and without a path.
+pub fn is_valid_graphite_url(s: &str) -> Result<Url, String> {
+    debug!("Ensuring that Graphite URL is valid");
+
+    let url = Url::parse(s).map_err(|e| format!("'{s}' is not a valid URL: {e}"))?;
+
+    if Protocol::from_scheme(url.scheme()).is_none() {
+        return Err(format!("unsupported URL scheme '{}', expected tcp or udp", url.scheme()));
+    }
+
+    if url.host_str().map_or(true, str::is_empty) {
+        return Err(format!("'{s}' has no host"));
+    }
+
+    if !matches!(url.path(), "" | "/") {
+        return Err(format!("'{s}' must not have a path"));
+    }
+
+    Ok(url)
+}
+
+#[cfg(feature = "graphite")]
+// Checks that a Graphite name replacement is a single path safe character.
+pub fn is_valid_name_replacement(s: &str) -> Result<char, String> {
+    debug!("Ensuring that Graphite name replacement is valid");
+
+    let mut chars = s.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if is_path_safe(c) => Ok(c),
+        _ => {
+            Err(format!("'{s}' must be a single letter, digit, '-' or '_'"))
+        },
+    }
+}
+
+#[cfg(feature = "push_http")]
+// Checks that a push URL is an absolute http or https URL.
+pub fn is_valid_push_url(s: &str) -> Result<Url, String> {
+    debug!("Ensuring that push URL is valid");
+
+    let url = Url::parse(s).map_err(|e| format!("'{s}' is not a valid URL: {e}"))?;
+
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme           => Err(format!("unsupported URL scheme '{scheme}'")),
+    }
+}
+
+#[cfg(feature = "push_http")]
+// Reads a secret, such as a password, from a file. A single trailing newline
+// is removed, since most editors add one.
+pub fn is_valid_secret_file(s: &str) -> Result<String, String> {
+    debug!("Ensuring that secret file is valid");
+
+    let secret = fs::read_to_string(s)
+        .map_err(|e| format!("could not read '{s}': {e}"))?;
+
+    let secret = secret
+        .strip_suffix('\n')
+        .map(|secret| secret.strip_suffix('\r').unwrap_or(secret))
+        .unwrap_or(&secret);
+
+    if secret.is_empty() {
+        return Err(format!("'{s}' is empty"));
+    }
+
+    Ok(secret.to_string())
+}
+
+// Checks that an interval is a positive number of seconds, minutes or hours,
+// such as 30s, 1m or 1h. A bare number is taken as seconds.
+pub fn is_valid_interval(s: &str) -> Result<Duration, String> {
+    debug!("Ensuring that interval is valid");
+
+    let (number, multiplier) = match s.chars().last() {
+        Some('s') => (&s[..s.len() - 1], 1),
+        Some('m') => (&s[..s.len() - 1], 60),
+        Some('h') => (&s[..s.len() - 1], 60 * 60),
+        _         => (s, 1),
+    };
+
+    let number = match number.parse::<u64>() {
+        Ok(number) => number,
+        Err(_)     => return Err(format!("could not parse '{s}' as an interval")),
+    };
+
+    if number == 0 {
+        return Err("interval must be greater than 0".to_owned());
+    }
+
+    number.checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("interval '{s}' is too large"))
+}
+
 #[cfg(feature = "bcrypt_cmd")]
 // Checks that a password is valid with some basic checks.
 pub fn is_valid_password(s: &str) -> Result<String, String> {
@@ -158,6 +370,13 @@ pub fn is_valid_telemetry_path(s: &str) -> Result<String, String> {
         return Err("path must not be /".to_owned());
     }
 
+    // Ensure that s doesn't clash with the JSON or InfluxDB APIs
+    for api_path in [JAILS_API_PATH, INFLUX_API_PATH] {
+        if s == api_path {
+            return Err(format!("path must not be {api_path}"));
+        }
+    }
+
     Ok(s.to_string())
 }
 
@@ -165,6 +384,46 @@ pub fn is_valid_telemetry_path(s: &str) -> Result<String, String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn is_valid_config_file_path_missing() {
+        let res = is_valid_config_file_path("test-data/nope.yaml");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_config_file_path_ok() {
+        let res = is_valid_config_file_path("test-data/config_collectors_ok.yaml");
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_cpu_smoothing_ok() {
+        assert_eq!(is_valid_cpu_smoothing("0"), Ok(0.0));
+        assert_eq!(is_valid_cpu_smoothing("0.5"), Ok(0.5));
+    }
+
+    #[test]
+    fn is_valid_cpu_smoothing_out_of_range() {
+        assert!(is_valid_cpu_smoothing("1").is_err());
+        assert!(is_valid_cpu_smoothing("-0.1").is_err());
+        assert!(is_valid_cpu_smoothing("NaN").is_err());
+        assert!(is_valid_cpu_smoothing("fast").is_err());
+    }
+
+    #[test]
+    fn is_valid_jail_name_regex_anchored() {
+        let regex = is_valid_jail_name_regex("ci-.*").unwrap();
+
+        assert!(regex.is_match("ci-1234"));
+        assert!(!regex.is_match("www-ci-1234"));
+    }
+
+    #[test]
+    fn is_valid_jail_name_regex_invalid() {
+        let res = is_valid_jail_name_regex("ci-(");
+        assert!(res.is_err());
+    }
+
     #[test]
     fn is_valid_output_file_path_absolute_path() {
         let res = is_valid_output_file_path("tmp/metrics.prom".into());
@@ -195,6 +454,18 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn is_valid_output_file_path_json() {
+        let res = is_valid_output_file_path("/tmp/jails.json");
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn is_valid_output_file_path_line_protocol() {
+        let res = is_valid_output_file_path("/tmp/jails.lp");
+        assert!(res.is_ok());
+    }
+
     #[test]
     fn is_valid_output_file_path_ok() {
         let res = is_valid_output_file_path("/tmp/metrics.prom".into());
@@ -213,6 +484,138 @@ mod tests {
         assert!(res.is_ok());
     }
 
+    #[test]
+    fn is_valid_output_file_mode_ok() {
+        assert_eq!(is_valid_output_file_mode("644"), Ok(0o644));
+        assert_eq!(is_valid_output_file_mode("0640"), Ok(0o640));
+        assert_eq!(is_valid_output_file_mode("1777"), Ok(0o1777));
+    }
+
+    #[test]
+    fn is_valid_output_file_mode_invalid() {
+        assert!(is_valid_output_file_mode("").is_err());
+        assert!(is_valid_output_file_mode("0o644").is_err());
+        assert!(is_valid_output_file_mode("689").is_err());
+        assert!(is_valid_output_file_mode("10000").is_err());
+        assert!(is_valid_output_file_mode("rw-r--r--").is_err());
+    }
+
+    #[test]
+    fn is_valid_output_file_owner_numeric() {
+        assert_eq!(is_valid_output_file_owner("4294967"), Ok(4294967));
+    }
+
+    #[test]
+    fn is_valid_output_file_owner_unknown() {
+        assert!(is_valid_output_file_owner("no-such-user-exists").is_err());
+    }
+
+    #[test]
+    fn is_valid_output_file_group_unknown() {
+        assert!(is_valid_output_file_group("no-such-group-exists").is_err());
+    }
+
+    #[cfg(feature = "remote_write")]
+    #[test]
+    fn is_valid_external_label_ok() {
+        let res = is_valid_external_label("cluster=edge-1");
+        assert_eq!(res, Ok(("cluster".to_string(), "edge-1".to_string())));
+
+        let res = is_valid_external_label("empty=");
+        assert_eq!(res, Ok(("empty".to_string(), String::new())));
+    }
+
+    #[cfg(feature = "remote_write")]
+    #[test]
+    fn is_valid_external_label_invalid() {
+        assert!(is_valid_external_label("cluster").is_err());
+        assert!(is_valid_external_label("=edge").is_err());
+        assert!(is_valid_external_label("a-b=c").is_err());
+        assert!(is_valid_external_label("__name__=c").is_err());
+    }
+
+    #[cfg(feature = "remote_write")]
+    #[test]
+    fn is_valid_queue_size_ok() {
+        assert_eq!(is_valid_queue_size("240"), Ok(240));
+        assert!(is_valid_queue_size("0").is_err());
+        assert!(is_valid_queue_size("-1").is_err());
+    }
+
+    #[cfg(feature = "graphite")]
+    #[test]
+    fn is_valid_graphite_url_ok() {
+        assert!(is_valid_graphite_url("tcp://graphite.example.com:2003").is_ok());
+        assert!(is_valid_graphite_url("udp://127.0.0.1").is_ok());
+        assert!(is_valid_graphite_url("udp://[::1]:8125/").is_ok());
+    }
+
+    #[cfg(feature = "graphite")]
+    #[test]
+    fn is_valid_graphite_url_invalid() {
+        assert!(is_valid_graphite_url("graphite:2003").is_err());
+        assert!(is_valid_graphite_url("http://graphite:2003").is_err());
+        assert!(is_valid_graphite_url("tcp:///metrics").is_err());
+        assert!(is_valid_graphite_url("tcp://graphite/metrics").is_err());
+    }
+
+    #[cfg(feature = "graphite")]
+    #[test]
+    fn is_valid_name_replacement_ok() {
+        assert_eq!(is_valid_name_replacement("_"), Ok('_'));
+        assert_eq!(is_valid_name_replacement("x"), Ok('x'));
+        assert!(is_valid_name_replacement(".").is_err());
+        assert!(is_valid_name_replacement("__").is_err());
+        assert!(is_valid_name_replacement("").is_err());
+    }
+
+    #[cfg(feature = "push_http")]
+    #[test]
+    fn is_valid_push_url_ok() {
+        let res = is_valid_push_url("https://pushgateway.example.com:9091/");
+        assert!(res.is_ok());
+    }
+
+    #[cfg(feature = "push_http")]
+    #[test]
+    fn is_valid_push_url_invalid() {
+        assert!(is_valid_push_url("pushgateway:9091").is_err());
+        assert!(is_valid_push_url("ftp://pushgateway/").is_err());
+        assert!(is_valid_push_url("/metrics").is_err());
+    }
+
+    #[cfg(feature = "push_http")]
+    #[test]
+    fn is_valid_secret_file_ok() {
+        let res = is_valid_secret_file("test-data/secret.txt");
+        assert_eq!(res, Ok("s3cr3t".to_string()));
+    }
+
+    #[cfg(feature = "push_http")]
+    #[test]
+    fn is_valid_secret_file_missing() {
+        let res = is_valid_secret_file("test-data/nope.txt");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_interval_ok() {
+        assert_eq!(is_valid_interval("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(is_valid_interval("15s"), Ok(Duration::from_secs(15)));
+        assert_eq!(is_valid_interval("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(is_valid_interval("1h"), Ok(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn is_valid_interval_invalid() {
+        assert!(is_valid_interval("0s").is_err());
+        assert!(is_valid_interval("-1m").is_err());
+        assert!(is_valid_interval("1.5m").is_err());
+        assert!(is_valid_interval("1d").is_err());
+        assert!(is_valid_interval("s").is_err());
+        assert!(is_valid_interval("").is_err());
+    }
+
     #[test]
     fn is_valid_socket_addr_ipv4_with_port() {
         let res = is_valid_socket_addr("127.0.0.1:9452".into());
@@ -255,6 +658,18 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn is_valid_telemetry_path_influx_api() {
+        let res = is_valid_telemetry_path("/api/v1/influx".into());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn is_valid_telemetry_path_jails_api() {
+        let res = is_valid_telemetry_path("/api/v1/jails".into());
+        assert!(res.is_err());
+    }
+
     #[test]
     fn is_valid_telemetry_path_relative() {
         let res = is_valid_telemetry_path("metrics".into());
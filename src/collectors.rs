@@ -0,0 +1,206 @@
+// collectors: Selection of the collectors that the exporter runs.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use rctl::Resource;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// The rctl resources that we can export, in the order that their metrics
+/// are registered.
+pub const RESOURCES: [Resource; 25] = [
+    Resource::CoreDumpSize,
+    Resource::CpuTime,
+    Resource::DataSize,
+    Resource::MaxProcesses,
+    Resource::MemoryLocked,
+    Resource::MemoryUse,
+    Resource::MsgqQueued,
+    Resource::MsgqSize,
+    Resource::NMsgq,
+    Resource::Nsem,
+    Resource::NSemop,
+    Resource::NShm,
+    Resource::NThreads,
+    Resource::OpenFiles,
+    Resource::PercentCpu,
+    Resource::PseudoTerminals,
+    Resource::ReadBps,
+    Resource::ReadIops,
+    Resource::ShmSize,
+    Resource::StackSize,
+    Resource::SwapUse,
+    Resource::VMemoryUse,
+    Resource::Wallclock,
+    Resource::WriteBps,
+    Resource::WriteIops,
+];
+
+/// A collector that can be enabled or disabled.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum CollectorKind {
+    /// A metric family populated from an rctl resource.
+    Resource(Resource),
+
+    /// Filesystem usage of jail roots and the mounts below them.
+    Filesystem,
+
+    /// Host totals, and the share of them used by each jail.
+    Host,
+
+    /// Summary of the process table of each jail.
+    Processes,
+
+    /// Network interface counters of VNET jails.
+    Vnet,
+
+    /// ZFS dataset properties of jail roots.
+    Zfs,
+}
+
+impl CollectorKind {
+    /// Collectors that aren't backed by an rctl resource.
+    const OTHERS: [Self; 5] = [
+        Self::Filesystem,
+        Self::Host,
+        Self::Processes,
+        Self::Vnet,
+        Self::Zfs,
+    ];
+
+    /// Returns every known collector.
+    pub fn all() -> impl Iterator<Item = Self> {
+        RESOURCES
+            .into_iter()
+            .map(Self::Resource)
+            .chain(Self::OTHERS)
+    }
+
+    /// Returns a boolean indicating if the collector is enabled by default.
+    pub fn default_enabled(&self) -> bool {
+        match self {
+            Self::Resource(_) => true,
+            Self::Filesystem  => false,
+            Self::Host        => false,
+            Self::Processes   => false,
+            Self::Vnet        => false,
+            Self::Zfs         => false,
+        }
+    }
+}
+
+impl fmt::Display for CollectorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Resource(resource) => write!(f, "{resource}"),
+            Self::Filesystem         => write!(f, "filesystem"),
+            Self::Host               => write!(f, "host"),
+            Self::Processes          => write!(f, "processes"),
+            Self::Vnet               => write!(f, "vnet"),
+            Self::Zfs                => write!(f, "zfs"),
+        }
+    }
+}
+
+impl FromStr for CollectorKind {
+    type Err = ExporterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all()
+            .find(|kind| kind.to_string() == s)
+            .ok_or_else(|| ExporterError::UnknownCollector(s.to_string()))
+    }
+}
+
+/// The set of enabled collectors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Collectors {
+    enabled: HashSet<CollectorKind>,
+}
+
+impl Default for Collectors {
+    fn default() -> Self {
+        let enabled = CollectorKind::all()
+            .filter(CollectorKind::default_enabled)
+            .collect();
+
+        Self {
+            enabled: enabled,
+        }
+    }
+}
+
+impl Collectors {
+    /// Returns a set with no collectors enabled.
+    pub fn none() -> Self {
+        Self {
+            enabled: HashSet::new(),
+        }
+    }
+
+    /// Returns a boolean indicating if the given collector is enabled.
+    pub fn is_enabled(&self, kind: &CollectorKind) -> bool {
+        self.enabled.contains(kind)
+    }
+
+    /// Returns a boolean indicating if the given rctl resource is enabled.
+    pub fn is_resource_enabled(&self, resource: &Resource) -> bool {
+        self.is_enabled(&CollectorKind::Resource(*resource))
+    }
+
+    /// Enables or disables the named collector. Unknown names are an error.
+    pub fn set(&mut self, name: &str, enabled: bool) -> Result<(), ExporterError> {
+        let kind = CollectorKind::from_str(name)?;
+
+        if enabled {
+            self.enabled.insert(kind);
+        }
+        else {
+            self.enabled.remove(&kind);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collectors_default_all_resources() {
+        let collectors = Collectors::default();
+
+        for resource in &RESOURCES {
+            assert!(collectors.is_resource_enabled(resource));
+        }
+    }
+
+    #[test]
+    fn collectors_default_filesystem_disabled() {
+        let collectors = Collectors::default();
+
+        assert!(!collectors.is_enabled(&CollectorKind::Filesystem));
+    }
+
+    #[test]
+    fn collectors_set_ok() {
+        let mut collectors = Collectors::none();
+
+        collectors.set("nmsgq", true).unwrap();
+        assert!(collectors.is_resource_enabled(&Resource::NMsgq));
+        assert!(!collectors.is_resource_enabled(&Resource::Nsem));
+
+        collectors.set("nmsgq", false).unwrap();
+        assert!(!collectors.is_resource_enabled(&Resource::NMsgq));
+    }
+
+    #[test]
+    fn collectors_set_unknown() {
+        let mut collectors = Collectors::default();
+        let res = collectors.set("nope", false);
+
+        assert!(res.is_err());
+    }
+}
@@ -0,0 +1,394 @@
+// config: This module deals with the exporter configuration file.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::collectors::CollectorKind;
+use crate::errors::ExporterError;
+use crate::exporter::{
+    is_reserved_label,
+    sanitise_label_name,
+    JailLabels,
+};
+use serde::{
+    Deserialize,
+    Serialize,
+};
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+use std::fs::{
+    self,
+    File,
+};
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(feature = "auth")]
+use crate::httpd::auth::BasicAuthConfig;
+
+// A value in the configuration file. YAML and TOML have different types, so
+// we only keep the ones that map onto command line options.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ConfigValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    List(Vec<ConfigValue>),
+    Map(BTreeMap<String, ConfigValue>),
+}
+
+impl ConfigValue {
+    // Returns the value as it would be given on the command line. Lists and
+    // maps have no single command line form.
+    pub fn as_arg(&self) -> Option<String> {
+        match self {
+            Self::Bool(b)    => Some(b.to_string()),
+            Self::Integer(i) => Some(i.to_string()),
+            Self::Float(f)   => Some(f.to_string()),
+            Self::String(s)  => Some(s.clone()),
+            Self::List(_)    => None,
+            Self::Map(_)     => None,
+        }
+    }
+}
+
+// Configuration file formats, chosen by file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    // Files ending in .toml are TOML, anything else is taken to be YAML.
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::Toml,
+            _            => Self::Yaml,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Config {
+    #[cfg(feature = "auth")]
+    // HTTP Basic Auth users, in the same form as the web.auth-config file.
+    pub basic_auth_users: Option<HashMap<String, String>>,
+
+    // Collectors to enable or disable, keyed by collector name.
+    pub collectors: Option<HashMap<String, bool>>,
+
+    // Static labels attached to every time series.
+    pub labels: Option<BTreeMap<String, String>>,
+
+    // Sections of command line options, such as web or output, keyed by the
+    // part of the long option before the first dot.
+    #[serde(flatten)]
+    pub sections: BTreeMap<String, ConfigValue>,
+}
+
+// A problem found while checking the configuration, along with the keys
+// leading to it, such as `collectors` and `nmsgq`.
+#[derive(Debug)]
+pub struct ConfigIssue {
+    pub keys:  Vec<String>,
+    pub error: ExporterError,
+}
+
+impl ConfigIssue {
+    fn new(keys: &[&str], error: ExporterError) -> Self {
+        Self {
+            keys:  keys.iter().map(ToString::to_string).collect(),
+            error: error,
+        }
+    }
+}
+
+impl Config {
+    // Loads a YAML or TOML config from the given path, depending on its
+    // extension, returning the Config
+    pub fn from_path(path: &Path) -> Result<Self, ExporterError> {
+        match ConfigFormat::from_path(path) {
+            ConfigFormat::Toml => Self::from_toml(path),
+            ConfigFormat::Yaml => Self::from_yaml(path),
+        }
+    }
+
+    // Loads a TOML config from the given path returning the Config
+    pub fn from_toml(path: &Path) -> Result<Self, ExporterError> {
+        let contents = fs::read_to_string(path)?;
+        let config: Self = toml::from_str(&contents)?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Loads a YAML config from the given path returning the Config
+    pub fn from_yaml(path: &Path) -> Result<Self, ExporterError> {
+        let file = File::open(path)?;
+        let reader = BufReader::new(file);
+        let config: Self = serde_yaml::from_reader(reader)?;
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    // Parses the contents of the config at the given path without validating
+    // it, so that every problem can be found with check.
+    pub fn parse(path: &Path, contents: &str) -> Result<Self, ExporterError> {
+        let config = match ConfigFormat::from_path(path) {
+            ConfigFormat::Toml => toml::from_str(contents)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
+        };
+
+        Ok(config)
+    }
+
+    // Returns the options set in the sections, keyed by their long option
+    // name. Nested maps are joined with dots, so `cputime: {smoothing: 0.5}`
+    // in the collector section is collector.cputime.smoothing.
+    pub fn options(&self) -> BTreeMap<String, &ConfigValue> {
+        let mut options = BTreeMap::new();
+
+        for (section, value) in &self.sections {
+            flatten_into(&mut options, section, value);
+        }
+
+        options
+    }
+
+    // Returns the static labels with sanitised names.
+    pub fn static_labels(&self) -> Result<JailLabels, ExporterError> {
+        let (static_labels, invalid) = self.sanitised_labels();
+
+        match invalid.into_iter().next() {
+            Some(name) => Err(ExporterError::InvalidLabelName(name)),
+            None       => Ok(static_labels),
+        }
+    }
+
+    // Returns the usable static labels with sanitised names, along with the
+    // names of the labels that are invalid or reserved.
+    fn sanitised_labels(&self) -> (JailLabels, Vec<String>) {
+        let mut static_labels = JailLabels::default();
+        let mut invalid = Vec::new();
+
+        let Some(labels) = &self.labels else {
+            return (static_labels, invalid);
+        };
+
+        for (name, value) in labels {
+            let sanitised = sanitise_label_name(name)
+                .filter(|n| !is_reserved_label(n))
+                .filter(|n| static_labels.get(n).is_none());
+
+            match sanitised {
+                Some(sanitised) => static_labels.push(&sanitised, value),
+                None            => invalid.push(name.clone()),
+            }
+        }
+
+        (static_labels, invalid)
+    }
+
+    // Checks that the configuration only refers to known collectors, and
+    // that the static labels are usable, returning every problem found.
+    // Whether the options exist is up to the command line parser.
+    pub fn check(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        if let Some(collectors) = &self.collectors {
+            for name in collectors.keys() {
+                if let Err(err) = CollectorKind::from_str(name) {
+                    issues.push(ConfigIssue::new(&["collectors", name], err));
+                }
+            }
+        }
+
+        for name in self.sanitised_labels().1 {
+            let err = ExporterError::InvalidLabelName(name.clone());
+
+            issues.push(ConfigIssue::new(&["labels", &name], err));
+        }
+
+        // Sections hold options, a bare value at the top level isn't one.
+        for (section, value) in &self.sections {
+            if !matches!(value, ConfigValue::Map(_)) {
+                let err = ExporterError::UnknownOption(section.clone());
+
+                issues.push(ConfigIssue::new(&[section], err));
+            }
+        }
+
+        #[cfg(feature = "auth")]
+        if let Some(users) = &self.basic_auth_users {
+            // Users can come from the configuration or the auth config file,
+            // but not both.
+            if self.options().contains_key("web.auth-config") {
+                let msg = "basic_auth_users conflicts with web.auth-config";
+                let err = ExporterError::InvalidOptionValue(msg.into());
+
+                issues.push(ConfigIssue::new(&["basic_auth_users"], err));
+            }
+
+            // Each user is checked on its own so that every bad user is
+            // reported.
+            for (username, hashed_password) in users {
+                let user = HashMap::from([
+                    (username.clone(), hashed_password.clone()),
+                ]);
+
+                if let Err(err) = BasicAuthConfig::from_users(user) {
+                    let keys = ["basic_auth_users", username.as_str()];
+
+                    issues.push(ConfigIssue::new(&keys, err));
+                }
+            }
+        }
+
+        issues
+    }
+
+    // Validates the configuration, returning the first problem found.
+    fn validate(&self) -> Result<(), ExporterError> {
+        match self.check().into_iter().next() {
+            Some(issue) => Err(issue.error),
+            None        => Ok(()),
+        }
+    }
+}
+
+// Adds the value to the options under the given name, descending into maps.
+fn flatten_into<'a>(
+    options: &mut BTreeMap<String, &'a ConfigValue>,
+    name: &str,
+    value: &'a ConfigValue,
+) {
+    match value {
+        ConfigValue::Map(map) => {
+            for (key, value) in map {
+                flatten_into(options, &format!("{name}.{key}"), value);
+            }
+        },
+        _ => {
+            options.insert(name.to_string(), value);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_from_yaml_collectors_ok() {
+        let path = Path::new("test-data/config_collectors_ok.yaml");
+        let config = Config::from_yaml(path).unwrap();
+        let collectors = config.collectors.unwrap();
+
+        assert_eq!(collectors.get("nmsgq"), Some(&false));
+        assert_eq!(collectors.get("cputime"), Some(&true));
+    }
+
+    #[test]
+    fn config_from_yaml_labels_ok() {
+        let path = Path::new("test-data/config_labels_ok.yaml");
+        let config = Config::from_yaml(path).unwrap();
+        let labels = config.static_labels().unwrap();
+
+        assert_eq!(labels.get("datacenter"), Some("ams1"));
+        assert_eq!(labels.get("rack_id"), Some("r12"));
+    }
+
+    #[test]
+    fn config_from_yaml_labels_reserved() {
+        let path = Path::new("test-data/config_labels_reserved.yaml");
+        let config = Config::from_yaml(path);
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn config_from_yaml_collectors_unknown() {
+        let path = Path::new("test-data/config_collectors_unknown.yaml");
+        let config = Config::from_yaml(path);
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn config_from_path_toml_matches_yaml() {
+        let toml = Config::from_path(Path::new("test-data/config_unified.toml"))
+            .unwrap();
+        let yaml = Config::from_path(Path::new("test-data/config_unified.yaml"))
+            .unwrap();
+
+        assert_eq!(toml.options(), yaml.options());
+        assert_eq!(toml.collectors, yaml.collectors);
+        assert_eq!(toml.labels, yaml.labels);
+    }
+
+    #[test]
+    fn config_options_flattened() {
+        let path = Path::new("test-data/config_unified.yaml");
+        let config = Config::from_path(path).unwrap();
+        let options = config.options();
+
+        assert_eq!(
+            options.get("web.listen-address"),
+            Some(&&ConfigValue::String("127.0.0.1:9500".into())),
+        );
+        assert_eq!(
+            options.get("collector.cputime.smoothing"),
+            Some(&&ConfigValue::Float(0.5)),
+        );
+        assert_eq!(
+            options.get("jail.hierarchy"),
+            Some(&&ConfigValue::Bool(true)),
+        );
+        assert_eq!(
+            options.get("collector.processes.top-n"),
+            Some(&&ConfigValue::Integer(3)),
+        );
+    }
+
+    #[test]
+    fn config_from_yaml_bare_section() {
+        let path = Path::new("test-data/config_bare_section.yaml");
+        let config = Config::from_yaml(path);
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn config_check_reports_every_issue() {
+        let path = Path::new("test-data/config_check_errors.yaml");
+        let contents = fs::read_to_string(path).unwrap();
+        let config = Config::parse(path, &contents).unwrap();
+        let keys: Vec<Vec<String>> = config
+            .check()
+            .into_iter()
+            .map(|issue| issue.keys)
+            .collect();
+
+        assert_eq!(keys, vec![
+            vec!["collectors".to_string(), "nope".to_string()],
+            vec!["labels".to_string(), "name".to_string()],
+        ]);
+    }
+
+    #[cfg(feature = "auth")]
+    #[test]
+    fn config_from_yaml_basic_auth_users() {
+        let path = Path::new("test-data/config_ok.yaml");
+        let config = Config::from_yaml(path).unwrap();
+        let users = config.basic_auth_users.unwrap();
+
+        assert!(users.contains_key("foo"));
+    }
+}
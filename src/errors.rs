@@ -22,9 +22,25 @@ pub enum ExporterError {
     #[error("bcrypt error with password for user: {0}")]
     BcryptValidationError(String),
 
+    /// Raised if check-config found problems with a configuration file.
+    #[error("configuration check found {0} problem(s)")]
+    ConfigCheckFailed(usize),
+
+    /// Raised if an external command used by a collector fails.
+    #[error("command failed: {0}")]
+    CommandError(String),
+
     #[error("HttpdError: {0}")]
     HttpdError(#[from] crate::httpd::HttpdError),
 
+    /// Raised if a configured label name is invalid or reserved.
+    #[error("Invalid label name: {0}")]
+    InvalidLabelName(String),
+
+    /// Raised if a configuration option has a value it can't take.
+    #[error("invalid value for configuration option: {0}")]
+    InvalidOptionValue(String),
+
     #[cfg(feature = "auth")]
     /// Raised if a configured username is invalid
     #[error("Invalid username: {0}")]
@@ -38,6 +54,11 @@ pub enum ExporterError {
     #[error("could not get jail name")]
     JailError(jail::JailError),
 
+    /// Raised if work moved to the blocking thread pool, such as a
+    /// collection, panics or is cancelled.
+    #[error("blocking task failed: {0}")]
+    JoinError(String),
+
     /// Raised if the `jail_exporter` is not running as root.
     #[error("jail_exporter must be run as root")]
     NotRunningAsRoot,
@@ -51,6 +72,17 @@ pub enum ExporterError {
     #[error("error occurred while persisting metrics")]
     PersistError(#[from] tempfile::PersistError),
 
+    #[cfg(feature = "push")]
+    /// Raised if pushing metrics to a remote receiver fails.
+    #[error("push failed: {0}")]
+    PushError(String),
+
+    #[cfg(feature = "push_http")]
+    /// Raised if a remote receiver rejects pushed metrics in a way that
+    /// sending them again wouldn't fix.
+    #[error("push rejected: {0}")]
+    PushRejected(String),
+
     /// Raised if there are errors originating within the `prometheus` crate.
     //#[error("error within Prometheus library")]
     //PrometheusError(#[from] prometheus::Error),
@@ -59,11 +91,30 @@ pub enum ExporterError {
     #[error("RACCT/RCTL: {0}")]
     RctlUnavailable(String),
 
+    /// Raised if a sysctl can't be read.
+    #[error("sysctl: {0}")]
+    SysctlError(#[from] sysctl::SysctlError),
+
+    /// Raised if a sysctl holds a value of an unexpected type or range.
+    #[error("unexpected value for sysctl: {0}")]
+    SysctlValueError(String),
+
+    /// Raised if there is an issue reading the TOML configuration
+    #[error("Failed to read TOML configuration")]
+    TomlError(#[from] toml::de::Error),
+
     /// Raised if there's an issue converting from UTF-8 to String
     #[error("Failed to convert UTF-8 to String")]
     Utf8Error(#[from] std::string::FromUtf8Error),
 
-    #[cfg(feature = "auth")]
+    /// Raised if the configuration refers to a collector that doesn't exist.
+    #[error("unknown collector: {0}")]
+    UnknownCollector(String),
+
+    /// Raised if the configuration sets an option that doesn't exist.
+    #[error("unknown configuration option: {0}")]
+    UnknownOption(String),
+
     /// Raised if there is an issue reading the YAML configuration
     #[error("Failed to read YAML configuration")]
     YamlError(#[from] serde_yaml::Error),
@@ -6,38 +6,122 @@ use crate::{
     register_gauge_with_registry,
     register_info_with_registry,
 };
+use crate::collectors::{
+    CollectorKind,
+    Collectors,
+    RESOURCES,
+};
 use crate::errors::ExporterError;
 use crate::httpd::{
     Collector,
     HttpdError,
 };
 use jail::RunningJail;
+use jail::param::Value;
 use parking_lot::Mutex;
 use prometheus_client::encoding::EncodeLabelSet;
-use prometheus_client::encoding::text::encode;
 use prometheus_client::metrics::{
     counter::Counter,
-    family::Family,
     gauge::Gauge,
 };
-use prometheus_client::registry::{
-    Registry,
-    Unit,
-};
 use rctl::Resource;
+use std::borrow::Cow;
 use std::collections::{
     HashMap,
     HashSet,
 };
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
-use tracing::debug;
+use std::time::{
+    Duration,
+    Instant,
+    SystemTime,
+};
+use tracing::{
+    debug,
+    warn,
+};
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
-struct NameLabel {
-    // Jail name.
-    name: String,
-}
+mod command;
+mod cpu;
+mod exposition;
+mod filesystem;
+mod filter;
+mod hierarchy;
+mod host;
+mod labels;
+mod processes;
+mod registry;
+mod resources;
+mod selfmetrics;
+mod vnet;
+mod zfs;
+
+#[cfg(test)]
+mod testing;
+
+use cpu::CpuUtilization;
+pub use cpu::{
+    DEFAULT_CPU_SMOOTHING,
+    DEFAULT_CPU_WINDOW,
+};
+pub use exposition::Format;
+use filesystem::FilesystemMetrics;
+pub use filter::JailFilter;
+pub use hierarchy::JailUsage;
+use hierarchy::JailTree;
+use host::{
+    HostMetrics,
+    HostSysctl,
+};
+pub use labels::{
+    is_reserved_label,
+    sanitise_label_name,
+    JailIdentity,
+    JailLabels,
+};
+use labels::JailSeries;
+
+pub use selfmetrics::{
+    HttpRequestLabels,
+    HttpRequests,
+};
+use processes::{
+    ProcessMetrics,
+    PsCommand,
+};
+pub use processes::{
+    DEFAULT_TOP_N,
+    MAX_TOP_N,
+};
+use registry::{
+    encode,
+    Family,
+    LabelPairs,
+    Registry,
+};
+use resources::ResourceMetadata;
+use selfmetrics::SelfMetrics;
+use vnet::{
+    VnetCommand,
+    VnetMetrics,
+};
+use zfs::{
+    ZfsCommand,
+    ZfsMetrics,
+};
+
+#[cfg(any(feature = "otlp", feature = "remote_write"))]
+pub use exposition::{
+    MetricFamily,
+    MetricSample,
+};
+
+#[cfg(feature = "otlp")]
+pub use resources::counter_family_names;
+
+#[cfg(feature = "graphite")]
+pub use exposition::ResourceUsage;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 struct VersionLabels {
@@ -48,12 +132,34 @@ struct VersionLabels {
     version: String,
 }
 
+impl LabelPairs for VersionLabels {
+    fn label_pairs(&self) -> Vec<(String, String)> {
+        vec![
+            ("rustversion".into(), self.rustversion.clone()),
+            ("version".into(), self.version.clone()),
+        ]
+    }
+}
+
 /// Type alias for our resource usage metrics coming from the rctl library.
 type Rusage = HashMap<Resource, usize>;
 
-/// Set of String representing jails that we have seen during the current
-/// scrape.
-type SeenJails = HashSet<String>;
+/// Set of label sets identifying the jails that we have seen during the
+/// current scrape.
+type SeenJails = HashSet<JailLabels>;
+
+/// A jail whose metrics were exported during the current scrape, handed to
+/// the collectors that aren't backed by rctl.
+pub struct ScrapedJail {
+    /// The jail ID.
+    pub jid: i32,
+
+    /// The jail name.
+    pub name: String,
+
+    /// The labels of the jail's time series.
+    pub labels: JailLabels,
+}
 
 /// Exporter structure containing the time series that are being tracked.
 pub struct Exporter {
@@ -61,256 +167,365 @@ pub struct Exporter {
     registry: Registry,
 
     // Prometheus time series
-    // These come from rctl
-    coredumpsize:    Family<NameLabel, Gauge>,
-    cputime:         Family<NameLabel, Counter>,
-    datasize:        Family<NameLabel, Gauge>,
-    memorylocked:    Family<NameLabel, Gauge>,
-    memoryuse:       Family<NameLabel, Gauge>,
-    msgqsize:        Family<NameLabel, Gauge>,
-    maxproc:         Family<NameLabel, Gauge>,
-    msgqqueued:      Family<NameLabel, Gauge>,
-    nmsgq:           Family<NameLabel, Gauge>,
-    nsem:            Family<NameLabel, Gauge>,
-    nsemop:          Family<NameLabel, Gauge>,
-    nshm:            Family<NameLabel, Gauge>,
-    nthr:            Family<NameLabel, Gauge>,
-    openfiles:       Family<NameLabel, Gauge>,
-    pcpu_used:       Family<NameLabel, Gauge>,
-    pseudoterminals: Family<NameLabel, Gauge>,
-    readbps:         Family<NameLabel, Gauge>,
-    readiops:        Family<NameLabel, Gauge>,
-    shmsize:         Family<NameLabel, Gauge>,
-    stacksize:       Family<NameLabel, Gauge>,
-    swapuse:         Family<NameLabel, Gauge>,
-    vmemoryuse:      Family<NameLabel, Gauge>,
-    wallclock:       Family<NameLabel, Counter>,
-    writebps:        Family<NameLabel, Gauge>,
-    writeiops:       Family<NameLabel, Gauge>,
+    // These come from rctl, keyed on the resource. Only the resources of
+    // enabled collectors are present.
+    counters: HashMap<Resource, Family<JailLabels, Counter>>,
+    gauges:   HashMap<Resource, Family<JailLabels, Gauge>>,
 
-    // Metrics this library generates
-    jail_id:  Family<NameLabel, Gauge>,
-    jail_num: Gauge,
+    // CPU utilisation worked out from the cputime, if that is enabled.
+    cpu_utilization: Option<CpuUtilization>,
 
-    // This keeps a record of which jails we saw on the last run. We use this
-    // to reap old jails (remove their label sets).
-    jail_names: Arc<Mutex<HashSet<String>>>,
+    // Metrics this library generates
+    jail_filtered: Gauge,
+    jail_id:       Family<JailLabels, Gauge>,
+    jail_num:      Gauge,
+
+    // Include and exclude patterns for jail names.
+    jail_filter: JailFilter,
+
+    // Metrics about the exporter itself, if enabled.
+    self_metrics: Option<SelfMetrics>,
+
+    // Metrics from collectors that aren't backed by rctl, if enabled.
+    filesystem: Option<FilesystemMetrics>,
+    host:       Option<HostMetrics>,
+    processes:  Option<ProcessMetrics>,
+    vnet:       Option<VnetMetrics>,
+    zfs:        Option<ZfsMetrics>,
+
+    // The labels used to identify a jail's time series.
+    jail_identity: JailIdentity,
+
+    // Whether hierarchy labels are added, and how the usage of jails with
+    // children is reported.
+    jail_hierarchy: bool,
+    jail_usage:     JailUsage,
+
+    // Labels attached to every time series, and the maximum number of labels
+    // to take from a jail's meta parameter, if enabled.
+    static_labels:     JailLabels,
+    meta_labels_limit: Option<usize>,
+
+    // This keeps a record of which jails we saw on the last run, and the
+    // labels their series were created with. We use this to reap old jails
+    // (remove their label sets).
+    jail_series: Arc<Mutex<HashMap<JailLabels, JailSeries>>>,
 }
 
 impl Default for Exporter {
-    // Descriptions of these metrics are taken from rctl(8) where possible.
-    #![allow(clippy::too_many_lines)]
     fn default() -> Self {
-        // We want to set this as a field in the returned struct, as well as
-        // pass it to the macros.
-        let mut registry = Registry::with_prefix("jail");
+        ExporterBuilder::new().build()
+    }
+}
 
-        let version_labels = VersionLabels {
-            rustversion: env!("RUSTC_VERSION").to_string(),
-            version: env!("CARGO_PKG_VERSION").to_string(),
-         };
+/// Builder used to configure an Exporter before its time series are
+/// registered.
+#[derive(Clone, Debug)]
+pub struct ExporterBuilder {
+    collectors:        Collectors,
+    cpu_smoothing:     f64,
+    cpu_window:        Duration,
+    exporter_metrics:  bool,
+    fs_deduplicate:    bool,
+    jail_filter:       JailFilter,
+    jail_hierarchy:    bool,
+    jail_identity:     JailIdentity,
+    jail_usage:        JailUsage,
+    meta_labels:       bool,
+    meta_labels_limit: usize,
+    processes_top_n:   u8,
+    static_labels:     JailLabels,
+}
 
-        // Static info metric, doesn't need to be in the struct.
-        register_info_with_registry!(
-            "exporter_build",
-            "A metric with constant '1' value labelled by version \
-             from which jail_exporter was built",
-            version_labels,
-            registry,
-        );
+/// The default maximum number of labels taken from a jail's meta parameter.
+pub const DEFAULT_META_LABELS_LIMIT: usize = 8;
 
+// Prefix that the registry applies to every metric name.
+const METRIC_PREFIX: &str = "jail";
+
+impl Default for ExporterBuilder {
+    fn default() -> Self {
         Self {
-            coredumpsize: register_gauge_with_registry!(
-                "coredumpsize",
-                "core dump size, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+            collectors:        Collectors::default(),
+            cpu_smoothing:     DEFAULT_CPU_SMOOTHING,
+            cpu_window:        DEFAULT_CPU_WINDOW,
+            exporter_metrics:  true,
+            fs_deduplicate:    false,
+            jail_filter:       JailFilter::default(),
+            jail_hierarchy:    false,
+            jail_identity:     JailIdentity::default(),
+            jail_usage:        JailUsage::default(),
+            meta_labels:       false,
+            meta_labels_limit: DEFAULT_META_LABELS_LIMIT,
+            processes_top_n:   DEFAULT_TOP_N,
+            static_labels:     JailLabels::default(),
+        }
+    }
+}
 
-            cputime: register_counter_with_registry!(
-                "cputime",
-                "CPU time, in seconds",
-                NameLabel,
-                Unit::Seconds,
-                registry,
-            ),
+impl ExporterBuilder {
+    /// Returns a new builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-            datasize: register_gauge_with_registry!(
-                "datasize",
-                "data size, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+    /// Sets the collectors that will be registered and populated.
+    pub fn collectors(mut self, collectors: Collectors) -> Self {
+        debug!("Setting collectors to: {:?}", collectors);
 
-            maxproc: register_gauge_with_registry!(
-                "maxproc",
-                "number of processes",
-                NameLabel,
-                registry,
-            ),
+        self.collectors = collectors;
+        self
+    }
 
-            memorylocked: register_gauge_with_registry!(
-                "memorylocked",
-                "locked memory, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+    /// Sets the weight given to the previous CPU utilisation when smoothing
+    /// it, between 0 for no smoothing and 1.
+    pub fn cpu_smoothing(mut self, smoothing: f64) -> Self {
+        debug!("Setting cpu_smoothing to: {}", smoothing);
 
-            memoryuse: register_gauge_with_registry!(
-                "memoryuse",
-                "resident set size, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+        self.cpu_smoothing = smoothing;
+        self
+    }
 
-            msgqqueued: register_gauge_with_registry!(
-                "msgqqueued",
-                "number of queued SysV messages",
-                NameLabel,
-                registry,
-            ),
+    /// Sets the time that the CPU utilisation is worked out over.
+    pub fn cpu_window(mut self, window: Duration) -> Self {
+        debug!("Setting cpu_window to: {:?}", window);
 
-            msgqsize: register_gauge_with_registry!(
-                "msgqsize",
-                "SysV message queue size, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+        self.cpu_window = window;
+        self
+    }
 
-            nmsgq: register_gauge_with_registry!(
-                "nmsgq",
-                "number of SysV message queues",
-                NameLabel,
-                registry,
-            ),
+    /// Enables or disables the metrics describing the exporter itself.
+    pub fn exporter_metrics(mut self, enabled: bool) -> Self {
+        debug!("Setting exporter_metrics to: {}", enabled);
 
-            nsem: register_gauge_with_registry!(
-                "nsem",
-                "number of SysV semaphores",
-                NameLabel,
-                registry,
-            ),
+        self.exporter_metrics = enabled;
+        self
+    }
 
-            nsemop: register_gauge_with_registry!(
-                "nsemop",
-                "number of SysV semaphores modified in a single semop(2) call",
-                NameLabel,
-                registry,
-            ),
+    /// Sets the include and exclude patterns for jail names.
+    pub fn jail_filter(mut self, jail_filter: JailFilter) -> Self {
+        debug!("Setting jail_filter to: {:?}", jail_filter);
 
-            nshm: register_gauge_with_registry!(
-                "nshm",
-                "number of SysV shared memory segments",
-                NameLabel,
-                registry,
-            ),
+        self.jail_filter = jail_filter;
+        self
+    }
 
-            nthr: register_gauge_with_registry!(
-                "nthr",
-                "number of threads",
-                NameLabel,
-                registry,
-            ),
+    /// Sets whether filesystems shared between jails are only reported once.
+    pub fn filesystem_deduplicate(mut self, enabled: bool) -> Self {
+        debug!("Setting fs_deduplicate to: {}", enabled);
 
-            openfiles: register_gauge_with_registry!(
-                "openfiles",
-                "file descriptor table size",
-                NameLabel,
-                registry,
-            ),
+        self.fs_deduplicate = enabled;
+        self
+    }
 
-            pcpu_used: register_gauge_with_registry!(
-                "pcpu_used",
-                "%CPU, in percents of a single CPU core",
-                NameLabel,
-                registry,
-            ),
+    /// Sets whether parent and depth labels are added to each jail's time
+    /// series.
+    pub fn jail_hierarchy(mut self, enabled: bool) -> Self {
+        debug!("Setting jail_hierarchy to: {}", enabled);
 
-            pseudoterminals: register_gauge_with_registry!(
-                "pseudoterminals",
-                "number of PTYs",
-                NameLabel,
-                registry,
-            ),
+        self.jail_hierarchy = enabled;
+        self
+    }
 
-            readbps: register_gauge_with_registry!(
-                "readbps",
-                "filesystem reads, in bytes per second",
-                NameLabel,
-                registry,
-            ),
+    /// Sets the labels used to identify a jail's time series.
+    pub fn jail_identity(mut self, jail_identity: JailIdentity) -> Self {
+        debug!("Setting jail_identity to: {}", jail_identity);
 
-            readiops: register_gauge_with_registry!(
-                "readiops",
-                "filesystem reads, in operations per second",
-                NameLabel,
-                registry,
-            ),
+        self.jail_identity = jail_identity;
+        self
+    }
 
-            shmsize: register_gauge_with_registry!(
-                "shmsize",
-                "SysV shared memory size, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+    /// Sets how the usage of jails with children is reported.
+    pub fn jail_usage(mut self, jail_usage: JailUsage) -> Self {
+        debug!("Setting jail_usage to: {}", jail_usage);
 
-            stacksize: register_gauge_with_registry!(
-                "stacksize",
-                "stack size, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+        self.jail_usage = jail_usage;
+        self
+    }
 
-            swapuse: register_gauge_with_registry!(
-                "swapuse",
-                "swap space that may be reserved or used, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+    /// Sets whether labels are parsed from each jail's meta parameter.
+    pub fn meta_labels(mut self, enabled: bool) -> Self {
+        debug!("Setting meta_labels to: {}", enabled);
 
-            vmemoryuse: register_gauge_with_registry!(
-                "vmemoryuse",
-                "address space limit, in bytes",
-                NameLabel,
-                Unit::Bytes,
-                registry,
-            ),
+        self.meta_labels = enabled;
+        self
+    }
 
-            wallclock: register_counter_with_registry!(
-                "wallclock",
-                "wallclock time, in seconds",
-                NameLabel,
-                Unit::Seconds,
-                registry,
-            ),
+    /// Sets the maximum number of labels parsed from a jail's meta parameter.
+    pub fn meta_labels_limit(mut self, limit: usize) -> Self {
+        debug!("Setting meta_labels_limit to: {}", limit);
 
-            writebps: register_gauge_with_registry!(
-                "writebps",
-                "filesystem writes, in bytes per second",
-                NameLabel,
-                registry,
-            ),
+        self.meta_labels_limit = limit;
+        self
+    }
+
+    /// Sets the number of processes reported by RSS and CPU for each jail.
+    pub fn processes_top_n(mut self, top_n: u8) -> Self {
+        debug!("Setting processes_top_n to: {}", top_n);
+
+        self.processes_top_n = top_n;
+        self
+    }
+
+    /// Sets labels which are attached to every time series. Label names are
+    /// expected to already be sanitised.
+    pub fn static_labels(mut self, labels: JailLabels) -> Self {
+        debug!("Setting static_labels to: {:?}", labels);
+
+        self.static_labels = labels;
+        self
+    }
 
-            writeiops: register_gauge_with_registry!(
-                "writeiops",
-                "filesystem writes, in operations per second",
-                NameLabel,
+    /// Registers the time series and returns the configured Exporter.
+    pub fn build(self) -> Exporter {
+        // We want to set this as a field in the returned struct, as well as
+        // pass it to the macros. Static labels are attached to everything
+        // registered here, including the sub-registries.
+        let static_labels = self.static_labels
+            .clone()
+            .into_iter()
+            .map(|(name, value)| (Cow::Owned(name), Cow::Owned(value)));
+
+        let mut registry = Registry::with_prefix_and_labels(
+            METRIC_PREFIX,
+            static_labels,
+        );
+
+        let version_labels = VersionLabels {
+            rustversion: env!("RUSTC_VERSION").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+         };
+
+        // Static info metric, doesn't need to be in the struct.
+        register_info_with_registry!(
+            "exporter_build",
+            "A metric with constant '1' value labelled by version \
+             from which jail_exporter was built",
+            version_labels,
+            registry,
+        );
+
+        // Register the families for each enabled rctl resource.
+        let mut counters = HashMap::new();
+        let mut gauges = HashMap::new();
+
+        let enabled = RESOURCES
+            .iter()
+            .filter(|r| self.collectors.is_resource_enabled(r));
+
+        for resource in enabled {
+            let ResourceMetadata {
+                name,
+                help,
+                unit,
+                counter,
+            } = resources::metadata(resource);
+
+            match (counter, unit) {
+                (true, Some(unit)) => {
+                    let family = register_counter_with_registry!(
+                        name,
+                        help,
+                        JailLabels,
+                        unit,
+                        registry,
+                    );
+
+                    counters.insert(*resource, family);
+                },
+                (true, None) => {
+                    let family = register_counter_with_registry!(
+                        name,
+                        help,
+                        JailLabels,
+                        registry,
+                    );
+
+                    counters.insert(*resource, family);
+                },
+                (false, Some(unit)) => {
+                    let family = register_gauge_with_registry!(
+                        name,
+                        help,
+                        JailLabels,
+                        unit,
+                        registry,
+                    );
+
+                    gauges.insert(*resource, family);
+                },
+                (false, None) => {
+                    let family = register_gauge_with_registry!(
+                        name,
+                        help,
+                        JailLabels,
+                        registry,
+                    );
+
+                    gauges.insert(*resource, family);
+                },
+            }
+        }
+
+        // CPU utilisation is derived from the cputime, so is only available
+        // alongside it.
+        let cpu_utilization = self.collectors
+            .is_resource_enabled(&Resource::CpuTime)
+            .then(|| {
+                CpuUtilization::new(&mut registry, self.cpu_smoothing, self.cpu_window)
+            });
+
+        // Exporter metrics live in their own sub-registry.
+        let self_metrics = if self.exporter_metrics {
+            Some(SelfMetrics::new(&mut registry))
+        }
+        else {
+            None
+        };
+
+        let filesystem = self.collectors
+            .is_enabled(&CollectorKind::Filesystem)
+            .then(|| FilesystemMetrics::new(&mut registry, self.fs_deduplicate));
+
+        let host = self.collectors
+            .is_enabled(&CollectorKind::Host)
+            .then(|| HostMetrics::new(&mut registry, Box::new(HostSysctl)));
+
+        let processes = self.collectors
+            .is_enabled(&CollectorKind::Processes)
+            .then(|| {
+                ProcessMetrics::new(
+                    &mut registry,
+                    self.processes_top_n,
+                    Box::new(PsCommand),
+                )
+            });
+
+        let vnet = self.collectors
+            .is_enabled(&CollectorKind::Vnet)
+            .then(|| VnetMetrics::new(&mut registry, Box::new(VnetCommand)));
+
+        let zfs = self.collectors
+            .is_enabled(&CollectorKind::Zfs)
+            .then(|| ZfsMetrics::new(&mut registry, Box::new(ZfsCommand)));
+
+        Exporter {
+            counters: counters,
+            gauges:   gauges,
+
+            cpu_utilization: cpu_utilization,
+
+            // Metrics created by the exporter
+            jail_filtered: register_gauge_with_registry!(
+                "filtered",
+                "Current number of running jails excluded by name filters",
                 registry,
             ),
 
-            // Metrics created by the exporter
             jail_id: register_gauge_with_registry!(
                 "id",
                 "ID of the named jail",
-                NameLabel,
+                JailLabels,
                 registry,
             ),
 
@@ -320,14 +535,30 @@ impl Default for Exporter {
                 registry,
             ),
 
+            jail_filter:   self.jail_filter,
+            jail_identity: self.jail_identity,
+
+            jail_hierarchy: self.jail_hierarchy,
+            jail_usage:     self.jail_usage,
+
+            static_labels:     self.static_labels,
+            meta_labels_limit: self.meta_labels.then_some(self.meta_labels_limit),
+
+            self_metrics: self_metrics,
+            filesystem:   filesystem,
+            host:         host,
+            processes:    processes,
+            vnet:         vnet,
+            zfs:          zfs,
+
             // Registry must be added after the macros making use of it
             registry: registry,
 
-            // Jail name tracking
-            // We keep a set of jails that we saw on the run, so that on the
+            // Jail tracking
+            // We keep a map of jails that we saw on the run, so that on the
             // next run, we can tell which jails have disappeared (if any) and
             // delete those metric families.
-            jail_names: Arc::new(Mutex::new(HashSet::new())),
+            jail_series: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 }
@@ -343,44 +574,127 @@ impl Exporter {
     /// ```
     /// let exporter = jail_exporter::Exporter::new();
     /// ```
+    // The binary configures its Exporter via ExporterBuilder, so this is only
+    // used by the tests.
+    #[allow(dead_code)]
     pub fn new() -> Self {
         Self::default()
     }
 
-    /// Collect and export the rctl metrics.
-    ///
-    /// This will return a `Vec<u8>` representing the Prometheus metrics
-    /// text format.
+    /// Collect and export the rctl metrics in the given format.
     ///
     /// # Example
     ///
     /// ```
     /// # let exporter = jail_exporter::Exporter::new();
-    /// let output = exporter.export();
+    /// let output = exporter.export_as(jail_exporter::Format::Text);
     /// ```
-    pub fn export(&self) -> Result<String, ExporterError> {
-        // Collect metrics
-        self.get_jail_metrics()?;
-
-        // Collect them in a buffer
-        let mut buffer = String::new(); //vec![];
-        encode(&mut buffer, &self.registry).expect("encode");
+    pub fn export_as(&self, format: Format) -> Result<Vec<u8>, ExporterError> {
+        let collected_at = SystemTime::now();
+
+        self.collect_metrics()?;
+
+        // prometheus_client only encodes OpenMetrics, which is converted
+        // for the classic text format. JSON, line protocol and protobuf are
+        // encoded straight from the values of the metrics.
+        let buffer = match format {
+            Format::OpenMetrics => self.encode_openmetrics().into_bytes(),
+            Format::Text        => {
+                exposition::openmetrics_to_text(&self.encode_openmetrics())
+                    .into_bytes()
+            },
+            Format::Json        => {
+                exposition::snapshot_to_json(
+                    &self.registry.snapshot(),
+                    self.jail_identity,
+                )
+            },
+            Format::Influx      => {
+                exposition::snapshot_to_influx(
+                    &self.registry.snapshot(),
+                    collected_at,
+                )
+            },
+
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf    => {
+                exposition::snapshot_to_protobuf(&self.registry.snapshot())
+            },
+        };
 
         // Return the exported metrics
         Ok(buffer)
     }
 
+    /// Collect the rctl metrics and return their families, for encodings
+    /// that aren't exposition formats.
+    #[cfg(any(feature = "otlp", feature = "remote_write"))]
+    pub fn families(&self) -> Result<Vec<MetricFamily>, ExporterError> {
+        self.collect_metrics()?;
+
+        Ok(exposition::snapshot_to_families(&self.registry.snapshot()))
+    }
+
+    /// Collect the rctl metrics and return the rctl usage of each jail, for
+    /// encodings that only carry the resources.
+    #[cfg(feature = "graphite")]
+    pub fn usage(&self) -> Result<Vec<ResourceUsage>, ExporterError> {
+        self.collect_metrics()?;
+
+        Ok(exposition::snapshot_to_usage(&self.registry.snapshot()))
+    }
+
+    // Collect the metrics into their time series.
+    fn collect_metrics(&self) -> Result<(), ExporterError> {
+        // Failed scrapes are counted and timed too, their errors having
+        // already been counted by type as they happened.
+        let start = Instant::now();
+        let collected = self.get_jail_metrics();
+
+        if let Some(self_metrics) = &self.self_metrics {
+            self_metrics.update_process();
+            self_metrics.observe_scrape(start.elapsed());
+        }
+
+        collected
+    }
+
+    // Encode the collected metrics as OpenMetrics text.
+    fn encode_openmetrics(&self) -> String {
+        let mut buffer = String::new();
+        encode(&mut buffer, &self.registry).expect("encode");
+
+        buffer
+    }
+
+    /// Returns a handle to the HTTP request counters, if exporter metrics
+    /// are enabled.
+    pub fn http_requests(&self) -> Option<HttpRequests> {
+        self.self_metrics
+            .as_ref()
+            .map(SelfMetrics::http_requests)
+    }
+
+    // Counts a scrape error of the given type, returning the error so that it
+    // can be propagated.
+    fn scrape_error<E>(&self, kind: &str, err: E) -> ExporterError
+    where E: Into<ExporterError> {
+        if let Some(self_metrics) = &self.self_metrics {
+            self_metrics.scrape_error(kind);
+        }
+
+        err.into()
+    }
+
     /// Processes the Rusage setting the appripriate time series.
-    fn process_rusage(&self, name: &str, metrics: &Rusage) {
+    fn process_rusage(&self, series: &JailSeries, metrics: &Rusage) {
         debug!("process_metrics_hash");
 
-        // Add the jail name to seen jails.
-        self.add_seen_jail(name);
+        // Add the jail to seen jails.
+        self.add_seen_jail(series);
 
         // Convenience variable
-        let labels = &NameLabel {
-            name: name.to_string(),
-        };
+        let labels = &series.labels;
 
         for (key, value) in metrics {
             // The original value comes in as a usize. Convert it to a u64 for
@@ -393,92 +707,24 @@ impl Exporter {
             let signed = i64::try_from(unsigned.clamp(0, i64::MAX as u64))
                 .expect("i64 from u64");
 
-            match key {
-                Resource::CoreDumpSize => {
-                    self.coredumpsize.get_or_create(labels).set(signed);
-                },
-                Resource::CpuTime => {
-                    // CPU time should only ever increase. Store the value from
-                    // the OS directly.
-                    self.cputime
-                        .get_or_create(labels)
-                        .inner()
-                        .store(unsigned, Ordering::Relaxed);
-                },
-                Resource::DataSize => {
-                    self.datasize.get_or_create(labels).set(signed);
-                },
-                Resource::MaxProcesses => {
-                    self.maxproc.get_or_create(labels).set(signed);
-                },
-                Resource::MemoryLocked => {
-                    self.memorylocked.get_or_create(labels).set(signed);
-                },
-                Resource::MemoryUse => {
-                    self.memoryuse.get_or_create(labels).set(signed);
-                },
-                Resource::MsgqQueued => {
-                    self.msgqqueued.get_or_create(labels).set(signed);
-                },
-                Resource::MsgqSize => {
-                    self.msgqsize.get_or_create(labels).set(signed);
-                },
-                Resource::NMsgq => {
-                    self.nmsgq.get_or_create(labels).set(signed);
-                },
-                Resource::Nsem => {
-                    self.nsem.get_or_create(labels).set(signed);
-                },
-                Resource::NSemop => {
-                    self.nsemop.get_or_create(labels).set(signed);
-                },
-                Resource::NShm => {
-                    self.nshm.get_or_create(labels).set(signed);
-                },
-                Resource::NThreads => {
-                    self.nthr.get_or_create(labels).set(signed);
-                },
-                Resource::OpenFiles => {
-                    self.openfiles.get_or_create(labels).set(signed);
-                },
-                Resource::PercentCpu => {
-                    self.pcpu_used.get_or_create(labels).set(signed);
-                },
-                Resource::PseudoTerminals => {
-                    self.pseudoterminals.get_or_create(labels).set(signed);
-                },
-                Resource::ReadBps => {
-                    self.readbps.get_or_create(labels).set(signed);
-                },
-                Resource::ReadIops => {
-                    self.readiops.get_or_create(labels).set(signed);
-                },
-                Resource::ShmSize => {
-                    self.shmsize.get_or_create(labels).set(signed);
-                },
-                Resource::StackSize => {
-                    self.stacksize.get_or_create(labels).set(signed);
-                },
-                Resource::SwapUse => {
-                    self.swapuse.get_or_create(labels).set(signed);
-                },
-                Resource::VMemoryUse => {
-                    self.vmemoryuse.get_or_create(labels).set(signed);
-                },
-                Resource::Wallclock => {
-                    // Wallclock should only ever increase, store the value
-                    // from the OS directly.
-                    self.wallclock
-                        .get_or_create(labels)
-                        .inner()
-                        .store(unsigned, Ordering::Relaxed);
-                },
-                Resource::WriteBps => {
-                    self.writebps.get_or_create(labels).set(signed);
-                },
-                Resource::WriteIops => {
-                    self.writeiops.get_or_create(labels).set(signed);
-                },
+            // Resources of disabled collectors won't be found in either
+            // map and are skipped.
+            if let Some(counter) = self.counters.get(key) {
+                // Counters such as CPU time should only ever increase. Store
+                // the value from the OS directly.
+                counter
+                    .get_or_create(labels)
+                    .inner()
+                    .store(unsigned, Ordering::Relaxed);
+            }
+            else if let Some(gauge) = self.gauges.get(key) {
+                gauge.get_or_create(labels).set(signed);
+            }
+        }
+
+        if let Some(cpu_utilization) = &self.cpu_utilization {
+            if let Some(cputime) = metrics.get(&Resource::CpuTime) {
+                cpu_utilization.observe(labels, *cputime, Instant::now());
             }
         }
     }
@@ -486,31 +732,128 @@ impl Exporter {
     fn get_jail_metrics(&self) -> Result<(), ExporterError> {
         debug!("get_jail_metrics");
 
-        // Set jail_total to zero before gathering.
+        // Set jail_total and jail_filtered to zero before gathering.
         self.jail_num.set(0);
+        self.jail_filtered.set(0);
 
         // Get a new vec of seen jails.
         let mut seen = SeenJails::new();
 
-        // Loop over jails.
+        // Usage of each jail, and the jails whose metrics we export. The
+        // usage of filtered jails is only needed to work out their parents'
+        // own usage, and the usage of processes outside of any jail.
+        let mut usage = HashMap::new();
+        let mut exported = Vec::new();
+
+        // Loop over jails, gathering their usage.
         for jail in RunningJail::all() {
-            let name = jail.name()?;
-            let rusage = jail.racct_statistics()?;
+            let start = Instant::now();
+
+            let name = jail.name()
+                .map_err(|e| self.scrape_error("jail_name", e))?;
+
+            // All running jails are counted, even those we filter out.
+            self.jail_num.inc();
+
+            // Skip jails excluded by the name filters. They aren't added to
+            // the seen jails, so any existing time series will be reaped.
+            let filtered = !self.jail_filter.is_match(&name);
+            if filtered {
+                debug!("Skipping filtered jail: {}", name);
+                self.jail_filtered.inc();
+
+                if self.jail_usage == JailUsage::Rollup && self.host.is_none() {
+                    continue;
+                }
+            }
+
+            let rusage = jail.racct_statistics()
+                .map_err(|e| self.scrape_error("racct", e))?;
 
             debug!("JID: {}, Name: {:?}", jail.jid, name);
 
+            usage.insert(name.clone(), rusage);
+
+            if !filtered {
+                exported.push((jail, name));
+            }
+
+            if let Some(self_metrics) = &self.self_metrics {
+                self_metrics.observe_jail_collection(start.elapsed());
+            }
+        }
+
+        // The kernel includes the usage of child jails in their parents, so
+        // if we want each jail's own usage, the children must be removed.
+        if self.jail_usage == JailUsage::Own {
+            let tree = JailTree::new(usage.keys().map(String::as_str));
+            usage = tree.own_usage(&usage);
+        }
+
+        // Jails handed on to the collectors that aren't backed by rctl.
+        let mut scraped = Vec::new();
+
+        for (jail, name) in exported {
+            // Work out the labels for this jail's time series.
+            let series = self.jail_identity
+                .series(jail.jid, &name)
+                .with_labels(self.hierarchy_labels(&name))
+                .with_labels(self.meta_labels(&jail));
+
             // Add to our vec of seen jails.
-            seen.insert(name.clone());
+            seen.insert(series.key.clone());
 
-            // Process rusage for the named jail, setting time series.
-            self.process_rusage(&name, &rusage);
+            // Process rusage for the jail, setting time series.
+            self.process_rusage(&series, &usage[&name]);
 
-            let labels = &NameLabel {
-                name: name,
-            };
+            self.jail_id
+                .get_or_create(&series.id_labels)
+                .set(i64::from(jail.jid));
 
-            self.jail_id.get_or_create(labels).set(i64::from(jail.jid));
-            self.jail_num.set(self.jail_num.get() + 1);
+            scraped.push(ScrapedJail {
+                jid:    jail.jid,
+                name:   name,
+                labels: series.labels,
+            });
+        }
+
+        // Failures of these collectors are counted, but don't fail the
+        // scrape as the rctl metrics are still good.
+        if let Some(filesystem) = &self.filesystem {
+            if let Err(e) = filesystem.collect(&scraped) {
+                let e = self.scrape_error("filesystem", e);
+                warn!("Filesystem collection failed: {}", e);
+            }
+        }
+
+        if let Some(host) = &self.host {
+            let jailed = hierarchy::total_usage(&usage, self.jail_usage);
+
+            if let Err(e) = host.collect(&scraped, &usage, &jailed) {
+                let e = self.scrape_error("host", e);
+                warn!("Host collection failed: {}", e);
+            }
+        }
+
+        if let Some(processes) = &self.processes {
+            if let Err(e) = processes.collect(&scraped) {
+                let e = self.scrape_error("processes", e);
+                warn!("Process collection failed: {}", e);
+            }
+        }
+
+        if let Some(vnet) = &self.vnet {
+            if let Err(e) = vnet.collect(&scraped) {
+                let e = self.scrape_error("vnet", e);
+                warn!("VNET collection failed: {}", e);
+            }
+        }
+
+        if let Some(zfs) = &self.zfs {
+            if let Err(e) = zfs.collect(&scraped) {
+                let e = self.scrape_error("zfs", e);
+                warn!("ZFS collection failed: {}", e);
+            }
         }
 
         // Get a list of dead jails based on what we've seen, and reap them.
@@ -521,74 +864,116 @@ impl Exporter {
         Ok(())
     }
 
-    fn add_seen_jail(&self, seen: &str) {
-        let mut names = self.jail_names.lock();
-        names.insert(seen.to_string());
+    // Returns the parent and depth labels for the named jail, if enabled.
+    // Jails at the top of the hierarchy have no parent label.
+    fn hierarchy_labels(&self, name: &str) -> JailLabels {
+        let mut labels = JailLabels::default();
+
+        if !self.jail_hierarchy {
+            return labels;
+        }
+
+        if let Some(parent) = hierarchy::parent(name) {
+            labels.push("parent", parent);
+        }
+
+        labels.push("depth", &hierarchy::depth(name).to_string());
+
+        labels
+    }
+
+    // Returns the labels parsed from the jail's meta parameter, if enabled.
+    // Older FreeBSD releases don't have the meta parameter, so a failure to
+    // read it just results in no labels.
+    fn meta_labels(&self, jail: &RunningJail) -> JailLabels {
+        let Some(limit) = self.meta_labels_limit else {
+            return JailLabels::default();
+        };
+
+        match jail.param("meta") {
+            Ok(Value::String(meta)) => {
+                JailLabels::from_meta(&meta, &self.static_labels, limit)
+            },
+            Ok(_) => JailLabels::default(),
+            Err(e) => {
+                debug!("Couldn't get meta parameter for jail: {}", e);
+                JailLabels::default()
+            },
+        }
     }
 
-    fn remove_dead_jails(&self, dead: &SeenJails) {
-        let mut names = self.jail_names.lock();
-        *names = &*names - dead;
+    fn add_seen_jail(&self, series: &JailSeries) {
+        let mut jails = self.jail_series.lock();
+        let previous = jails.insert(series.key.clone(), series.clone());
+
+        // If the jail is still around but its labels have changed, for
+        // example a jail keyed on its JID was renamed, the old series must
+        // go.
+        if let Some(previous) = previous {
+            if previous != *series {
+                self.remove_jail_metrics(&previous);
+            }
+        }
     }
 
-    // Loop over jail names from the previous run, as determined by book
-    // keeping, and create a vector of jail names that no longer exist.
-    fn dead_jails(&self, seen: &SeenJails) -> HashSet<String> {
-        let names = self.jail_names.lock();
-        &*names - seen
+    fn remove_dead_jails(&self, dead: &SeenJails) -> Vec<JailSeries> {
+        let mut jails = self.jail_series.lock();
+
+        dead.iter()
+            .filter_map(|key| jails.remove(key))
+            .collect()
+    }
+
+    // Loop over jails from the previous run, as determined by book keeping,
+    // and create a set of jails that no longer exist.
+    fn dead_jails(&self, seen: &SeenJails) -> SeenJails {
+        let jails = self.jail_series.lock();
+
+        jails.keys()
+            .filter(|key| !seen.contains(*key))
+            .cloned()
+            .collect()
     }
 
     // Loop over dead jails removing old labels and killing old book keeping.
     fn reap(&self, dead: SeenJails) {
-        self.remove_dead_jails(&dead);
+        let dead = self.remove_dead_jails(&dead);
+
+        if let Some(self_metrics) = &self.self_metrics {
+            self_metrics.reaped(dead.len());
+        }
 
-        for name in dead {
-            self.remove_jail_metrics(&name);
+        for series in dead {
+            self.remove_jail_metrics(&series);
         }
     }
 
-    fn remove_jail_metrics(&self, name: &str) {
+    fn remove_jail_metrics(&self, series: &JailSeries) {
         // Convenience variable
-        let labels = &NameLabel {
-            name: name.to_string(),
-        };
+        let labels = &series.labels;
 
         // Remove the jail metrics
-        self.coredumpsize.remove(labels);
-        self.cputime.remove(labels);
-        self.datasize.remove(labels);
-        self.maxproc.remove(labels);
-        self.memorylocked.remove(labels);
-        self.memoryuse.remove(labels);
-        self.msgqqueued.remove(labels);
-        self.msgqsize.remove(labels);
-        self.nmsgq.remove(labels);
-        self.nsem.remove(labels);
-        self.nsemop.remove(labels);
-        self.nshm.remove(labels);
-        self.nthr.remove(labels);
-        self.openfiles.remove(labels);
-        self.pcpu_used.remove(labels);
-        self.pseudoterminals.remove(labels);
-        self.readbps.remove(labels);
-        self.readiops.remove(labels);
-        self.shmsize.remove(labels);
-        self.stacksize.remove(labels);
-        self.swapuse.remove(labels);
-        self.vmemoryuse.remove(labels);
-        self.wallclock.remove(labels);
-        self.writebps.remove(labels);
-        self.writeiops.remove(labels);
+        for counter in self.counters.values() {
+            counter.remove(labels);
+        }
+
+        for gauge in self.gauges.values() {
+            gauge.remove(labels);
+        }
+
+        if let Some(cpu_utilization) = &self.cpu_utilization {
+            cpu_utilization.remove(labels);
+        }
 
         //// Reset metrics we generated.
-        self.jail_id.remove(labels);
+        self.jail_id.remove(&series.id_labels);
     }
 }
 
 /// Implements the Collector trait used by the Httpd component.
 impl Collector for Exporter {
-    fn collect(&self) -> Result<String, HttpdError> {
-        self.export()
+    fn collect(&self, format: Format) -> Result<Vec<u8>, HttpdError> {
+        self.export_as(format)
             .map_err(|e| HttpdError::CollectorError(e.to_string()))
     }
 }
@@ -600,6 +985,11 @@ mod tests {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    // Series for a jail keyed on its name.
+    fn series(name: &str) -> JailSeries {
+        JailIdentity::Name.series(0, name)
+    }
+
     #[test]
     fn cputime_counter_increase() {
         let names = ["test", "test2"];
@@ -608,40 +998,109 @@ mod tests {
         for name in names.iter() {
             let mut hash = Rusage::new();
 
-            let labels = &NameLabel {
-                name: name.to_string(),
-            };
+            let series = series(name);
+            let labels = &series.labels;
 
             // Initial check, should be zero. We didn't set anything yet.
-            assert_eq!(exporter.cputime.get_or_create(labels).get(), 0);
+            assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 0);
 
             // First run, adds 1000, total 1000.
             hash.insert(Resource::CpuTime, 1000);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.cputime.get_or_create(labels).get(), 1000);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 1000);
 
             // Second, adds 20, total 1020
             hash.insert(Resource::CpuTime, 1020);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.cputime.get_or_create(labels).get(), 1020);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 1020);
 
             // Third, counter was reset. Adds 10, total 1030.
             hash.insert(Resource::CpuTime, 10);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.cputime.get_or_create(labels).get(), 10);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 10);
 
             // Fourth, adds 40, total 1070.
             hash.insert(Resource::CpuTime, 50);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.cputime.get_or_create(labels).get(), 50);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 50);
 
             // Fifth, add 0, total 1070
             hash.insert(Resource::CpuTime, 50);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.cputime.get_or_create(labels).get(), 50);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 50);
         }
     }
 
+    #[test]
+    fn disabled_collectors_not_registered() {
+        let mut collectors = Collectors::default();
+        collectors.set("nmsgq", false).unwrap();
+
+        let exporter = ExporterBuilder::new()
+            .collectors(collectors)
+            .build();
+
+        let mut hash = Rusage::new();
+        hash.insert(Resource::NMsgq, 10);
+        hash.insert(Resource::NThreads, 10);
+        exporter.process_rusage(&series("test"), &hash);
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &exporter.registry).unwrap();
+
+        assert!(!exporter.gauges.contains_key(&Resource::NMsgq));
+        assert!(!buffer.contains("jail_nmsgq"));
+        assert!(buffer.contains("jail_nthr{name=\"test\"} 10"));
+    }
+
+    #[test]
+    fn static_labels_on_every_series() {
+        let mut labels = JailLabels::default();
+        labels.push("datacenter", "ams1");
+
+        let exporter = ExporterBuilder::new()
+            .static_labels(labels)
+            .build();
+
+        let mut hash = Rusage::new();
+        hash.insert(Resource::NThreads, 10);
+        exporter.process_rusage(&series("test"), &hash);
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &exporter.registry).unwrap();
+
+        let samples = buffer
+            .lines()
+            .filter(|l| !l.starts_with('#'));
+
+        for sample in samples {
+            assert!(sample.contains("datacenter=\"ams1\""), "{sample}");
+        }
+    }
+
+    #[test]
+    fn hierarchy_labels_ok() {
+        let exporter = ExporterBuilder::new()
+            .jail_hierarchy(true)
+            .build();
+
+        let labels = exporter.hierarchy_labels("outer.inner");
+        assert_eq!(labels.get("parent"), Some("outer"));
+        assert_eq!(labels.get("depth"), Some("1"));
+
+        let labels = exporter.hierarchy_labels("outer");
+        assert_eq!(labels.get("parent"), None);
+        assert_eq!(labels.get("depth"), Some("0"));
+    }
+
+    #[test]
+    fn hierarchy_labels_disabled() {
+        let exporter = Exporter::new();
+        let labels = exporter.hierarchy_labels("outer.inner");
+
+        assert_eq!(labels, JailLabels::default());
+    }
+
     #[test]
     fn dead_jails_ok() {
         let names = ["test_a", "test_b", "test_c"];
@@ -651,19 +1110,19 @@ mod tests {
         for name in names.iter() {
             let mut hash = Rusage::new();
             hash.insert(Resource::CpuTime, 1000);
-            exporter.process_rusage(&name, &hash);
+            exporter.process_rusage(&series(name), &hash);
         }
 
         // Now, create a seen array containing only a and c.
         let seen = SeenJails::from([
-            "test_a".into(),
-            "test_c".into(),
+            series("test_a").key,
+            series("test_c").key,
         ]);
 
         // Workout which jails are dead, it should be b.
         let dead = exporter.dead_jails(&seen);
         let ok = SeenJails::from([
-            "test_b".into(),
+            series("test_b").key,
         ]);
 
         assert_eq!(ok, dead);
@@ -678,27 +1137,95 @@ mod tests {
         for name in names.iter() {
             let mut hash = Rusage::new();
             hash.insert(Resource::CpuTime, 1000);
-            exporter.process_rusage(&name, &hash);
+            exporter.process_rusage(&series(name), &hash);
         }
 
         // Now, create a seen array containing only a and c.
         let seen = SeenJails::from([
-            "test_a".into(),
-            "test_c".into(),
+            series("test_a").key,
+            series("test_c").key,
         ]);
 
-        let dead_jail = "test_b";
-        let labels = &NameLabel {
-            name: dead_jail.to_string(),
-        };
+        let dead_jail = series("test_b");
+        let labels = &dead_jail.labels;
 
-        assert_eq!(exporter.cputime.get_or_create(labels).get(), 1000);
+        assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 1000);
 
         // Workout which jails are dead, it should be b.
         let dead = exporter.dead_jails(&seen);
         exporter.reap(dead);
 
-        assert_eq!(exporter.cputime.get_or_create(labels).get(), 0);
+        assert_eq!(exporter.counters[&Resource::CpuTime].get_or_create(labels).get(), 0);
+    }
+
+    #[test]
+    fn renamed_jail_reaped() {
+        let identity = JailIdentity::NameAndJid;
+        let exporter = ExporterBuilder::new()
+            .jail_identity(identity)
+            .build();
+
+        let mut hash = Rusage::new();
+        hash.insert(Resource::CpuTime, 1000);
+
+        // Jail 5 is called old, and is later renamed to new. The name is part
+        // of the series, so the rename is a new series.
+        let old = identity.series(5, "old");
+        let new = identity.series(5, "new");
+        assert_ne!(old.labels, new.labels);
+
+        exporter.process_rusage(&old, &hash);
+        exporter.jail_id.get_or_create(&old.id_labels).set(5);
+
+        // Next scrape only sees the renamed jail.
+        exporter.process_rusage(&new, &hash);
+        exporter.jail_id.get_or_create(&new.id_labels).set(5);
+
+        let seen = SeenJails::from([new.key.clone()]);
+        let dead = exporter.dead_jails(&seen);
+        exporter.reap(dead);
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &exporter.registry).unwrap();
+
+        assert!(!buffer.contains("name=\"old\""));
+        assert!(buffer.contains("jail_id{name=\"new\",jid=\"5\"} 5"));
+        assert_eq!(
+            exporter.counters[&Resource::CpuTime]
+                .get_or_create(&new.labels)
+                .get(),
+            1000,
+        );
+    }
+
+    #[test]
+    fn renamed_jail_reaped_jid() {
+        let identity = JailIdentity::Jid;
+        let exporter = ExporterBuilder::new()
+            .jail_identity(identity)
+            .build();
+
+        let mut hash = Rusage::new();
+        hash.insert(Resource::CpuTime, 1000);
+
+        // Jail 5 is called old, and is later renamed to new. Only the
+        // jail_id series carries the name, and is replaced as soon as the
+        // renamed jail is seen.
+        let old = identity.series(5, "old");
+        let new = identity.series(5, "new");
+        assert_eq!(old.key, new.key);
+
+        exporter.process_rusage(&old, &hash);
+        exporter.jail_id.get_or_create(&old.id_labels).set(5);
+
+        exporter.process_rusage(&new, &hash);
+        exporter.jail_id.get_or_create(&new.id_labels).set(5);
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &exporter.registry).unwrap();
+
+        assert!(!buffer.contains("name=\"old\""));
+        assert!(buffer.contains("jail_id{jid=\"5\",name=\"new\"} 5"));
     }
 
     #[test]
@@ -709,37 +1236,36 @@ mod tests {
         for name in names.iter() {
             let mut hash = Rusage::new();
 
-            let labels = &NameLabel {
-                name: name.to_string(),
-            };
+            let series = series(name);
+            let labels = &series.labels;
 
             // Initial check, should be zero. We didn't set anything yet.
-            assert_eq!(exporter.wallclock.get_or_create(labels).get(), 0);
+            assert_eq!(exporter.counters[&Resource::Wallclock].get_or_create(labels).get(), 0);
 
             // First run, adds 1000, total 1000.
             hash.insert(Resource::Wallclock, 1000);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.wallclock.get_or_create(labels).get(), 1000);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::Wallclock].get_or_create(labels).get(), 1000);
 
             // Second, adds 20, total 1020
             hash.insert(Resource::Wallclock, 1020);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.wallclock.get_or_create(labels).get(), 1020);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::Wallclock].get_or_create(labels).get(), 1020);
 
             // Third, counter was reset. Adds 10, total 1030.
             hash.insert(Resource::Wallclock, 10);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.wallclock.get_or_create(labels).get(), 10);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::Wallclock].get_or_create(labels).get(), 10);
 
             // Fourth, adds 40, total 1070.
             hash.insert(Resource::Wallclock, 50);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.wallclock.get_or_create(labels).get(), 50);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::Wallclock].get_or_create(labels).get(), 50);
 
             // Fifth, add 0, total 1070
             hash.insert(Resource::Wallclock, 50);
-            exporter.process_rusage(&name, &hash);
-            assert_eq!(exporter.wallclock.get_or_create(labels).get(), 50);
+            exporter.process_rusage(&series, &hash);
+            assert_eq!(exporter.counters[&Resource::Wallclock].get_or_create(labels).get(), 50);
         }
     }
 }
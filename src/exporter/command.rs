@@ -0,0 +1,27 @@
+// command: Runs the external commands used by some collectors.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use std::process::Command;
+use tracing::debug;
+
+/// Runs the program with the given arguments, returning its standard output.
+/// A non-zero exit status is an error, carrying the program's standard error.
+pub fn output(program: &str, args: &[&str]) -> Result<String, ExporterError> {
+    debug!("Running: {} {}", program, args.join(" "));
+
+    let output = Command::new(program)
+        .args(args)
+        .output()?;
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let err = format!("{program}: {}", err.trim());
+
+        return Err(ExporterError::CommandError(err));
+    }
+
+    let stdout = String::from_utf8(output.stdout)?;
+
+    Ok(stdout)
+}
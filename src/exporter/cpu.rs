@@ -0,0 +1,295 @@
+// cpu: CPU utilisation worked out from successive cputime samples.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use parking_lot::Mutex;
+use prometheus_client::metrics::gauge::Gauge;
+use std::collections::{
+    HashMap,
+    VecDeque,
+};
+use std::sync::atomic::AtomicU64;
+use std::time::{
+    Duration,
+    Instant,
+};
+use tracing::debug;
+use super::registry::{
+    Family,
+    Registry,
+};
+use super::JailLabels;
+
+/// The default smoothing factor, which applies no smoothing.
+pub const DEFAULT_CPU_SMOOTHING: f64 = 0.0;
+
+/// The default window that the utilisation is worked out over.
+pub const DEFAULT_CPU_WINDOW: Duration = Duration::from_secs(300);
+
+/// A cputime sample from a scrape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CpuSample {
+    // The cputime of the jail, in seconds.
+    cputime: usize,
+
+    // When the sample was taken.
+    at: Instant,
+}
+
+/// The cputime samples of a jail that are still within the window.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct CpuSamples {
+    // Samples, oldest first. The oldest is the newest sample taken at least
+    // a window before the latest one, if there is one.
+    samples: VecDeque<CpuSample>,
+
+    // The utilisation last reported, if any.
+    utilization: Option<f64>,
+}
+
+impl CpuSamples {
+    /// Adds the sample, dropping the samples it replaces as the start of
+    /// the window. A cputime that went backwards means the jail was
+    /// restarted, and the earlier samples are of its previous life.
+    fn push(&mut self, sample: CpuSample, window: Duration) {
+        let restarted = self.samples
+            .back()
+            .is_some_and(|previous| sample.cputime < previous.cputime);
+
+        if restarted {
+            self.samples.clear();
+            self.utilization = None;
+        }
+
+        self.samples.push_back(sample);
+
+        while self.samples
+            .get(1)
+            .is_some_and(|next| sample.at.saturating_duration_since(next.at) >= window)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Returns the CPU time used between the oldest and latest samples,
+    /// divided by the time between them. A jail keeping two CPU cores busy
+    /// has a utilisation of 2. There is no utilisation until the jail has
+    /// samples taken at different times.
+    fn utilization(&self) -> Option<f64> {
+        let first = self.samples.front()?;
+        let last = self.samples.back()?;
+
+        let elapsed = last.at.checked_duration_since(first.at)?.as_secs_f64();
+        let used = last.cputime.checked_sub(first.cputime)?;
+
+        if elapsed <= 0.0 {
+            return None;
+        }
+
+        Some(used as f64 / elapsed)
+    }
+}
+
+/// Returns the utilisation smoothed with an exponentially weighted moving
+/// average, giving the previous utilisation the weight of the smoothing
+/// factor.
+fn smooth(previous: Option<f64>, current: f64, smoothing: f64) -> f64 {
+    match previous {
+        Some(previous) => smoothing * previous + (1.0 - smoothing) * current,
+        None           => current,
+    }
+}
+
+/// CPU utilisation time series, worked out from the rctl cputime of each
+/// jail. The kernel counts cputime in whole seconds, so the utilisation is
+/// worked out over a window of several scrapes, the longer the window the
+/// finer the utilisation that can be told apart.
+pub struct CpuUtilization {
+    utilization: Family<JailLabels, Gauge<f64, AtomicU64>>,
+
+    // Samples of each jail, keyed on the jail's labels.
+    samples: Mutex<HashMap<JailLabels, CpuSamples>>,
+
+    // Weight given to the previous utilisation, between 0 and 1.
+    smoothing: f64,
+
+    // Time that the utilisation is worked out over.
+    window: Duration,
+}
+
+impl CpuUtilization {
+    /// Registers the CPU utilisation metric with the given registry.
+    pub fn new(registry: &mut Registry, smoothing: f64, window: Duration) -> Self {
+        let utilization = Family::<JailLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "cpu_utilization_ratio",
+            "CPU time used by the jail divided by the time elapsed, over the \
+             CPU utilisation window",
+            utilization.clone(),
+        );
+
+        Self {
+            utilization: utilization,
+            samples:     Mutex::new(HashMap::new()),
+            smoothing:   smoothing.clamp(0.0, 1.0),
+            window:      window,
+        }
+    }
+
+    /// Records the cputime of the jail with the given labels, taken at the
+    /// given time, setting its utilisation over the window. The first sample
+    /// of a jail, or of a restarted jail, only records its cputime.
+    pub fn observe(&self, labels: &JailLabels, cputime: usize, at: Instant) {
+        let mut samples = self.samples.lock();
+        let samples = samples.entry(labels.clone()).or_default();
+
+        let sample = CpuSample {
+            cputime: cputime,
+            at:      at,
+        };
+
+        samples.push(sample, self.window);
+
+        let utilization = samples.utilization().map(|current| {
+            smooth(samples.utilization, current, self.smoothing)
+        });
+
+        match utilization {
+            Some(utilization) => {
+                self.utilization.get_or_create(labels).set(utilization);
+            },
+            None => {
+                debug!("No CPU utilisation for jail: {:?}", labels);
+                self.utilization.remove(labels);
+            },
+        }
+
+        samples.utilization = utilization;
+    }
+
+    /// Removes the time series and samples of the jail with the given
+    /// labels.
+    pub fn remove(&self, labels: &JailLabels) {
+        self.samples.lock().remove(labels);
+        self.utilization.remove(labels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn labels() -> JailLabels {
+        let mut labels = JailLabels::default();
+        labels.push("name", "test");
+        labels
+    }
+
+    // Returns the utilisation recorded for the jail, checking that the time
+    // series agrees with it.
+    fn get(cpu: &CpuUtilization) -> Option<f64> {
+        let utilization = cpu.samples.lock().get(&labels())?.utilization?;
+        let gauge = cpu.utilization.get_or_create(&labels()).get();
+
+        assert_eq!(gauge, utilization);
+
+        Some(utilization)
+    }
+
+    #[test]
+    fn utilization_from_samples() {
+        let mut registry = Registry::default();
+        let window = Duration::from_secs(10);
+        let cpu = CpuUtilization::new(&mut registry, DEFAULT_CPU_SMOOTHING, window);
+        let start = Instant::now();
+
+        // The first sample has nothing to compare with.
+        cpu.observe(&labels(), 100, start);
+        assert_eq!(get(&cpu), None);
+
+        // 5 seconds of CPU over 10 seconds.
+        cpu.observe(&labels(), 105, start + Duration::from_secs(10));
+        assert_eq!(get(&cpu), Some(0.5));
+
+        // 20 seconds of CPU over 10 seconds, on more than one core.
+        cpu.observe(&labels(), 125, start + Duration::from_secs(20));
+        assert_eq!(get(&cpu), Some(2.0));
+    }
+
+    #[test]
+    fn utilization_over_window() {
+        let mut registry = Registry::default();
+        let window = Duration::from_secs(60);
+        let cpu = CpuUtilization::new(&mut registry, DEFAULT_CPU_SMOOTHING, window);
+        let start = Instant::now();
+
+        // A lightly loaded jail, using a second of CPU every 45 seconds,
+        // scraped every 15 seconds.
+        for (secs, cputime) in [(0, 100), (15, 100), (30, 101), (45, 101), (60, 101)] {
+            cpu.observe(&labels(), cputime, start + Duration::from_secs(secs));
+        }
+
+        assert_eq!(get(&cpu), Some(1.0 / 60.0));
+
+        // The window moves on from the first sample.
+        cpu.observe(&labels(), 102, start + Duration::from_secs(75));
+        assert_eq!(get(&cpu), Some(2.0 / 60.0));
+        assert_eq!(cpu.samples.lock()[&labels()].samples.len(), 5);
+    }
+
+    #[test]
+    fn utilization_smoothed() {
+        let mut registry = Registry::default();
+        let window = Duration::from_secs(10);
+        let cpu = CpuUtilization::new(&mut registry, 0.75, window);
+        let start = Instant::now();
+
+        cpu.observe(&labels(), 0, start);
+        cpu.observe(&labels(), 10, start + Duration::from_secs(10));
+        assert_eq!(get(&cpu), Some(1.0));
+
+        // Idle for the next 10 seconds.
+        cpu.observe(&labels(), 10, start + Duration::from_secs(20));
+        assert_eq!(get(&cpu), Some(0.75));
+    }
+
+    #[test]
+    fn utilization_jail_restarted() {
+        let mut registry = Registry::default();
+        let cpu = CpuUtilization::new(
+            &mut registry,
+            DEFAULT_CPU_SMOOTHING,
+            DEFAULT_CPU_WINDOW,
+        );
+        let start = Instant::now();
+
+        cpu.observe(&labels(), 100, start);
+        cpu.observe(&labels(), 110, start + Duration::from_secs(10));
+
+        // The cputime went backwards, so the series is removed until the
+        // next sample, and the samples before the restart aren't used.
+        cpu.observe(&labels(), 2, start + Duration::from_secs(20));
+        assert_eq!(get(&cpu), None);
+
+        cpu.observe(&labels(), 7, start + Duration::from_secs(30));
+        assert_eq!(get(&cpu), Some(0.5));
+    }
+
+    #[test]
+    fn utilization_removed() {
+        let mut registry = Registry::default();
+        let cpu = CpuUtilization::new(
+            &mut registry,
+            DEFAULT_CPU_SMOOTHING,
+            DEFAULT_CPU_WINDOW,
+        );
+        let start = Instant::now();
+
+        cpu.observe(&labels(), 100, start);
+        cpu.observe(&labels(), 110, start + Duration::from_secs(10));
+        cpu.remove(&labels());
+
+        assert_eq!(get(&cpu), None);
+        assert!(cpu.samples.lock().is_empty());
+    }
+}
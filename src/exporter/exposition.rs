@@ -0,0 +1,292 @@
+// exposition: Formats that the metrics can be exposed in.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+mod influx;
+mod json;
+mod usage;
+
+#[cfg(any(feature = "otlp", feature = "remote_write"))]
+mod families;
+
+#[cfg(feature = "protobuf")]
+mod protobuf;
+
+pub use influx::snapshot_to_influx;
+pub use json::snapshot_to_json;
+
+#[cfg(any(feature = "otlp", feature = "remote_write"))]
+pub use families::{
+    snapshot_to_families,
+    MetricFamily,
+    MetricSample,
+};
+
+#[cfg(feature = "protobuf")]
+pub use protobuf::snapshot_to_protobuf;
+
+#[cfg(feature = "graphite")]
+pub use usage::{
+    snapshot_to_usage,
+    ResourceUsage,
+};
+
+/// Content type of the OpenMetrics text format.
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Content type of the classic Prometheus text format.
+pub const TEXT_CONTENT_TYPE: &str =
+    "text/plain; version=0.0.4; charset=utf-8";
+
+/// Content type of the JSON encoding of each jail's metrics.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Content type of the InfluxDB line protocol.
+pub const INFLUX_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// Content type of the Prometheus delimited protobuf format.
+#[cfg(feature = "protobuf")]
+pub const PROTOBUF_CONTENT_TYPE: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+/// The format that metrics are exposed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The OpenMetrics 1.0.0 text format.
+    #[default]
+    OpenMetrics,
+
+    /// The classic Prometheus 0.0.4 text format.
+    Text,
+
+    /// A JSON encoding of each jail's metrics. This is not a Prometheus
+    /// format, so it is never negotiated on the telemetry path.
+    Json,
+
+    /// The InfluxDB line protocol encoding of each jail's rctl usage. Like
+    /// JSON, it is never negotiated on the telemetry path.
+    Influx,
+
+    /// The Prometheus delimited protobuf format.
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+impl Format {
+    /// Returns the HTTP content type of the format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+            Self::Text        => TEXT_CONTENT_TYPE,
+            Self::Json        => JSON_CONTENT_TYPE,
+            Self::Influx      => INFLUX_CONTENT_TYPE,
+
+            #[cfg(feature = "protobuf")]
+            Self::Protobuf    => PROTOBUF_CONTENT_TYPE,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = match self {
+            Self::OpenMetrics => "openmetrics",
+            Self::Text        => "text",
+            Self::Json        => "json",
+            Self::Influx      => "influx",
+
+            #[cfg(feature = "protobuf")]
+            Self::Protobuf    => "protobuf",
+        };
+
+        write!(f, "{format}")
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "openmetrics" => Ok(Self::OpenMetrics),
+            "text"        => Ok(Self::Text),
+            "json"        => Ok(Self::Json),
+            "influx"      => Ok(Self::Influx),
+
+            #[cfg(feature = "protobuf")]
+            "protobuf"    => Ok(Self::Protobuf),
+
+            _             => Err(format!("unknown format: {s}")),
+        }
+    }
+}
+
+/// Converts the OpenMetrics text produced by `prometheus_client` into the
+/// classic Prometheus text format.
+///
+/// The formats differ in their metadata rather than their samples:
+///
+///   - Counter families are named with their `_total` suffix.
+///   - Info families become gauges, named with their `_info` suffix.
+///   - Types that the classic format doesn't have become `untyped`.
+///   - There are no `# UNIT` or `# EOF` lines, nor exemplars.
+pub fn openmetrics_to_text(openmetrics: &str) -> String {
+    // The type of each family, needed when rewriting its HELP line, which
+    // comes before the TYPE line.
+    let types: HashMap<&str, &str> = openmetrics
+        .lines()
+        .filter_map(|line| line.strip_prefix("# TYPE "))
+        .filter_map(|line| line.split_once(' '))
+        .collect();
+
+    let mut text = String::with_capacity(openmetrics.len());
+
+    for line in openmetrics.lines() {
+        if line == "# EOF" || line.starts_with("# UNIT ") {
+            continue;
+        }
+
+        if let Some(help) = line.strip_prefix("# HELP ") {
+            let (name, help) = help.split_once(' ').unwrap_or((help, ""));
+            let metric_type = types.get(name).copied().unwrap_or("unknown");
+
+            text.push_str("# HELP ");
+            text.push_str(&text_name(name, metric_type));
+            text.push(' ');
+            text.push_str(help);
+        }
+        else if let Some(type_line) = line.strip_prefix("# TYPE ") {
+            let (name, metric_type) = type_line
+                .split_once(' ')
+                .unwrap_or((type_line, "unknown"));
+
+            text.push_str("# TYPE ");
+            text.push_str(&text_name(name, metric_type));
+            text.push(' ');
+            text.push_str(text_type(metric_type));
+        }
+        else {
+            // Exemplars follow the sample after a `#`, which can't otherwise
+            // appear outside of a quoted label value.
+            text.push_str(strip_exemplar(line));
+        }
+
+        text.push('\n');
+    }
+
+    text
+}
+
+// Returns the name of the family in the classic text format.
+fn text_name(name: &str, metric_type: &str) -> String {
+    match metric_type {
+        "counter" => format!("{name}_total"),
+        "info"    => format!("{name}_info"),
+        _         => name.to_string(),
+    }
+}
+
+// Returns the type of the family in the classic text format.
+fn text_type(metric_type: &str) -> &str {
+    match metric_type {
+        "counter" | "gauge" | "histogram" | "summary" => metric_type,
+        "info"                                        => "gauge",
+        _                                             => "untyped",
+    }
+}
+
+// Removes any exemplar from a sample line.
+fn strip_exemplar(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped   => escaped = false,
+            '\\'           => escaped = true,
+            '"'            => quoted = !quoted,
+            '#' if !quoted => return line[..i].trim_end(),
+            _              => {},
+        }
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn format_from_str() {
+        assert_eq!(Format::from_str("openmetrics"), Ok(Format::OpenMetrics));
+        assert_eq!(Format::from_str("text"), Ok(Format::Text));
+        assert_eq!(Format::from_str("json"), Ok(Format::Json));
+        assert_eq!(Format::from_str("influx"), Ok(Format::Influx));
+        assert!(Format::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn openmetrics_to_text_ok() {
+        let openmetrics = indoc!(r#"
+            # HELP jail_exporter_build A metric with constant '1' value.
+            # TYPE jail_exporter_build info
+            jail_exporter_build_info{version="0.17.0"} 1
+            # HELP jail_cputime_seconds CPU time, in seconds.
+            # TYPE jail_cputime_seconds counter
+            # UNIT jail_cputime_seconds seconds
+            jail_cputime_seconds_total{name="test"} 300
+            # HELP jail_memoryuse_bytes resident set size, in bytes.
+            # TYPE jail_memoryuse_bytes gauge
+            # UNIT jail_memoryuse_bytes bytes
+            jail_memoryuse_bytes{name="test # 1"} 1024
+            # EOF
+        "#);
+
+        let expected = indoc!(r#"
+            # HELP jail_exporter_build_info A metric with constant '1' value.
+            # TYPE jail_exporter_build_info gauge
+            jail_exporter_build_info{version="0.17.0"} 1
+            # HELP jail_cputime_seconds_total CPU time, in seconds.
+            # TYPE jail_cputime_seconds_total counter
+            jail_cputime_seconds_total{name="test"} 300
+            # HELP jail_memoryuse_bytes resident set size, in bytes.
+            # TYPE jail_memoryuse_bytes gauge
+            jail_memoryuse_bytes{name="test # 1"} 1024
+        "#);
+
+        assert_eq!(openmetrics_to_text(openmetrics), expected);
+    }
+
+    #[test]
+    fn openmetrics_to_text_unknown_type() {
+        let openmetrics = indoc!(r#"
+            # HELP states Some states.
+            # TYPE states stateset
+            states{states="a"} 1
+            # EOF
+        "#);
+
+        let text = openmetrics_to_text(openmetrics);
+
+        assert!(text.contains("# TYPE states untyped\n"));
+    }
+
+    #[test]
+    fn strip_exemplar_ok() {
+        assert_eq!(
+            strip_exemplar(r#"requests_total 1 # {trace_id="abc"} 1.0"#),
+            "requests_total 1",
+        );
+        assert_eq!(
+            strip_exemplar(r##"requests_total{path="/\"#"} 1"##),
+            r##"requests_total{path="/\"#"} 1"##,
+        );
+    }
+}
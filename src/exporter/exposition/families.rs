@@ -0,0 +1,215 @@
+// families: The metric families as data, for encodings built outside of the
+// exposition formats, such as those used when pushing metrics.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::exporter::registry::{
+    FamilySnapshot,
+    Series,
+    Value,
+};
+
+/// A metric family and its samples.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricFamily {
+    /// The family name, as in the OpenMetrics format.
+    pub name: String,
+
+    /// The description of the family.
+    pub help: String,
+
+    /// The OpenMetrics type of the family, such as `counter`.
+    pub metric_type: String,
+
+    /// The unit of the family, such as `bytes`, if it has one.
+    pub unit: Option<String>,
+
+    /// The samples of the family.
+    pub samples: Vec<MetricSample>,
+}
+
+/// A single sample of a metric family.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSample {
+    /// The sample name, which includes any suffix such as `_total` or
+    /// `_bucket`.
+    pub name: String,
+
+    /// The labels of the sample, in the order that they were encoded.
+    pub labels: Vec<(String, String)>,
+
+    /// The sample value.
+    pub value: f64,
+}
+
+/// Returns the metric families from the registered families, with a sample
+/// for each one that the OpenMetrics format has. Created timestamps are left
+/// out, since they describe their counter rather than being samples in their
+/// own right.
+pub fn snapshot_to_families(families: &[FamilySnapshot]) -> Vec<MetricFamily> {
+    families
+        .iter()
+        .map(|family| {
+            let samples = family.series
+                .iter()
+                .flat_map(|series| samples(&family.name, series))
+                .collect();
+
+            MetricFamily {
+                name:        family.name.clone(),
+                help:        family.help.clone(),
+                metric_type: family.metric_type.clone(),
+                unit:        family.unit.clone(),
+                samples:     samples,
+            }
+        })
+        .collect()
+}
+
+// Returns the samples of a time series, named as in the OpenMetrics format.
+fn samples(name: &str, series: &Series) -> Vec<MetricSample> {
+    let sample = |suffix: &str, labels: Vec<(String, String)>, value: f64| {
+        MetricSample {
+            name:   format!("{name}{suffix}"),
+            labels: labels,
+            value:  value,
+        }
+    };
+
+    let labels = series.labels.clone();
+
+    match &series.value {
+        Value::Counter(value) => vec![sample("_total", labels, value.as_f64())],
+        Value::Gauge(value)   => vec![sample("", labels, value.as_f64())],
+        Value::Info           => vec![sample("_info", labels, 1.0)],
+        Value::Histogram { sum, count, buckets } => {
+            let mut samples = vec![
+                sample("_sum", labels.clone(), *sum),
+                sample("_count", labels.clone(), *count as f64),
+            ];
+
+            for (upper_bound, cumulative_count) in buckets {
+                let mut labels = labels.clone();
+                labels.push(("le".into(), le(*upper_bound)));
+
+                samples.push(sample("_bucket", labels, *cumulative_count as f64));
+            }
+
+            samples
+        },
+    }
+}
+
+// Returns the `le` label value of a histogram bucket, as prometheus_client
+// formats it.
+fn le(upper_bound: f64) -> String {
+    if upper_bound == f64::INFINITY {
+        "+Inf".into()
+    }
+    else {
+        format!("{upper_bound:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::JailLabels;
+    use crate::exporter::registry::{
+        Family,
+        Histogram,
+        Info,
+        Registry,
+    };
+    use crate::exporter::testing;
+    use pretty_assertions::assert_eq;
+    use prometheus_client::metrics::{
+        counter::Counter,
+        gauge::Gauge,
+    };
+    use prometheus_client::registry::Unit;
+    use std::iter;
+
+    #[test]
+    fn snapshot_to_families_ok() {
+        let mut registry = Registry::with_prefix_and_labels("jail", iter::empty());
+        let cputime = Family::<JailLabels, Counter>::default();
+        let num = Gauge::<i64>::default();
+        let duration = Histogram::new(iter::once(0.5));
+        let version = testing::labels(&[("version", "0.17.0")]);
+
+        registry.register("exporter_build", "Build", Info::new(version));
+        registry.register_with_unit(
+            "cputime",
+            "CPU time, in seconds",
+            Unit::Seconds,
+            cputime.clone(),
+        );
+        registry.register("num", "Current number of jails", num.clone());
+        registry.register_with_unit(
+            "duration",
+            "Duration",
+            Unit::Seconds,
+            duration.clone(),
+        );
+
+        cputime.get_or_create(&testing::labels(&[("name", "web")])).inc_by(12);
+        num.set(1);
+        duration.observe(0.25);
+        duration.observe(2.0);
+
+        let families = snapshot_to_families(&registry.snapshot());
+
+        let sample = |name: &str, labels: &[(&str, &str)], value: f64| {
+            MetricSample {
+                name:   name.into(),
+                labels: labels
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                value:  value,
+            }
+        };
+
+        assert_eq!(families, vec![
+            MetricFamily {
+                name:        "jail_exporter_build".into(),
+                help:        "Build.".into(),
+                metric_type: "info".into(),
+                unit:        None,
+                samples:     vec![
+                    sample("jail_exporter_build_info", &[("version", "0.17.0")], 1.0),
+                ],
+            },
+            MetricFamily {
+                name:        "jail_cputime_seconds".into(),
+                help:        "CPU time, in seconds.".into(),
+                metric_type: "counter".into(),
+                unit:        Some("seconds".into()),
+                samples:     vec![
+                    sample("jail_cputime_seconds_total", &[("name", "web")], 12.0),
+                ],
+            },
+            MetricFamily {
+                name:        "jail_num".into(),
+                help:        "Current number of jails.".into(),
+                metric_type: "gauge".into(),
+                unit:        None,
+                samples:     vec![
+                    sample("jail_num", &[], 1.0),
+                ],
+            },
+            MetricFamily {
+                name:        "jail_duration_seconds".into(),
+                help:        "Duration.".into(),
+                metric_type: "histogram".into(),
+                unit:        Some("seconds".into()),
+                samples:     vec![
+                    sample("jail_duration_seconds_sum", &[], 2.25),
+                    sample("jail_duration_seconds_count", &[], 2.0),
+                    sample("jail_duration_seconds_bucket", &[("le", "0.5")], 1.0),
+                    sample("jail_duration_seconds_bucket", &[("le", "+Inf")], 2.0),
+                ],
+            },
+        ]);
+    }
+}
@@ -0,0 +1,140 @@
+// influx: The InfluxDB line protocol encoding of each jail's rctl usage.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::exporter::registry::FamilySnapshot;
+use std::time::{
+    SystemTime,
+    UNIX_EPOCH,
+};
+use super::usage::snapshot_to_usage;
+
+// Measurement that the usage of every jail is written to.
+const INFLUX_MEASUREMENT: &str = "jail";
+
+// Escapes a tag key, tag value or field key. Commas, equals signs and spaces
+// would otherwise end the key or value.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Encodes the registered families as the InfluxDB line protocol, with one
+/// line per jail in the `jail` measurement.
+/// The jail's labels, such as `name`, become tags, and its usage of each
+/// rctl resource becomes an integer field named after the resource, such as
+/// `cputime`. Every line has the given timestamp, in nanoseconds.
+///
+/// Metrics that aren't rctl usage, such as the process and host metrics, are
+/// left out.
+pub fn snapshot_to_influx(
+    families: &[FamilySnapshot],
+    timestamp: SystemTime,
+) -> Vec<u8> {
+    let timestamp = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let mut influx = String::new();
+
+    // Jails are ordered by their sorted tags, which InfluxDB handles best.
+    for jail in snapshot_to_usage(families) {
+        influx.push_str(INFLUX_MEASUREMENT);
+
+        for (name, value) in &jail.labels {
+            influx.push(',');
+            influx.push_str(&escape(name));
+            influx.push('=');
+            influx.push_str(&escape(value));
+        }
+
+        let fields: Vec<String> = jail.usage
+            .iter()
+            .map(|(resource, value)| {
+                format!("{}={value}i", escape(resource.as_str()))
+            })
+            .collect();
+
+        influx.push(' ');
+        influx.push_str(&fields.join(","));
+        influx.push(' ');
+        influx.push_str(&timestamp.to_string());
+        influx.push('\n');
+    }
+
+    influx.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::JailLabels;
+    use crate::exporter::registry::{
+        Family,
+        Registry,
+    };
+    use crate::exporter::testing;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use prometheus_client::metrics::gauge::Gauge;
+    use std::iter;
+    use std::time::Duration;
+
+    fn timestamp() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn snapshot_to_influx_golden() {
+        let registry = testing::golden_registry();
+
+        let influx = snapshot_to_influx(&registry.snapshot(), timestamp());
+
+        let expected = indoc!(r#"
+            jail,depth=0,env=prod,name=db\ "primary" cputime=86400i,memoryuse=1073741824i 1700000000000000000
+            jail,depth=1,env=prod,name=web,parent=host cputime=3600i,memoryuse=268435456i 1700000000000000000
+        "#);
+
+        assert_eq!(String::from_utf8(influx).unwrap(), expected);
+    }
+
+    #[test]
+    fn snapshot_to_influx_escaped() {
+        let mut registry = Registry::with_prefix_and_labels("jail", iter::empty());
+        let maxproc = Family::<JailLabels, Gauge>::default();
+
+        registry.register("maxproc", "number of processes", maxproc.clone());
+
+        maxproc
+            .get_or_create(&testing::labels(&[("name", "a,b=c d"), ("meta", "")]))
+            .set(10);
+
+        let influx = snapshot_to_influx(&registry.snapshot(), timestamp());
+
+        assert_eq!(
+            String::from_utf8(influx).unwrap(),
+            "jail,name=a\\,b\\=c\\ d maxproc=10i 1700000000000000000\n",
+        );
+    }
+
+    #[test]
+    fn snapshot_to_influx_no_jails() {
+        let mut registry = Registry::with_prefix_and_labels("jail", iter::empty());
+        let num = Gauge::<i64>::default();
+
+        registry.register("num", "Current number of running jails", num);
+
+        let influx = snapshot_to_influx(&registry.snapshot(), timestamp());
+
+        assert!(influx.is_empty());
+    }
+}
@@ -0,0 +1,298 @@
+// json: A JSON encoding of the metrics of each jail.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::exporter::{
+    is_reserved_label,
+    JailIdentity,
+};
+use crate::exporter::registry::{
+    FamilySnapshot,
+    Value,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Version of the JSON schema. This is increased whenever a change could
+/// break existing consumers, such as removing or renaming a field.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+// Labels that identify or describe the jail itself, rather than one of its
+// time series, despite being reserved by the exporter.
+const JAIL_LABELS: &[&str] = &[
+    "depth",
+    "jid",
+    "name",
+    "parent",
+];
+
+/// The metrics of every jail, as served at `/api/v1/jails`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct JailsDocument {
+    /// The version of the schema, see [`JSON_SCHEMA_VERSION`].
+    pub version: u32,
+
+    /// The jails, ordered by the labels of the jail identity.
+    pub jails: Vec<JailDocument>,
+}
+
+/// The metrics of a single jail.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct JailDocument {
+    /// The jail name. When jails aren't identified by name, it is taken
+    /// from the jail's `jail_id` time series.
+    pub name: Option<String>,
+
+    /// The jail ID, if the jail identity includes it.
+    pub jid: Option<i32>,
+
+    /// Labels describing the jail, such as its parent and depth, static
+    /// labels from the configuration file and labels from its `meta`
+    /// parameter.
+    pub labels: BTreeMap<String, String>,
+
+    /// The jail's metrics, in the order that the exporter registers them.
+    pub metrics: Vec<MetricDocument>,
+}
+
+/// A single time series of a jail.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MetricDocument {
+    /// The metric family name, as in the OpenMetrics format.
+    pub name: String,
+
+    /// The description of the metric family.
+    pub help: String,
+
+    /// The OpenMetrics type of the metric family, such as `counter`.
+    #[serde(rename = "type")]
+    pub metric_type: String,
+
+    /// The unit of the metric family, such as `bytes`, if it has one.
+    pub unit: Option<String>,
+
+    /// Labels telling apart the time series of the family for the jail,
+    /// such as a process `state`.
+    pub labels: BTreeMap<String, String>,
+
+    /// The value. Values that JSON can't represent, such as NaN, are null.
+    pub value: f64,
+}
+
+// The position of a time series in a jail's metrics, ordered by the family
+// and then the labels of the series, so that the document doesn't depend on
+// the order of the time series within a family.
+type MetricKey = (usize, BTreeMap<String, String>);
+
+// A jail being built from the registered families.
+#[derive(Default)]
+struct JailBuilder {
+    name:    Option<String>,
+    labels:  BTreeMap<String, String>,
+    metrics: BTreeMap<MetricKey, MetricDocument>,
+}
+
+/// Builds the document holding the metrics of each jail from the registered
+/// families. Jails are keyed on the labels of the jail identity, and time
+/// series without them, such as those describing the exporter or the host,
+/// don't belong to a jail and are left out.
+pub fn snapshot_to_document(
+    families: &[FamilySnapshot],
+    identity: JailIdentity,
+) -> JailsDocument {
+    let key_labels = identity.key_labels();
+    let by_name = key_labels.contains(&"name");
+    let by_jid = key_labels.contains(&"jid");
+
+    let mut jails: BTreeMap<(Option<String>, Option<i32>), JailBuilder> =
+        BTreeMap::new();
+
+    for (family_index, family) in families.iter().enumerate() {
+        for series in &family.series {
+            // Histograms have no single value to give.
+            let value = match series.value {
+                Value::Counter(value) | Value::Gauge(value) => value.as_f64(),
+                Value::Info                                 => 1.0,
+                Value::Histogram { .. }                     => continue,
+            };
+
+            let mut name = None;
+            let mut jid = None;
+            let mut jail_labels = BTreeMap::new();
+            let mut series_labels = BTreeMap::new();
+
+            for (label, value) in &series.labels {
+                match label.as_str() {
+                    "name" => name = Some(value.clone()),
+                    "jid"  => jid = value.parse::<i32>().ok(),
+                    _ if JAIL_LABELS.contains(&label.as_str()) ||
+                         !is_reserved_label(label) => {
+                        jail_labels.insert(label.clone(), value.clone());
+                    },
+                    _ => {
+                        series_labels.insert(label.clone(), value.clone());
+                    },
+                }
+            }
+
+            if (by_name && name.is_none()) || (by_jid && jid.is_none()) {
+                continue;
+            }
+
+            // Only the identity labels key the jail. A name outside of the
+            // identity, as the jail_id series carries, describes the jail.
+            let key = (
+                name.clone().filter(|_| by_name),
+                jid.filter(|_| by_jid),
+            );
+
+            let jail = jails.entry(key).or_default();
+            jail.name = jail.name.take().or(name);
+            jail.labels.extend(jail_labels);
+
+            let metric = MetricDocument {
+                name:        family.name.clone(),
+                help:        family.help.clone(),
+                metric_type: family.metric_type.clone(),
+                unit:        family.unit.clone(),
+                labels:      series_labels.clone(),
+                value:       value,
+            };
+
+            jail.metrics.insert((family_index, series_labels), metric);
+        }
+    }
+
+    let jails = jails
+        .into_iter()
+        .map(|((_, jid), jail)| {
+            JailDocument {
+                name:    jail.name,
+                jid:     jid,
+                labels:  jail.labels,
+                metrics: jail.metrics.into_values().collect(),
+            }
+        })
+        .collect();
+
+    JailsDocument {
+        version: JSON_SCHEMA_VERSION,
+        jails:   jails,
+    }
+}
+
+/// Encodes the registered families as the JSON encoding of the metrics of
+/// each jail.
+pub fn snapshot_to_json(families: &[FamilySnapshot], identity: JailIdentity) -> Vec<u8> {
+    let document = snapshot_to_document(families, identity);
+
+    let mut json = serde_json::to_vec_pretty(&document)
+        .expect("document only holds strings, numbers and maps");
+    json.push(b'\n');
+
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing;
+    use crate::exporter::registry::{
+        Number,
+        Series,
+    };
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    fn gauge(name: &str, series: &[(&[(&str, &str)], f64)]) -> FamilySnapshot {
+        let series = series
+            .iter()
+            .map(|(labels, value)| {
+                Series {
+                    labels: labels
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect(),
+                    value:  Value::Gauge(Number::Float(*value)),
+                }
+            })
+            .collect();
+
+        FamilySnapshot {
+            name:        name.into(),
+            help:        format!("{name}."),
+            metric_type: "gauge".into(),
+            unit:        None,
+            series:      series,
+        }
+    }
+
+    // The golden file pins the JSON schema, so any change to it must come
+    // with a change to JSON_SCHEMA_VERSION if it could break consumers.
+    #[test]
+    fn snapshot_to_json_golden() {
+        let registry = testing::golden_registry();
+
+        let expected = fs::read_to_string("test-data/exposition_golden.json")
+            .unwrap();
+
+        let json = snapshot_to_json(&registry.snapshot(), JailIdentity::Name);
+
+        assert_eq!(String::from_utf8(json).unwrap(), expected);
+    }
+
+    #[test]
+    fn snapshot_to_document_jid_identity() {
+        let families = [
+            gauge("jail_maxproc", &[
+                (&[("jid", "2")], 10.0),
+                (&[("jid", "1")], 20.0),
+            ]),
+            // The jail_id series also carries the name of the jail.
+            gauge("jail_id", &[
+                (&[("jid", "1"), ("name", "web")], 1.0),
+                (&[("jid", "2"), ("name", "db")], 2.0),
+            ]),
+        ];
+
+        let document = snapshot_to_document(&families, JailIdentity::Jid);
+        let jails: Vec<(Option<i32>, Option<&str>, usize)> = document.jails
+            .iter()
+            .map(|jail| (jail.jid, jail.name.as_deref(), jail.metrics.len()))
+            .collect();
+
+        assert_eq!(jails, vec![
+            (Some(1), Some("web"), 2),
+            (Some(2), Some("db"), 2),
+        ]);
+        assert_eq!(document.jails[0].metrics[0].value, 20.0);
+        assert_eq!(document.jails[0].metrics[1].name, "jail_id");
+        assert_eq!(document.jails[0].labels, BTreeMap::new());
+    }
+
+    #[test]
+    fn snapshot_to_document_name_identity() {
+        let families = [
+            gauge("jail_maxproc", &[(&[("name", "web")], 10.0)]),
+            gauge("jail_id", &[(&[("name", "web")], 1.0)]),
+        ];
+
+        let document = snapshot_to_document(&families, JailIdentity::Name);
+
+        assert_eq!(document.jails.len(), 1);
+        assert_eq!(document.jails[0].name.as_deref(), Some("web"));
+        assert_eq!(document.jails[0].jid, None);
+        assert_eq!(document.jails[0].metrics.len(), 2);
+    }
+
+    #[test]
+    fn snapshot_to_document_no_jails() {
+        let families = [gauge("jail_num", &[(&[], 0.0)])];
+
+        let document = snapshot_to_document(&families, JailIdentity::Name);
+
+        assert_eq!(document, JailsDocument {
+            version: JSON_SCHEMA_VERSION,
+            jails:   vec![],
+        });
+    }
+}
@@ -0,0 +1,548 @@
+// protobuf: The Prometheus delimited protobuf exposition format.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::exporter::registry::{
+    FamilySnapshot,
+    Series,
+    Value,
+};
+use prost::Message;
+use super::text_name;
+
+/// A label name and value.
+#[derive(Clone, PartialEq, Message)]
+pub struct LabelPair {
+    /// The label name.
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+
+    /// The label value.
+    #[prost(string, optional, tag = "2")]
+    pub value: Option<String>,
+}
+
+/// The type of a metric family. Only the types that we export are present.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum MetricType {
+    /// A counter.
+    Counter = 0,
+
+    /// A gauge.
+    Gauge = 1,
+
+    /// A metric of unknown type.
+    Untyped = 3,
+
+    /// A histogram.
+    Histogram = 4,
+}
+
+/// The value of a gauge.
+#[derive(Clone, PartialEq, Message)]
+pub struct Gauge {
+    /// The gauge value.
+    #[prost(double, optional, tag = "1")]
+    pub value: Option<f64>,
+}
+
+/// The value of a counter.
+#[derive(Clone, PartialEq, Message)]
+pub struct Counter {
+    /// The counter value.
+    #[prost(double, optional, tag = "1")]
+    pub value: Option<f64>,
+}
+
+/// The value of an untyped metric.
+#[derive(Clone, PartialEq, Message)]
+pub struct Untyped {
+    /// The metric value.
+    #[prost(double, optional, tag = "1")]
+    pub value: Option<f64>,
+}
+
+/// A histogram bucket.
+#[derive(Clone, PartialEq, Message)]
+pub struct Bucket {
+    /// The number of observations less than or equal to the upper bound.
+    #[prost(uint64, optional, tag = "1")]
+    pub cumulative_count: Option<u64>,
+
+    /// The upper bound of the bucket.
+    #[prost(double, optional, tag = "2")]
+    pub upper_bound: Option<f64>,
+}
+
+/// The value of a histogram.
+#[derive(Clone, PartialEq, Message)]
+pub struct Histogram {
+    /// The number of observations.
+    #[prost(uint64, optional, tag = "1")]
+    pub sample_count: Option<u64>,
+
+    /// The sum of the observations.
+    #[prost(double, optional, tag = "2")]
+    pub sample_sum: Option<f64>,
+
+    /// The buckets, ordered by upper bound, without the implicit `+Inf`.
+    #[prost(message, repeated, tag = "3")]
+    pub bucket: Vec<Bucket>,
+}
+
+/// A single time series.
+#[derive(Clone, PartialEq, Message)]
+pub struct Metric {
+    /// The labels of the time series.
+    #[prost(message, repeated, tag = "1")]
+    pub label: Vec<LabelPair>,
+
+    /// The value, if a gauge.
+    #[prost(message, optional, tag = "2")]
+    pub gauge: Option<Gauge>,
+
+    /// The value, if a counter.
+    #[prost(message, optional, tag = "3")]
+    pub counter: Option<Counter>,
+
+    /// The value, if untyped.
+    #[prost(message, optional, tag = "5")]
+    pub untyped: Option<Untyped>,
+
+    /// The value, if a histogram.
+    #[prost(message, optional, tag = "7")]
+    pub histogram: Option<Histogram>,
+}
+
+/// A metric family, as defined by `io.prometheus.client.MetricFamily`.
+#[derive(Clone, PartialEq, Message)]
+pub struct MetricFamily {
+    /// The family name.
+    #[prost(string, optional, tag = "1")]
+    pub name: Option<String>,
+
+    /// The family help.
+    #[prost(string, optional, tag = "2")]
+    pub help: Option<String>,
+
+    /// The family type.
+    #[prost(enumeration = "MetricType", optional, tag = "3")]
+    pub r#type: Option<i32>,
+
+    /// The time series of the family.
+    #[prost(message, repeated, tag = "4")]
+    pub metric: Vec<Metric>,
+}
+
+// Returns the label pairs of a time series.
+fn label_pairs(labels: &[(String, String)]) -> Vec<LabelPair> {
+    labels
+        .iter()
+        .map(|(name, value)| {
+            LabelPair {
+                name:  Some(name.clone()),
+                value: Some(value.clone()),
+            }
+        })
+        .collect()
+}
+
+// Returns the metric holding the value of a time series.
+fn metric(series: &Series) -> Metric {
+    let label = label_pairs(&series.labels);
+
+    match &series.value {
+        Value::Counter(value) => {
+            Metric {
+                label:   label,
+                counter: Some(Counter { value: Some(value.as_f64()) }),
+                ..Default::default()
+            }
+        },
+        Value::Gauge(value) => {
+            Metric {
+                label: label,
+                gauge: Some(Gauge { value: Some(value.as_f64()) }),
+                ..Default::default()
+            }
+        },
+        // Info metrics are gauges with a value of 1, as in the classic text
+        // format.
+        Value::Info => {
+            Metric {
+                label: label,
+                gauge: Some(Gauge { value: Some(1.0) }),
+                ..Default::default()
+            }
+        },
+        Value::Histogram { sum, count, buckets } => {
+            // The +Inf bucket is implied by the sample count.
+            let bucket = buckets
+                .iter()
+                .filter(|(upper_bound, _)| upper_bound.is_finite())
+                .map(|(upper_bound, cumulative_count)| {
+                    Bucket {
+                        cumulative_count: Some(*cumulative_count),
+                        upper_bound:      Some(*upper_bound),
+                    }
+                })
+                .collect();
+
+            Metric {
+                label:     label,
+                histogram: Some(Histogram {
+                    sample_count: Some(*count),
+                    sample_sum:   Some(*sum),
+                    bucket:       bucket,
+                }),
+                ..Default::default()
+            }
+        },
+    }
+}
+
+// Returns the metric family of a registered family, named as it would be in
+// the classic text format.
+fn metric_family(family: &FamilySnapshot) -> MetricFamily {
+    let family_type = match family.metric_type.as_str() {
+        "counter"        => MetricType::Counter,
+        "gauge" | "info" => MetricType::Gauge,
+        "histogram"      => MetricType::Histogram,
+        _                => MetricType::Untyped,
+    };
+
+    MetricFamily {
+        name:   Some(text_name(&family.name, &family.metric_type)),
+        help:   Some(family.help.clone()),
+        r#type: Some(family_type as i32),
+        metric: family.series.iter().map(metric).collect(),
+    }
+}
+
+/// Encodes the registered families in the Prometheus delimited protobuf
+/// format, where each metric family is prefixed with its length as a
+/// varint.
+pub fn snapshot_to_protobuf(families: &[FamilySnapshot]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+
+    for family in families {
+        metric_family(family)
+            .encode_length_delimited(&mut buffer)
+            .expect("Vec has enough capacity");
+    }
+
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::JailLabels;
+    use crate::exporter::registry::{
+        Family,
+        Histogram,
+        Info,
+        Number,
+        Registry,
+    };
+    use pretty_assertions::assert_eq;
+    use prometheus_client::metrics::{
+        counter::Counter,
+        gauge::Gauge,
+    };
+    use prometheus_client::registry::Unit;
+    use std::borrow::Cow;
+    use std::iter;
+
+    // A field of a protobuf message, decoded from the wire format alone so
+    // that the encoding is checked independently of the prost structs.
+    #[derive(Clone, Debug, PartialEq)]
+    enum Field {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(Vec<u8>),
+    }
+
+    // The field numbers and enum values of the upstream
+    // io.prometheus.client definitions in metrics.proto.
+    const FAMILY_NAME: u64 = 1;
+    const FAMILY_HELP: u64 = 2;
+    const FAMILY_TYPE: u64 = 3;
+    const FAMILY_METRIC: u64 = 4;
+    const METRIC_LABEL: u64 = 1;
+    const METRIC_GAUGE: u64 = 2;
+    const METRIC_COUNTER: u64 = 3;
+    const METRIC_HISTOGRAM: u64 = 7;
+    const LABEL_NAME: u64 = 1;
+    const LABEL_VALUE: u64 = 2;
+    const VALUE: u64 = 1;
+    const HISTOGRAM_SAMPLE_COUNT: u64 = 1;
+    const HISTOGRAM_SAMPLE_SUM: u64 = 2;
+    const HISTOGRAM_BUCKET: u64 = 3;
+    const BUCKET_CUMULATIVE_COUNT: u64 = 1;
+    const BUCKET_UPPER_BOUND: u64 = 2;
+    const TYPE_COUNTER: u64 = 0;
+    const TYPE_GAUGE: u64 = 1;
+    const TYPE_HISTOGRAM: u64 = 4;
+
+    // A decoded message, as its field numbers and values.
+    type Fields = Vec<(u64, Field)>;
+
+    fn varint(buffer: &mut &[u8]) -> u64 {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = buffer.split_first().expect("truncated varint");
+            *buffer = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+
+        panic!("varint too long");
+    }
+
+    fn take<'a>(buffer: &mut &'a [u8], len: usize) -> &'a [u8] {
+        let (taken, rest) = buffer.split_at(len);
+        *buffer = rest;
+
+        taken
+    }
+
+    fn decode_fields(mut buffer: &[u8]) -> Fields {
+        let mut fields = Vec::new();
+
+        while !buffer.is_empty() {
+            let key = varint(&mut buffer);
+
+            let field = match key & 0x07 {
+                0 => Field::Varint(varint(&mut buffer)),
+                1 => {
+                    let bytes = take(&mut buffer, 8).try_into().unwrap();
+
+                    Field::Fixed64(u64::from_le_bytes(bytes))
+                },
+                2 => {
+                    let len = varint(&mut buffer) as usize;
+
+                    Field::Bytes(take(&mut buffer, len).to_vec())
+                },
+                wire_type => panic!("unexpected wire type {wire_type}"),
+            };
+
+            fields.push((key >> 3, field));
+        }
+
+        fields
+    }
+
+    // Splits a delimited buffer into its messages.
+    fn decode_delimited(mut buffer: &[u8]) -> Vec<Fields> {
+        let mut messages = Vec::new();
+
+        while !buffer.is_empty() {
+            let len = varint(&mut buffer) as usize;
+
+            messages.push(decode_fields(take(&mut buffer, len)));
+        }
+
+        messages
+    }
+
+    fn all(fields: &Fields, number: u64) -> Vec<Field> {
+        fields
+            .iter()
+            .filter(|(n, _)| *n == number)
+            .map(|(_, field)| field.clone())
+            .collect()
+    }
+
+    fn one(fields: &Fields, number: u64) -> Field {
+        let fields = all(fields, number);
+        assert_eq!(fields.len(), 1, "field {number}");
+
+        fields[0].clone()
+    }
+
+    fn string(fields: &Fields, number: u64) -> String {
+        match one(fields, number) {
+            Field::Bytes(bytes) => String::from_utf8(bytes).unwrap(),
+            field               => panic!("field {number} is {field:?}"),
+        }
+    }
+
+    fn double(fields: &Fields, number: u64) -> f64 {
+        match one(fields, number) {
+            Field::Fixed64(bits) => f64::from_bits(bits),
+            field                => panic!("field {number} is {field:?}"),
+        }
+    }
+
+    fn uint(fields: &Fields, number: u64) -> u64 {
+        match one(fields, number) {
+            Field::Varint(value) => value,
+            field                => panic!("field {number} is {field:?}"),
+        }
+    }
+
+    fn messages(fields: &Fields, number: u64) -> Vec<Fields> {
+        all(fields, number)
+            .into_iter()
+            .map(|field| {
+                match field {
+                    Field::Bytes(bytes) => decode_fields(&bytes),
+                    field               => panic!("field {number} is {field:?}"),
+                }
+            })
+            .collect()
+    }
+
+    fn labels(metric: &Fields) -> Vec<(String, String)> {
+        messages(metric, METRIC_LABEL)
+            .iter()
+            .map(|label| (string(label, LABEL_NAME), string(label, LABEL_VALUE)))
+            .collect()
+    }
+
+    fn jail(name: &str) -> JailLabels {
+        let mut labels = JailLabels::default();
+        labels.push("name", name);
+
+        labels
+    }
+
+    #[test]
+    fn snapshot_to_protobuf_decoded() {
+        let static_labels = iter::once((Cow::Borrowed("dc"), Cow::Borrowed("ams1")));
+        let mut registry = Registry::with_prefix_and_labels("jail", static_labels);
+
+        let mut version = JailLabels::default();
+        version.push("version", "0.17.0");
+
+        let cputime = Family::<JailLabels, Counter>::default();
+        let memoryuse = Family::<JailLabels, Gauge>::default();
+        let duration = Histogram::new([0.1, 1.0].into_iter());
+
+        registry.register("exporter_build", "Build", Info::new(version));
+        registry.register_with_unit("cputime", "CPU time", Unit::Seconds, cputime.clone());
+        registry.register_with_unit("memoryuse", "RSS", Unit::Bytes, memoryuse.clone());
+        registry
+            .sub_registry_with_prefix("exporter")
+            .register_with_unit("duration", "Duration", Unit::Seconds, duration.clone());
+
+        cputime.get_or_create(&jail(r#"a "b"\c"#)).inc_by(300);
+        memoryuse.get_or_create(&jail("other")).set(2048);
+        memoryuse.get_or_create(&jail("test")).set(1024);
+        duration.observe(0.0625);
+        duration.observe(0.5);
+        duration.observe(0.75);
+
+        let families = decode_delimited(&snapshot_to_protobuf(&registry.snapshot()));
+
+        let names: Vec<String> = families
+            .iter()
+            .map(|family| string(family, FAMILY_NAME))
+            .collect();
+
+        assert_eq!(names, vec![
+            "jail_exporter_build_info",
+            "jail_cputime_seconds_total",
+            "jail_memoryuse_bytes",
+            "jail_exporter_duration_seconds",
+        ]);
+
+        // Info metrics are gauges with a value of 1.
+        let build = &families[0];
+        assert_eq!(string(build, FAMILY_HELP), "Build.");
+        assert_eq!(uint(build, FAMILY_TYPE), TYPE_GAUGE);
+
+        let metrics = messages(build, FAMILY_METRIC);
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(labels(&metrics[0]), vec![
+            ("dc".into(), "ams1".into()),
+            ("version".into(), "0.17.0".into()),
+        ]);
+        assert_eq!(double(&messages(&metrics[0], METRIC_GAUGE)[0], VALUE), 1.0);
+
+        let cputime = &families[1];
+        assert_eq!(uint(cputime, FAMILY_TYPE), TYPE_COUNTER);
+
+        let metrics = messages(cputime, FAMILY_METRIC);
+        assert_eq!(labels(&metrics[0]), vec![
+            ("dc".into(), "ams1".into()),
+            ("name".into(), r#"a "b"\c"#.into()),
+        ]);
+        assert_eq!(double(&messages(&metrics[0], METRIC_COUNTER)[0], VALUE), 300.0);
+
+        let memoryuse = &families[2];
+        assert_eq!(uint(memoryuse, FAMILY_TYPE), TYPE_GAUGE);
+
+        let mut values: Vec<(Vec<(String, String)>, f64)> = messages(memoryuse, FAMILY_METRIC)
+            .iter()
+            .map(|metric| {
+                (labels(metric), double(&messages(metric, METRIC_GAUGE)[0], VALUE))
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(values, vec![
+            (vec![("dc".into(), "ams1".into()), ("name".into(), "other".into())], 2048.0),
+            (vec![("dc".into(), "ams1".into()), ("name".into(), "test".into())], 1024.0),
+        ]);
+
+        // The +Inf bucket is left out, being implied by the sample count.
+        let duration = &families[3];
+        assert_eq!(uint(duration, FAMILY_TYPE), TYPE_HISTOGRAM);
+
+        let metrics = messages(duration, FAMILY_METRIC);
+        let histogram = &messages(&metrics[0], METRIC_HISTOGRAM)[0];
+        assert_eq!(uint(histogram, HISTOGRAM_SAMPLE_COUNT), 3);
+        assert_eq!(double(histogram, HISTOGRAM_SAMPLE_SUM), 1.3125);
+
+        let buckets: Vec<(u64, f64)> = messages(histogram, HISTOGRAM_BUCKET)
+            .iter()
+            .map(|bucket| {
+                (
+                    uint(bucket, BUCKET_CUMULATIVE_COUNT),
+                    double(bucket, BUCKET_UPPER_BOUND),
+                )
+            })
+            .collect();
+
+        assert_eq!(buckets, vec![(1, 0.1), (3, 1.0)]);
+    }
+
+    // Checks the wire encoding against bytes worked out by hand from the
+    // io.prometheus.client protobuf definitions.
+    #[test]
+    fn snapshot_to_protobuf_wire_format() {
+        let family = FamilySnapshot {
+            name:        "a".into(),
+            help:        String::new(),
+            metric_type: "gauge".into(),
+            unit:        None,
+            series:      vec![Series {
+                labels: vec![],
+                value:  Value::Gauge(Number::Int(1)),
+            }],
+        };
+
+        let expected: &[u8] = &[
+            // Length of the family.
+            0x14,
+            // name = "a"
+            0x0a, 0x01, b'a',
+            // help = ""
+            0x12, 0x00,
+            // type = GAUGE
+            0x18, 0x01,
+            // metric, containing gauge, containing value = 1.0
+            0x22, 0x0b, 0x12, 0x09, 0x09,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f,
+        ];
+
+        assert_eq!(snapshot_to_protobuf(&[family]), expected);
+    }
+}
@@ -0,0 +1,159 @@
+// usage: The rctl usage of each jail, for encodings that only carry the
+// resources rather than every metric.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::collectors::RESOURCES;
+use crate::exporter::registry::{
+    FamilySnapshot,
+    Number,
+    Value,
+};
+use rctl::Resource;
+use std::collections::{
+    BTreeMap,
+    HashMap,
+};
+use super::super::resources::family_name;
+
+/// The labels of a jail, as name and value pairs.
+type Labels = Vec<(String, String)>;
+
+/// The rctl usage of a single jail.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResourceUsage {
+    /// The labels of the jail, such as `name`, sorted by name. Labels with
+    /// empty values are left out.
+    pub labels: Labels,
+
+    /// The usage of each resource, in the order that the resources are
+    /// registered.
+    pub usage: Vec<(Resource, i64)>,
+}
+
+#[cfg(feature = "graphite")]
+impl ResourceUsage {
+    /// Returns the value of the given label, if the jail has it.
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Returns the rctl usage of each jail from the registered families, ordered
+/// by the jail's labels. Metrics that aren't rctl usage, such as the process
+/// and host metrics, are left out.
+pub fn snapshot_to_usage(families: &[FamilySnapshot]) -> Vec<ResourceUsage> {
+    let resources: HashMap<String, Resource> = RESOURCES
+        .iter()
+        .map(|resource| (family_name(resource), *resource))
+        .collect();
+
+    let mut jails: BTreeMap<Labels, Vec<(Resource, i64)>> = BTreeMap::new();
+
+    for family in families {
+        let Some(resource) = resources.get(&family.name) else {
+            continue;
+        };
+
+        for series in &family.series {
+            // rctl usage is always kept as a whole number.
+            let usage = match series.value {
+                Value::Counter(Number::Int(usage))
+                | Value::Gauge(Number::Int(usage)) => usage,
+                _                                   => continue,
+            };
+
+            let mut labels = series.labels.clone();
+            labels.retain(|(_, value)| !value.is_empty());
+            labels.sort();
+
+            jails
+                .entry(labels)
+                .or_default()
+                .push((*resource, usage));
+        }
+    }
+
+    jails
+        .into_iter()
+        .map(|(labels, usage)| {
+            ResourceUsage {
+                labels: labels,
+                usage:  usage,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::JailLabels;
+    use crate::exporter::registry::{
+        Family,
+        Registry,
+    };
+    use crate::exporter::testing;
+    use pretty_assertions::assert_eq;
+    use prometheus_client::metrics::{
+        counter::Counter,
+        gauge::Gauge,
+    };
+    use prometheus_client::registry::Unit;
+    use std::iter;
+
+    #[test]
+    fn snapshot_to_usage_ok() {
+        let mut registry = Registry::with_prefix_and_labels("jail", iter::empty());
+        let cputime = Family::<JailLabels, Counter>::default();
+        let maxproc = Family::<JailLabels, Gauge>::default();
+        let num = Gauge::<i64>::default();
+
+        registry.register_with_unit(
+            "cputime",
+            "CPU time, in seconds",
+            Unit::Seconds,
+            cputime.clone(),
+        );
+        registry.register("maxproc", "number of processes", maxproc.clone());
+        registry.register("num", "Current number of running jails", num.clone());
+
+        let web = testing::labels(&[("name", "web"), ("meta", "")]);
+        let db = testing::labels(&[("name", "db"), ("meta", "")]);
+
+        cputime.get_or_create(&web).inc_by(3600);
+        maxproc.get_or_create(&web).set(10);
+        maxproc.get_or_create(&db).set(4);
+        num.set(2);
+
+        let usage = snapshot_to_usage(&registry.snapshot());
+
+        assert_eq!(usage, vec![
+            ResourceUsage {
+                labels: vec![("name".into(), "db".into())],
+                usage:  vec![(Resource::MaxProcesses, 4)],
+            },
+            ResourceUsage {
+                labels: vec![("name".into(), "web".into())],
+                usage:  vec![
+                    (Resource::CpuTime, 3600),
+                    (Resource::MaxProcesses, 10),
+                ],
+            },
+        ]);
+    }
+
+    #[cfg(feature = "graphite")]
+    #[test]
+    fn resource_usage_label() {
+        let usage = ResourceUsage {
+            labels: vec![("name".into(), "web".into())],
+            usage:  vec![],
+        };
+
+        assert_eq!(usage.label("name"), Some("web"));
+        assert_eq!(usage.label("jid"), None);
+    }
+}
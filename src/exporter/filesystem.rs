@@ -0,0 +1,435 @@
+// filesystem: Filesystem usage of jail roots and the mounts below them.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::register_gauge_with_registry;
+use jail::RunningJail;
+use nix::sys::statfs::statfs;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Unit;
+use std::collections::HashSet;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use tracing::debug;
+use super::command;
+use super::registry::{
+    Family,
+    Registry,
+};
+use super::{
+    JailLabels,
+    ScrapedJail,
+};
+
+// Command used to list the mounted filesystems, in fstab(5) format.
+const MOUNT_COMMAND: &str = "/sbin/mount";
+
+// Limit on the number of nullfs mounts followed when looking for the
+// filesystem underlying a path.
+const MAX_NULLFS_DEPTH: usize = 8;
+
+/// A mounted filesystem.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    /// The mounted device, or source path for nullfs mounts.
+    pub device: String,
+
+    /// The path the filesystem is mounted on.
+    pub mountpoint: PathBuf,
+
+    /// The filesystem type.
+    pub fstype: String,
+}
+
+/// The table of mounted filesystems.
+#[derive(Clone, Debug, Default)]
+pub struct MountTable(Vec<Mount>);
+
+impl MountTable {
+    /// Returns the mount table, as listed by `mount -p`.
+    pub fn load() -> Result<Self, ExporterError> {
+        let output = command::output(MOUNT_COMMAND, &["-p"])?;
+
+        Ok(Self::parse(&output))
+    }
+
+    /// Parses the fstab(5) formatted output of `mount -p`. Lines that can't
+    /// be parsed are skipped.
+    pub fn parse(output: &str) -> Self {
+        let mounts = output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+
+                let device = fields.next()?;
+                let mountpoint = fields.next()?;
+                let fstype = fields.next()?;
+
+                Some(Mount {
+                    device:     unescape(device),
+                    mountpoint: PathBuf::from(unescape(mountpoint)),
+                    fstype:     fstype.to_string(),
+                })
+            })
+            .collect();
+
+        Self(mounts)
+    }
+
+    /// Returns the mount containing the given path.
+    pub fn containing(&self, path: &Path) -> Option<&Mount> {
+        self.0
+            .iter()
+            .filter(|m| path.starts_with(&m.mountpoint))
+            .max_by_key(|m| m.mountpoint.components().count())
+    }
+
+    /// Returns the mounts below the given root, excluding the root itself.
+    pub fn below<'a>(&'a self, root: &'a Path) -> impl Iterator<Item = &'a Mount> {
+        self.0
+            .iter()
+            .filter(move |m| m.mountpoint.starts_with(root) && m.mountpoint != root)
+    }
+
+    /// Returns the device of the filesystem underlying the given path,
+    /// following nullfs mounts back to their source.
+    pub fn underlying_device(&self, path: &Path) -> Option<String> {
+        self.underlying(path).map(|m| m.device.clone())
+    }
+
+    /// Returns the mount underlying the given path, following nullfs mounts
+    /// back to their source.
+    pub fn underlying(&self, path: &Path) -> Option<&Mount> {
+        let mut mount = self.containing(path)?;
+
+        for _ in 0..MAX_NULLFS_DEPTH {
+            if mount.fstype != "nullfs" {
+                break;
+            }
+
+            match self.containing(Path::new(&mount.device)) {
+                Some(source) => mount = source,
+                None         => break,
+            }
+        }
+
+        Some(mount)
+    }
+
+    /// Returns the mount whose mountpoint is the given path, following nullfs
+    /// mounts back to their source. Paths that are only a directory within a
+    /// filesystem have no mount.
+    pub fn mounted_at(&self, path: &Path) -> Option<&Mount> {
+        let mut path = path.to_path_buf();
+
+        for _ in 0..MAX_NULLFS_DEPTH {
+            let mount = self.containing(&path)?;
+
+            if mount.fstype != "nullfs" {
+                return (mount.mountpoint == path).then_some(mount);
+            }
+
+            // Carry on with the same path within the nullfs source.
+            let relative = path.strip_prefix(&mount.mountpoint)
+                .expect("path below mountpoint");
+            path = Path::new(&mount.device).join(relative);
+        }
+
+        None
+    }
+}
+
+// Decodes the octal escapes, such as `\040` for a space, used by fstab(5).
+fn unescape(field: &str) -> String {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut rest = field;
+
+    while let Some(pos) = rest.find('\\') {
+        unescaped.push_str(&rest[..pos]);
+
+        let code = rest.get(pos + 1..pos + 4)
+            .and_then(|code| u8::from_str_radix(code, 8).ok());
+
+        match code {
+            Some(code) => {
+                unescaped.push(char::from(code));
+                rest = &rest[pos + 4..];
+            },
+            None => {
+                unescaped.push('\\');
+                rest = &rest[pos + 1..];
+            },
+        }
+    }
+
+    unescaped.push_str(rest);
+    unescaped
+}
+
+// Returns the jail root and the mountpoints below it. Jails rooted at `/`,
+// as is common for service jails, share the host's filesystems rather than
+// having their own, so have none.
+fn jail_paths(table: &MountTable, root: &Path) -> Vec<PathBuf> {
+    if root == Path::new("/") {
+        debug!("Skipping filesystems of jail rooted at /");
+        return Vec::new();
+    }
+
+    std::iter::once(root.to_path_buf())
+        .chain(table.below(root).map(|m| m.mountpoint.clone()))
+        .collect()
+}
+
+/// Filesystem usage time series, registered under the `filesystem` prefix.
+pub struct FilesystemMetrics {
+    size:       Family<JailLabels, Gauge>,
+    free:       Family<JailLabels, Gauge>,
+    avail:      Family<JailLabels, Gauge>,
+    files:      Family<JailLabels, Gauge>,
+    files_free: Family<JailLabels, Gauge>,
+
+    // Report each underlying filesystem only once per scrape.
+    deduplicate: bool,
+}
+
+impl FilesystemMetrics {
+    /// Registers the filesystem metrics within a sub-registry of the given
+    /// registry.
+    pub fn new(registry: &mut Registry, deduplicate: bool) -> Self {
+        let registry = registry.sub_registry_with_prefix("filesystem");
+
+        Self {
+            size: register_gauge_with_registry!(
+                "size",
+                "filesystem size, in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            free: register_gauge_with_registry!(
+                "free",
+                "filesystem free space, in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            avail: register_gauge_with_registry!(
+                "avail",
+                "filesystem space available to non-root users, in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            files: register_gauge_with_registry!(
+                "files",
+                "filesystem total file nodes",
+                JailLabels,
+                registry,
+            ),
+
+            files_free: register_gauge_with_registry!(
+                "files_free",
+                "filesystem free file nodes",
+                JailLabels,
+                registry,
+            ),
+
+            deduplicate: deduplicate,
+        }
+    }
+
+    /// Sets the filesystem usage of the given jails. Filesystems are
+    /// collected afresh on each scrape, so any existing time series are
+    /// removed first.
+    pub fn collect(&self, jails: &[ScrapedJail]) -> Result<(), ExporterError> {
+        debug!("Collecting filesystem metrics");
+
+        self.clear();
+
+        let table = MountTable::load()?;
+
+        // Underlying filesystems reported so far, for deduplication.
+        let mut seen = HashSet::new();
+
+        for jail in jails {
+            let root = RunningJail::from_jid_unchecked(jail.jid).path()?;
+
+            for path in jail_paths(&table, &root) {
+                let device = table.underlying_device(&path)
+                    .unwrap_or_else(|| path.display().to_string());
+
+                if self.deduplicate && !seen.insert(device) {
+                    debug!("Skipping duplicate filesystem at {}", path.display());
+                    continue;
+                }
+
+                // Mounts can vanish between listing and statfs, which isn't
+                // worth failing the scrape over.
+                let stats = match statfs(&path) {
+                    Ok(stats) => stats,
+                    Err(e) => {
+                        debug!("statfs failed for {}: {}", path.display(), e);
+                        continue;
+                    },
+                };
+
+                // Mountpoints are reported relative to the jail root.
+                let relative = path.strip_prefix(&root)
+                    .expect("path below jail root");
+                let mountpoint = Path::new("/").join(relative);
+
+                let mut labels = jail.labels.clone();
+                labels.push("mountpoint", &mountpoint.display().to_string());
+                labels.push("fstype", stats.filesystem_type_name());
+
+                let bsize = stats.block_size() as i64;
+
+                self.size
+                    .get_or_create(&labels)
+                    .set(stats.blocks() as i64 * bsize);
+                self.free
+                    .get_or_create(&labels)
+                    .set(stats.blocks_free() as i64 * bsize);
+                self.avail
+                    .get_or_create(&labels)
+                    .set(stats.blocks_available() * bsize);
+                self.files
+                    .get_or_create(&labels)
+                    .set(stats.files() as i64);
+                self.files_free
+                    .get_or_create(&labels)
+                    .set(stats.files_free());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Removes all filesystem time series.
+    fn clear(&self) {
+        self.size.clear();
+        self.free.clear();
+        self.avail.clear();
+        self.files.clear();
+        self.files_free.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    fn table() -> MountTable {
+        let output = fs::read_to_string("test-data/mount_p.txt").unwrap();
+        MountTable::parse(&output)
+    }
+
+    #[test]
+    fn mount_table_parse() {
+        let table = table();
+
+        assert_eq!(table.0.len(), 7);
+        assert_eq!(table.0[0], Mount {
+            device:     "zroot/ROOT/default".into(),
+            mountpoint: PathBuf::from("/"),
+            fstype:     "zfs".into(),
+        });
+    }
+
+    #[test]
+    fn mount_table_parse_escaped() {
+        let table = table();
+        let mount = table.containing(Path::new("/mnt/My Files/a")).unwrap();
+
+        assert_eq!(mount.mountpoint, PathBuf::from("/mnt/My Files"));
+    }
+
+    #[test]
+    fn mount_table_below() {
+        let table = table();
+        let root = Path::new("/jails/www");
+
+        let below: Vec<&Path> = table.below(root)
+            .map(|m| m.mountpoint.as_path())
+            .collect();
+
+        assert_eq!(below, vec![
+            Path::new("/jails/www/dev"),
+            Path::new("/jails/www/usr/ports"),
+        ]);
+    }
+
+    #[test]
+    fn jail_paths_ok() {
+        let table = table();
+
+        assert_eq!(jail_paths(&table, Path::new("/jails/www")), vec![
+            PathBuf::from("/jails/www"),
+            PathBuf::from("/jails/www/dev"),
+            PathBuf::from("/jails/www/usr/ports"),
+        ]);
+    }
+
+    #[test]
+    fn jail_paths_host_root() {
+        let table = table();
+
+        assert_eq!(jail_paths(&table, Path::new("/")), Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn mount_table_underlying_device() {
+        let table = table();
+
+        // Jail root on its own dataset.
+        assert_eq!(
+            table.underlying_device(Path::new("/jails/www")),
+            Some("zroot/jails/www".into()),
+        );
+
+        // nullfs mount of a directory on another dataset.
+        assert_eq!(
+            table.underlying_device(Path::new("/jails/www/usr/ports")),
+            Some("zroot/usr/ports".into()),
+        );
+
+        // Jail root that is a plain directory on the root dataset.
+        assert_eq!(
+            table.underlying_device(Path::new("/jails/plain")),
+            Some("zroot/ROOT/default".into()),
+        );
+    }
+
+    #[test]
+    fn mount_table_mounted_at() {
+        let table = table();
+
+        let device = |path| {
+            table.mounted_at(Path::new(path)).map(|m| m.device.as_str())
+        };
+
+        // Jail root on its own dataset.
+        assert_eq!(device("/jails/www"), Some("zroot/jails/www"));
+
+        // nullfs mount of another dataset's mountpoint.
+        assert_eq!(device("/jails/www/usr/ports"), Some("zroot/usr/ports"));
+
+        // Plain directories, directly or through a nullfs mount.
+        assert_eq!(device("/jails/plain"), None);
+        assert_eq!(device("/jails/www/usr/ports/distfiles"), None);
+    }
+
+    #[test]
+    fn unescape_ok() {
+        assert_eq!(unescape("/mnt/My\\040Files"), "/mnt/My Files");
+        assert_eq!(unescape("/no/escapes"), "/no/escapes");
+        assert_eq!(unescape("trailing\\"), "trailing\\");
+    }
+}
@@ -0,0 +1,70 @@
+// filter: Selects which jails are exported based on their names.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use regex::Regex;
+
+/// Include and exclude patterns applied to jail names.
+///
+/// A jail is exported if it matches the include pattern (or there is none)
+/// and doesn't match the exclude pattern.
+#[derive(Clone, Debug, Default)]
+pub struct JailFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+impl JailFilter {
+    /// Returns a new filter from the given patterns.
+    pub fn new(include: Option<Regex>, exclude: Option<Regex>) -> Self {
+        Self {
+            include,
+            exclude,
+        }
+    }
+
+    /// Returns a boolean indicating if the named jail should be exported.
+    pub fn is_match(&self, name: &str) -> bool {
+        let included = self.include
+            .as_ref()
+            .map_or(true, |include| include.is_match(name));
+
+        let excluded = self.exclude
+            .as_ref()
+            .is_some_and(|exclude| exclude.is_match(name));
+
+        included && !excluded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jail_filter_default() {
+        let filter = JailFilter::default();
+
+        assert!(filter.is_match("anything"));
+    }
+
+    #[test]
+    fn jail_filter_exclude() {
+        let exclude = Regex::new("^(?:ci-.*)$").unwrap();
+        let filter = JailFilter::new(None, Some(exclude));
+
+        assert!(filter.is_match("www"));
+        assert!(filter.is_match("www-ci-1"));
+        assert!(!filter.is_match("ci-1234"));
+    }
+
+    #[test]
+    fn jail_filter_include_and_exclude() {
+        let include = Regex::new("^(?:prod-.*)$").unwrap();
+        let exclude = Regex::new("^(?:prod-test)$").unwrap();
+        let filter = JailFilter::new(Some(include), Some(exclude));
+
+        assert!(filter.is_match("prod-www"));
+        assert!(!filter.is_match("prod-test"));
+        assert!(!filter.is_match("dev-www"));
+    }
+}
@@ -0,0 +1,249 @@
+// hierarchy: Relationships between hierarchical jails.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use super::Rusage;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::fmt;
+use std::str::FromStr;
+
+/// How resource usage is reported for jails with children.
+///
+/// The kernel charges resource usage to a jail and all of its ancestors, so
+/// the usage reported for a parent jail already includes the usage of its
+/// children.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JailUsage {
+    /// Parents include the usage of their children.
+    #[default]
+    Rollup,
+
+    /// Parents report only their own usage, with their children's removed.
+    Own,
+}
+
+impl fmt::Display for JailUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let usage = match self {
+            Self::Rollup => "rollup",
+            Self::Own    => "own",
+        };
+
+        write!(f, "{usage}")
+    }
+}
+
+impl FromStr for JailUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rollup" => Ok(Self::Rollup),
+            "own"    => Ok(Self::Own),
+            _        => Err(format!("unknown jail usage: {s}")),
+        }
+    }
+}
+
+/// Returns the name of the jail's parent, if it has one.
+///
+/// Child jail names are prefixed with the name of their parent and a dot, eg.
+/// `outer.inner`. Names are relative to the jail the exporter runs in, so
+/// jails directly below it have no parent.
+pub fn parent(name: &str) -> Option<&str> {
+    name.rsplit_once('.').map(|(parent, _)| parent)
+}
+
+/// Returns the depth of the jail below the exporter, starting at 0.
+pub fn depth(name: &str) -> usize {
+    name.matches('.').count()
+}
+
+/// Returns the total usage of all jails, counting the usage of each child
+/// jail only once. With [`JailUsage::Rollup`], only the jails at the top of
+/// the hierarchy are summed, as their usage includes their children's.
+pub fn total_usage(
+    usage: &HashMap<String, Rusage>,
+    jail_usage: JailUsage,
+) -> Rusage {
+    let mut total = Rusage::new();
+
+    let jails = usage
+        .iter()
+        .filter(|(name, _)| {
+            jail_usage == JailUsage::Own || parent(name).is_none()
+        });
+
+    for (_, rusage) in jails {
+        for (resource, value) in rusage {
+            let sum = total.entry(*resource).or_default();
+            *sum = sum.saturating_add(*value);
+        }
+    }
+
+    total
+}
+
+/// The tree of running jails, built from their names.
+#[derive(Debug, Default)]
+pub struct JailTree {
+    // Names of the direct children of each jail.
+    children: HashMap<String, HashSet<String>>,
+}
+
+impl JailTree {
+    /// Builds the tree from the names of the running jails.
+    pub fn new<'a, I>(names: I) -> Self
+    where I: IntoIterator<Item = &'a str> {
+        let mut children: HashMap<String, HashSet<String>> = HashMap::new();
+
+        for name in names {
+            if let Some(parent) = parent(name) {
+                children
+                    .entry(parent.to_string())
+                    .or_default()
+                    .insert(name.to_string());
+            }
+        }
+
+        Self {
+            children: children,
+        }
+    }
+
+    /// Returns the names of the direct children of the named jail.
+    pub fn children(&self, name: &str) -> impl Iterator<Item = &str> {
+        self.children
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+    }
+
+    /// Returns the usage of each jail with the usage of its direct children
+    /// removed. The usage of a direct child includes that of its own
+    /// children, so this leaves just the jail's own usage.
+    pub fn own_usage(
+        &self,
+        usage: &HashMap<String, Rusage>,
+    ) -> HashMap<String, Rusage> {
+        usage
+            .iter()
+            .map(|(name, rusage)| {
+                let mut own = rusage.clone();
+
+                let children = self
+                    .children(name)
+                    .filter_map(|child| usage.get(child));
+
+                for child in children {
+                    for (resource, value) in child {
+                        if let Some(own_value) = own.get_mut(resource) {
+                            *own_value = own_value.saturating_sub(*value);
+                        }
+                    }
+                }
+
+                (name.clone(), own)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rctl::Resource;
+    use std::fs;
+
+    // Reads the jail names from a fixture in the format of `jls -h name`.
+    fn fixture_names(path: &str) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn rusage(cputime: usize) -> Rusage {
+        Rusage::from([(Resource::CpuTime, cputime)])
+    }
+
+    #[test]
+    fn jail_parent_and_depth() {
+        assert_eq!(parent("outer"), None);
+        assert_eq!(parent("outer.inner"), Some("outer"));
+        assert_eq!(parent("outer.inner.leaf"), Some("outer.inner"));
+
+        assert_eq!(depth("outer"), 0);
+        assert_eq!(depth("outer.inner"), 1);
+        assert_eq!(depth("outer.inner.leaf"), 2);
+    }
+
+    #[test]
+    fn jail_tree_children() {
+        let names = fixture_names("test-data/jls_hierarchy.txt");
+        let tree = JailTree::new(names.iter().map(String::as_str));
+
+        let mut children: Vec<&str> = tree.children("outer").collect();
+        children.sort_unstable();
+
+        assert_eq!(children, vec!["outer.db", "outer.web"]);
+        assert_eq!(tree.children("outer.web").collect::<Vec<_>>(), vec![
+            "outer.web.cache",
+        ]);
+        assert_eq!(tree.children("standalone").count(), 0);
+    }
+
+    #[test]
+    fn jail_tree_own_usage() {
+        let names = fixture_names("test-data/jls_hierarchy.txt");
+        let tree = JailTree::new(names.iter().map(String::as_str));
+
+        let usage = HashMap::from([
+            ("outer".to_string(), rusage(100)),
+            ("outer.db".to_string(), rusage(20)),
+            ("outer.web".to_string(), rusage(50)),
+            ("outer.web.cache".to_string(), rusage(10)),
+            ("standalone".to_string(), rusage(5)),
+        ]);
+
+        let own = tree.own_usage(&usage);
+
+        assert_eq!(own["outer"], rusage(30));
+        assert_eq!(own["outer.db"], rusage(20));
+        assert_eq!(own["outer.web"], rusage(40));
+        assert_eq!(own["outer.web.cache"], rusage(10));
+        assert_eq!(own["standalone"], rusage(5));
+    }
+
+    #[test]
+    fn total_usage_counts_children_once() {
+        let names = fixture_names("test-data/jls_hierarchy.txt");
+        let tree = JailTree::new(names.iter().map(String::as_str));
+
+        let usage = HashMap::from([
+            ("outer".to_string(), rusage(100)),
+            ("outer.db".to_string(), rusage(20)),
+            ("outer.web".to_string(), rusage(50)),
+            ("outer.web.cache".to_string(), rusage(10)),
+            ("standalone".to_string(), rusage(5)),
+        ]);
+
+        let own = tree.own_usage(&usage);
+
+        assert_eq!(total_usage(&usage, JailUsage::Rollup), rusage(105));
+        assert_eq!(total_usage(&own, JailUsage::Own), rusage(105));
+    }
+
+    #[test]
+    fn jail_usage_from_str() {
+        assert_eq!(JailUsage::from_str("rollup"), Ok(JailUsage::Rollup));
+        assert_eq!(JailUsage::from_str("own"), Ok(JailUsage::Own));
+        assert!(JailUsage::from_str("nope").is_err());
+    }
+}
@@ -0,0 +1,438 @@
+// host: Host totals, and the share of them used by each jail.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::register_gauge_with_registry;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Unit;
+use rctl::{
+    Resource,
+    Subject,
+};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use sysctl::{
+    Ctl,
+    CtlValue,
+    Sysctl,
+};
+use tracing::debug;
+use super::command;
+use super::registry::{
+    Family,
+    Registry,
+};
+use super::{
+    JailLabels,
+    Rusage,
+    ScrapedJail,
+};
+
+// Sysctls holding the host's physical memory and CPU count.
+const CTL_HW_NCPU: &str    = "hw.ncpu";
+const CTL_HW_PHYSMEM: &str = "hw.physmem";
+
+// Command used to list the swap devices.
+const SWAPINFO_COMMAND: &str = "/usr/sbin/swapinfo";
+
+// Name of the jail that every process on the host belongs to.
+const HOST_JAIL: &str = "0";
+
+/// What a jail's usage of a resource is compared against to work out its
+/// share of the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Denominator {
+    /// The number of CPUs, in percents.
+    Cpus,
+
+    /// The host's physical memory.
+    Physmem,
+
+    /// The host's total swap space.
+    Swap,
+
+    /// The host-wide racct usage of the same resource.
+    HostUsage,
+}
+
+/// The resources for which a jail's share of the host is reported. Resources
+/// that accumulate over the life of a jail, such as `cputime`, aren't
+/// comparable with the host's and are left out.
+const SHARE_RESOURCES: [(Resource, Denominator); 10] = [
+    (Resource::MaxProcesses, Denominator::HostUsage),
+    (Resource::MemoryUse,    Denominator::Physmem),
+    (Resource::NThreads,     Denominator::HostUsage),
+    (Resource::OpenFiles,    Denominator::HostUsage),
+    (Resource::PercentCpu,   Denominator::Cpus),
+    (Resource::ReadBps,      Denominator::HostUsage),
+    (Resource::ReadIops,     Denominator::HostUsage),
+    (Resource::SwapUse,      Denominator::Swap),
+    (Resource::WriteBps,     Denominator::HostUsage),
+    (Resource::WriteIops,    Denominator::HostUsage),
+];
+
+/// A source of host information. This is abstracted so that the collector
+/// can be tested without a FreeBSD host.
+pub trait HostSource: Send + Sync {
+    /// Returns the number of CPUs.
+    fn ncpu(&self) -> Result<u64, ExporterError>;
+
+    /// Returns the physical memory, in bytes.
+    fn physmem(&self) -> Result<u64, ExporterError>;
+
+    /// Returns the `swapinfo -k` output.
+    fn swapinfo(&self) -> Result<String, ExporterError>;
+
+    /// Returns the racct usage of the whole host.
+    fn usage(&self) -> Result<Rusage, ExporterError>;
+}
+
+/// Gets host information from sysctls, `swapinfo(8)` and the racct usage of
+/// jail 0, which every process on the host belongs to.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HostSysctl;
+
+impl HostSource for HostSysctl {
+    fn ncpu(&self) -> Result<u64, ExporterError> {
+        sysctl_u64(CTL_HW_NCPU)
+    }
+
+    fn physmem(&self) -> Result<u64, ExporterError> {
+        sysctl_u64(CTL_HW_PHYSMEM)
+    }
+
+    fn swapinfo(&self) -> Result<String, ExporterError> {
+        command::output(SWAPINFO_COMMAND, &["-k"])
+    }
+
+    fn usage(&self) -> Result<Rusage, ExporterError> {
+        Subject::jail_name(HOST_JAIL)
+            .usage()
+            .map_err(|e| ExporterError::RctlUnavailable(e.to_string()))
+    }
+}
+
+// Reads an integer sysctl. The width of these varies between sysctls and
+// architectures.
+fn sysctl_u64(name: &str) -> Result<u64, ExporterError> {
+    let value = match Ctl::new(name)?.value()? {
+        CtlValue::Int(v)   => u64::try_from(v).ok(),
+        CtlValue::Long(v)  => u64::try_from(v).ok(),
+        CtlValue::Uint(v)  => Some(u64::from(v)),
+        CtlValue::Ulong(v) => Some(v),
+        CtlValue::U64(v)   => Some(v),
+        _                  => None,
+    };
+
+    value.ok_or_else(|| ExporterError::SysctlValueError(name.to_string()))
+}
+
+/// Total and used swap space, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Swap {
+    /// Total swap space.
+    pub total: u64,
+
+    /// Used swap space.
+    pub used: u64,
+}
+
+/// Parses the output of `swapinfo -k`, summing the swap devices. The `Total`
+/// line printed for multiple devices is skipped.
+pub fn parse_swapinfo(output: &str) -> Swap {
+    output
+        .lines()
+        .skip(1)
+        .filter(|line| !line.starts_with("Total"))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+
+            let total: u64 = fields.next()?.parse().ok()?;
+            let used: u64 = fields.next()?.parse().ok()?;
+
+            Some(Swap {
+                total: total * 1024,
+                used:  used * 1024,
+            })
+        })
+        .fold(Swap::default(), |acc, swap| {
+            Swap {
+                total: acc.total + swap.total,
+                used:  acc.used + swap.used,
+            }
+        })
+}
+
+/// Host time series, registered under the `host` prefix.
+pub struct HostMetrics {
+    cpus:        Gauge,
+    physmem:     Gauge,
+    swap_total:  Gauge,
+    swap_used:   Gauge,
+    usage:       Family<JailLabels, Gauge>,
+    unjailed:    Family<JailLabels, Gauge>,
+    share_ratio: Family<JailLabels, Gauge<f64, AtomicU64>>,
+
+    // Where the host information comes from.
+    source: Box<dyn HostSource>,
+}
+
+impl HostMetrics {
+    /// Registers the host metrics within a sub-registry of the given
+    /// registry, getting information from the given source.
+    pub fn new(registry: &mut Registry, source: Box<dyn HostSource>) -> Self {
+        let registry = registry.sub_registry_with_prefix("host");
+
+        let share_ratio = Family::<JailLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "share_ratio",
+            "share of the host's resource used by the jail, between 0 and 1",
+            share_ratio.clone(),
+        );
+
+        Self {
+            cpus: register_gauge_with_registry!(
+                "cpus",
+                "number of CPUs in the host",
+                registry,
+            ),
+
+            physmem: register_bytes(
+                registry,
+                "physmem",
+                "physical memory of the host, in bytes",
+            ),
+
+            swap_total: register_bytes(
+                registry,
+                "swap_total",
+                "total swap space of the host, in bytes",
+            ),
+
+            swap_used: register_bytes(
+                registry,
+                "swap_used",
+                "used swap space of the host, in bytes",
+            ),
+
+            usage: register_gauge_with_registry!(
+                "usage",
+                "racct usage of the whole host, by resource",
+                JailLabels,
+                registry,
+            ),
+
+            unjailed: register_gauge_with_registry!(
+                "unjailed_usage",
+                "racct usage of processes outside of any jail, by resource",
+                JailLabels,
+                registry,
+            ),
+
+            share_ratio: share_ratio,
+            source:      source,
+        }
+    }
+
+    /// Sets the host totals and the share of them used by the given jails.
+    ///
+    /// `usage` holds the usage of each jail by name, and `jailed` the total
+    /// usage of all running jails, including those filtered out.
+    pub fn collect(
+        &self,
+        jails: &[ScrapedJail],
+        usage: &HashMap<String, Rusage>,
+        jailed: &Rusage,
+    ) -> Result<(), ExporterError> {
+        debug!("Collecting host metrics");
+
+        self.clear();
+
+        let ncpu = self.source.ncpu()?;
+        let physmem = self.source.physmem()?;
+        let swap = parse_swapinfo(&self.source.swapinfo()?);
+
+        self.cpus.set(clamp(ncpu));
+        self.physmem.set(clamp(physmem));
+        self.swap_total.set(clamp(swap.total));
+        self.swap_used.set(clamp(swap.used));
+
+        // Jail 0 isn't visible when we're running within a jail, in which
+        // case the host-wide usage is left out.
+        let host_usage = match self.source.usage() {
+            Ok(host_usage) => host_usage,
+            Err(e) => {
+                debug!("Couldn't get host usage: {}", e);
+                Rusage::new()
+            },
+        };
+
+        for (resource, value) in &host_usage {
+            let labels = resource_labels(&JailLabels::default(), resource);
+            let unjailed = value.saturating_sub(
+                jailed.get(resource).copied().unwrap_or_default()
+            );
+
+            self.usage.get_or_create(&labels).set(clamp(*value as u64));
+            self.unjailed.get_or_create(&labels).set(clamp(unjailed as u64));
+        }
+
+        for jail in jails {
+            let Some(rusage) = usage.get(&jail.name) else {
+                continue;
+            };
+
+            for (resource, denominator) in SHARE_RESOURCES {
+                let total = match denominator {
+                    Denominator::Cpus      => ncpu * 100,
+                    Denominator::Physmem   => physmem,
+                    Denominator::Swap      => swap.total,
+                    Denominator::HostUsage => {
+                        host_usage.get(&resource).copied().unwrap_or_default() as u64
+                    },
+                };
+
+                let Some(value) = rusage.get(&resource) else {
+                    continue;
+                };
+
+                // A share of nothing isn't meaningful.
+                if total == 0 {
+                    continue;
+                }
+
+                let labels = resource_labels(&jail.labels, &resource);
+                let ratio = *value as f64 / total as f64;
+
+                self.share_ratio.get_or_create(&labels).set(ratio);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Removes the per resource and per jail time series.
+    fn clear(&self) {
+        self.usage.clear();
+        self.unjailed.clear();
+        self.share_ratio.clear();
+    }
+}
+
+// Registers an unlabelled gauge measured in bytes.
+fn register_bytes(registry: &mut Registry, name: &str, help: &str) -> Gauge {
+    let gauge = Gauge::default();
+    registry.register_with_unit(name, help, Unit::Bytes, gauge.clone());
+    gauge
+}
+
+// Clamps a value down to an i64 for our Gauge values.
+fn clamp(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+// Returns the given labels with a resource label added.
+fn resource_labels(labels: &JailLabels, resource: &Resource) -> JailLabels {
+    let mut labels = labels.clone();
+    labels.push("resource", resource.as_str());
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing::scraped;
+    use crate::exporter::registry::encode;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    // Source returning fixed host information.
+    struct FixtureSource;
+
+    impl HostSource for FixtureSource {
+        fn ncpu(&self) -> Result<u64, ExporterError> {
+            Ok(4)
+        }
+
+        fn physmem(&self) -> Result<u64, ExporterError> {
+            Ok(8 * GIB)
+        }
+
+        fn swapinfo(&self) -> Result<String, ExporterError> {
+            Ok(fs::read_to_string("test-data/swapinfo_k.txt")?)
+        }
+
+        fn usage(&self) -> Result<Rusage, ExporterError> {
+            Ok(Rusage::from([
+                (Resource::MaxProcesses, 200),
+                (Resource::MemoryUse, 4 * GIB as usize),
+            ]))
+        }
+    }
+
+    #[test]
+    fn parse_swapinfo_ok() {
+        let output = fs::read_to_string("test-data/swapinfo_k.txt").unwrap();
+
+        assert_eq!(parse_swapinfo(&output), Swap {
+            total: 3 * GIB,
+            used:  GIB / 4,
+        });
+    }
+
+    #[test]
+    fn parse_swapinfo_no_swap() {
+        let output = "Device          1K-blocks     Used    Avail Capacity\n";
+
+        assert_eq!(parse_swapinfo(output), Swap::default());
+    }
+
+    #[test]
+    fn collect_fixture() {
+        let mut registry = Registry::default();
+        let metrics = HostMetrics::new(&mut registry, Box::new(FixtureSource));
+
+        let usage = HashMap::from([
+            ("db".to_string(), Rusage::from([
+                (Resource::MaxProcesses, 50),
+                (Resource::MemoryUse, 2 * GIB as usize),
+                (Resource::PercentCpu, 100),
+                (Resource::SwapUse, 0),
+            ])),
+        ]);
+
+        let jailed = Rusage::from([
+            (Resource::MaxProcesses, 80),
+            (Resource::MemoryUse, 3 * GIB as usize),
+        ]);
+
+        metrics.collect(&[scraped(1, "db")], &usage, &jailed).unwrap();
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        assert!(buffer.contains("host_cpus 4\n"));
+        assert!(buffer.contains("host_physmem_bytes 8589934592\n"));
+        assert!(buffer.contains("host_swap_total_bytes 3221225472\n"));
+        assert!(buffer.contains("host_swap_used_bytes 268435456\n"));
+        assert!(buffer.contains("host_usage{resource=\"maxproc\"} 200\n"));
+        assert!(buffer.contains("host_unjailed_usage{resource=\"maxproc\"} 120\n"));
+        assert!(buffer.contains(
+            "host_unjailed_usage{resource=\"memoryuse\"} 1073741824\n"
+        ));
+        assert!(buffer.contains(
+            "host_share_ratio{name=\"db\",resource=\"memoryuse\"} 0.25\n"
+        ));
+        assert!(buffer.contains(
+            "host_share_ratio{name=\"db\",resource=\"maxproc\"} 0.25\n"
+        ));
+        assert!(buffer.contains(
+            "host_share_ratio{name=\"db\",resource=\"pcpu\"} 0.25\n"
+        ));
+        assert!(buffer.contains(
+            "host_share_ratio{name=\"db\",resource=\"swapuse\"} 0.0\n"
+        ));
+    }
+}
@@ -0,0 +1,355 @@
+// labels: Label sets identifying the time series of each jail.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use prometheus_client::encoding::{
+    EncodeLabelSet,
+    LabelSetEncoder,
+};
+use std::fmt;
+use std::str::FromStr;
+use tracing::debug;
+use super::registry::LabelPairs;
+
+// Label names used by the exporter's own time series. Extra labels may not
+// use these, or the same label would appear twice on a series.
+const RESERVED_LABELS: &[&str] = &[
+    "command",
+    "dataset",
+    "depth",
+    "fstype",
+    "interface",
+    "jid",
+    "mountpoint",
+    "name",
+    "parent",
+    "path",
+    "rank",
+    "resource",
+    "rustversion",
+    "state",
+    "status",
+    "type",
+    "version",
+    "view",
+];
+
+/// Returns a valid Prometheus label name derived from the given name, or
+/// `None` if no sensible label name can be made from it.
+///
+/// Characters outside of `[a-zA-Z0-9_]` are replaced with underscores and a
+/// leading digit is prefixed with an underscore. Names beginning with `__`
+/// are reserved for Prometheus' internal use and are rejected.
+pub fn sanitise_label_name(name: &str) -> Option<String> {
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut sanitised: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    if sanitised.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitised.insert(0, '_');
+    }
+
+    if sanitised.starts_with("__") {
+        return None;
+    }
+
+    Some(sanitised)
+}
+
+/// Returns a boolean indicating if the label name is used by the exporter.
+pub fn is_reserved_label(name: &str) -> bool {
+    RESERVED_LABELS.contains(&name)
+}
+
+/// The labels used to identify a jail's time series.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JailIdentity {
+    /// Series are keyed on the jail name.
+    #[default]
+    Name,
+
+    /// Series are keyed on the jail ID.
+    Jid,
+
+    /// Series are keyed on both the jail name and jail ID.
+    NameAndJid,
+}
+
+impl fmt::Display for JailIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let identity = match self {
+            Self::Name       => "name",
+            Self::Jid        => "jid",
+            Self::NameAndJid => "name+jid",
+        };
+
+        write!(f, "{identity}")
+    }
+}
+
+impl FromStr for JailIdentity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name"     => Ok(Self::Name),
+            "jid"      => Ok(Self::Jid),
+            "name+jid" => Ok(Self::NameAndJid),
+            _          => Err(format!("unknown jail identity: {s}")),
+        }
+    }
+}
+
+impl JailIdentity {
+    /// Returns the names of the labels that identify a jail's time series.
+    pub fn key_labels(self) -> &'static [&'static str] {
+        match self {
+            Self::Name       => &["name"],
+            Self::Jid        => &["jid"],
+            Self::NameAndJid => &["name", "jid"],
+        }
+    }
+
+    /// Returns the series for the given jail.
+    pub fn series(self, jid: i32, name: &str) -> JailSeries {
+        let mut key = JailLabels::default();
+
+        match self {
+            Self::Name => {
+                key.push("name", name);
+            },
+            Self::Jid => {
+                key.push("jid", &jid.to_string());
+            },
+            Self::NameAndJid => {
+                key.push("name", name);
+                key.push("jid", &jid.to_string());
+            },
+        }
+
+        // The jail_id metric always carries the jail name, so that jail IDs
+        // can be mapped back to names when series are keyed on the JID.
+        let mut id_labels = key.clone();
+        if id_labels.get("name").is_none() {
+            id_labels.push("name", name);
+        }
+
+        JailSeries {
+            key:       key.clone(),
+            labels:    key,
+            id_labels: id_labels,
+        }
+    }
+}
+
+/// A dynamic set of labels attached to a jail's time series.
+#[derive(Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct JailLabels(Vec<(String, String)>);
+
+impl JailLabels {
+    /// Returns the value of the named label, if present.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Appends a label to the set.
+    pub fn push(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_string(), value.to_string()));
+    }
+
+    /// Parses labels from whitespace separated `key=value` pairs, as found in
+    /// a jail's `meta` parameter.
+    ///
+    /// Label names are sanitised, and pairs are skipped if they can't be
+    /// turned into a label name, if their name is reserved or if it is
+    /// already present in `existing`. At most `limit` labels are returned.
+    pub fn from_meta(meta: &str, existing: &Self, limit: usize) -> Self {
+        let mut labels = Self::default();
+
+        for (key, value) in meta.split_whitespace().filter_map(|p| p.split_once('=')) {
+            if labels.0.len() >= limit {
+                debug!("Jail meta label limit of {} reached", limit);
+                break;
+            }
+
+            let Some(name) = sanitise_label_name(key) else {
+                debug!("Skipping jail meta label with invalid name: {}", key);
+                continue;
+            };
+
+            let duplicate = is_reserved_label(&name)
+                || existing.get(&name).is_some()
+                || labels.get(&name).is_some();
+
+            if duplicate {
+                debug!("Skipping duplicate jail meta label: {}", name);
+                continue;
+            }
+
+            labels.push(&name, value);
+        }
+
+        labels
+    }
+}
+
+impl IntoIterator for JailLabels {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl EncodeLabelSet for JailLabels {
+    fn encode(&self, encoder: LabelSetEncoder) -> Result<(), fmt::Error> {
+        self.0.encode(encoder)
+    }
+}
+
+impl LabelPairs for JailLabels {
+    fn label_pairs(&self) -> Vec<(String, String)> {
+        self.0.clone()
+    }
+}
+
+/// The label sets used for the time series of a single jail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JailSeries {
+    /// Labels identifying the jail across scrapes.
+    pub key: JailLabels,
+
+    /// Labels attached to the jail's resource time series.
+    pub labels: JailLabels,
+
+    /// Labels attached to the `jail_id` time series.
+    pub id_labels: JailLabels,
+}
+
+impl JailSeries {
+    /// Adds extra labels to the resource and `jail_id` time series. The key
+    /// is left untouched, so a jail whose extra labels change is still
+    /// recognised as the same jail.
+    pub fn with_labels(mut self, extra: JailLabels) -> Self {
+        for (name, value) in extra {
+            self.labels.push(&name, &value);
+            self.id_labels.push(&name, &value);
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn jail_labels_from_meta() {
+        let existing = JailLabels::default();
+        let labels = JailLabels::from_meta(
+            "env=prod team.name=web 1x=y novalue name=bad __x=y",
+            &existing,
+            10,
+        );
+
+        assert_eq!(labels.get("env"), Some("prod"));
+        assert_eq!(labels.get("team_name"), Some("web"));
+        assert_eq!(labels.get("_1x"), Some("y"));
+        assert_eq!(labels.get("name"), None);
+        assert_eq!(labels.0.len(), 3);
+    }
+
+    #[test]
+    fn jail_labels_from_meta_existing() {
+        let mut existing = JailLabels::default();
+        existing.push("datacenter", "ams1");
+
+        let labels = JailLabels::from_meta(
+            "datacenter=lon1 env=prod env=dev",
+            &existing,
+            10,
+        );
+
+        assert_eq!(labels.get("datacenter"), None);
+        assert_eq!(labels.get("env"), Some("prod"));
+        assert_eq!(labels.0.len(), 1);
+    }
+
+    #[test]
+    fn jail_labels_from_meta_limit() {
+        let existing = JailLabels::default();
+        let labels = JailLabels::from_meta("a=1 b=2 c=3", &existing, 2);
+
+        assert_eq!(labels.get("a"), Some("1"));
+        assert_eq!(labels.get("b"), Some("2"));
+        assert_eq!(labels.get("c"), None);
+    }
+
+    #[test]
+    fn jail_series_with_labels() {
+        let mut extra = JailLabels::default();
+        extra.push("env", "prod");
+
+        let series = JailIdentity::Jid.series(5, "www").with_labels(extra);
+
+        assert_eq!(series.labels.get("env"), Some("prod"));
+        assert_eq!(series.id_labels.get("env"), Some("prod"));
+        assert_eq!(series.key.get("env"), None);
+    }
+
+    #[test]
+    fn sanitise_label_name_ok() {
+        assert_eq!(sanitise_label_name("env"), Some("env".into()));
+        assert_eq!(sanitise_label_name("a-b.c"), Some("a_b_c".into()));
+        assert_eq!(sanitise_label_name("9lives"), Some("_9lives".into()));
+        assert_eq!(sanitise_label_name("__meta"), None);
+        assert_eq!(sanitise_label_name(""), None);
+    }
+
+    #[test]
+    fn jail_identity_from_str() {
+        assert_eq!(JailIdentity::from_str("name"), Ok(JailIdentity::Name));
+        assert_eq!(JailIdentity::from_str("jid"), Ok(JailIdentity::Jid));
+        assert_eq!(
+            JailIdentity::from_str("name+jid"),
+            Ok(JailIdentity::NameAndJid),
+        );
+        assert!(JailIdentity::from_str("nope").is_err());
+    }
+
+    #[test]
+    fn jail_identity_series_jid() {
+        let series = JailIdentity::Jid.series(5, "www");
+
+        assert_eq!(series.labels.get("jid"), Some("5"));
+        assert_eq!(series.labels.get("name"), None);
+        assert_eq!(series.id_labels.get("name"), Some("www"));
+    }
+
+    #[test]
+    fn jail_identity_series_name() {
+        let series = JailIdentity::Name.series(5, "www");
+
+        assert_eq!(series.labels.get("name"), Some("www"));
+        assert_eq!(series.labels.get("jid"), None);
+        assert_eq!(series.labels, series.id_labels);
+    }
+
+    #[test]
+    fn jail_identity_series_name_and_jid() {
+        let series = JailIdentity::NameAndJid.series(5, "www");
+
+        assert_eq!(series.labels.get("name"), Some("www"));
+        assert_eq!(series.labels.get("jid"), Some("5"));
+    }
+}
@@ -0,0 +1,424 @@
+// processes: Summary of the process table of each jail.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::register_gauge_with_registry;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Unit;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
+use tracing::debug;
+use super::command;
+use super::registry::{
+    Family,
+    Registry,
+};
+use super::{
+    JailLabels,
+    ScrapedJail,
+};
+
+// Command used to list processes.
+const PS_COMMAND: &str = "/bin/ps";
+
+/// The default number of processes reported by RSS and CPU for each jail.
+pub const DEFAULT_TOP_N: u8 = 5;
+
+/// The maximum number of processes that may be reported by RSS and CPU for
+/// each jail. This caps the cardinality of the top process time series.
+pub const MAX_TOP_N: u8 = 20;
+
+/// A source of process information. This is abstracted so that the collector
+/// can be tested against captured command output.
+pub trait ProcessSource: Send + Sync {
+    /// Returns the `ps -ax -o jid,state,rss,pcpu,pid,comm` output.
+    fn processes(&self) -> Result<String, ExporterError>;
+}
+
+/// Gets process information by running `ps(1)`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PsCommand;
+
+impl ProcessSource for PsCommand {
+    fn processes(&self) -> Result<String, ExporterError> {
+        command::output(PS_COMMAND, &["-ax", "-o", "jid,state,rss,pcpu,pid,comm"])
+    }
+}
+
+/// The state of a process, simplified from the `ps(1)` state codes.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum ProcessState {
+    /// Runnable (`R`).
+    Running,
+
+    /// Sleeping (`S`) or idle (`I`).
+    Sleeping,
+
+    /// Stopped (`T`).
+    Stopped,
+
+    /// Waiting on disk (`D`) or a lock (`L`).
+    Waiting,
+
+    /// Zombie (`Z`).
+    Zombie,
+}
+
+impl ProcessState {
+    /// Every process state, in the order that they are reported.
+    pub const ALL: [Self; 5] = [
+        Self::Running,
+        Self::Sleeping,
+        Self::Stopped,
+        Self::Waiting,
+        Self::Zombie,
+    ];
+
+    /// Returns the state from the first character of a `ps(1)` state.
+    pub fn from_stat(stat: &str) -> Option<Self> {
+        match stat.chars().next()? {
+            'R'       => Some(Self::Running),
+            'S' | 'I' => Some(Self::Sleeping),
+            'T'       => Some(Self::Stopped),
+            'D' | 'L' => Some(Self::Waiting),
+            'Z'       => Some(Self::Zombie),
+            _         => None,
+        }
+    }
+
+    /// Returns the label value for the state.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Running  => "running",
+            Self::Sleeping => "sleeping",
+            Self::Stopped  => "stopped",
+            Self::Waiting  => "waiting",
+            Self::Zombie   => "zombie",
+        }
+    }
+}
+
+/// A single process.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Process {
+    /// ID of the jail the process is running in.
+    pub jid: i32,
+
+    /// State of the process.
+    pub state: Option<ProcessState>,
+
+    /// Resident set size, in bytes.
+    pub rss: u64,
+
+    /// CPU usage, in percents of a single CPU core.
+    pub pcpu: f64,
+
+    /// Process ID.
+    pub pid: i32,
+
+    /// Command name.
+    pub command: String,
+}
+
+/// Parses the output of `ps -ax -o jid,state,rss,pcpu,pid,comm`. Lines that
+/// can't be parsed are skipped.
+pub fn parse_ps(output: &str) -> Vec<Process> {
+    output
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            if fields.len() < 6 {
+                debug!("Skipping unparsable ps output: {}", line);
+                return None;
+            }
+
+            Some(Process {
+                jid:     fields[0].parse().ok()?,
+                state:   ProcessState::from_stat(fields[1]),
+                rss:     fields[2].parse::<u64>().ok()? * 1024,
+                pcpu:    fields[3].parse().ok()?,
+                pid:     fields[4].parse().ok()?,
+                command: fields[5..].join(" "),
+            })
+        })
+        .collect()
+}
+
+/// Returns up to `n` processes, ordered by the given comparison. Ties are
+/// broken on the process ID, so the result is stable between scrapes.
+pub fn top_n<'a, F>(processes: &[&'a Process], n: usize, cmp: F) -> Vec<&'a Process>
+where F: Fn(&Process, &Process) -> Ordering {
+    let mut sorted = processes.to_vec();
+    sorted.sort_by(|a, b| cmp(b, a).then(a.pid.cmp(&b.pid)));
+    sorted.truncate(n);
+    sorted
+}
+
+/// Process table time series.
+pub struct ProcessMetrics {
+    by_state:      Family<JailLabels, Gauge>,
+    top_rss:       Family<JailLabels, Gauge>,
+    top_rss_info:  Family<JailLabels, Gauge>,
+    top_pcpu:      Family<JailLabels, Gauge<f64, AtomicU64>>,
+    top_pcpu_info: Family<JailLabels, Gauge>,
+
+    // Number of processes reported by RSS and CPU for each jail.
+    top_n: usize,
+
+    // Where the process information comes from.
+    source: Box<dyn ProcessSource>,
+}
+
+impl ProcessMetrics {
+    /// Registers the process metrics with the given registry, getting
+    /// information from the given source.
+    pub fn new(
+        registry: &mut Registry,
+        top_n: u8,
+        source: Box<dyn ProcessSource>,
+    ) -> Self {
+        let by_state = register_gauge_with_registry!(
+            "processes",
+            "number of processes in the jail, by state",
+            JailLabels,
+            registry,
+        );
+
+        let registry = registry.sub_registry_with_prefix("processes");
+
+        let top_pcpu = Family::<JailLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "top_pcpu",
+            "%CPU of the jail's top processes by CPU, in percents of a \
+             single CPU core",
+            top_pcpu.clone(),
+        );
+
+        Self {
+            by_state: by_state,
+
+            top_rss: register_gauge_with_registry!(
+                "top_rss",
+                "resident set size of the jail's top processes by memory, \
+                 in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            top_rss_info: register_gauge_with_registry!(
+                "top_rss_info",
+                "command of each of the jail's top processes by memory, \
+                 always 1",
+                JailLabels,
+                registry,
+            ),
+
+            top_pcpu_info: register_gauge_with_registry!(
+                "top_pcpu_info",
+                "command of each of the jail's top processes by CPU, always 1",
+                JailLabels,
+                registry,
+            ),
+
+            top_pcpu: top_pcpu,
+            top_n:    usize::from(top_n.min(MAX_TOP_N)),
+            source:   source,
+        }
+    }
+
+    /// Sets the process metrics of the given jails. Processes are collected
+    /// afresh on each scrape, so any existing time series are removed first.
+    pub fn collect(&self, jails: &[ScrapedJail]) -> Result<(), ExporterError> {
+        debug!("Collecting process metrics");
+
+        self.clear();
+
+        let processes = parse_ps(&self.source.processes()?);
+
+        let mut by_jail: HashMap<i32, Vec<&Process>> = HashMap::new();
+        for process in &processes {
+            by_jail.entry(process.jid).or_default().push(process);
+        }
+
+        for jail in jails {
+            let processes = by_jail.get(&jail.jid)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            self.set(&jail.labels, processes);
+        }
+
+        Ok(())
+    }
+
+    // Sets the time series for a single jail.
+    fn set(&self, labels: &JailLabels, processes: &[&Process]) {
+        // Every state is reported, so that zero counts are visible.
+        for state in ProcessState::ALL {
+            let count = processes
+                .iter()
+                .filter(|p| p.state == Some(state))
+                .count();
+
+            let mut labels = labels.clone();
+            labels.push("state", state.as_str());
+
+            self.by_state
+                .get_or_create(&labels)
+                .set(i64::try_from(count).unwrap_or(i64::MAX));
+        }
+
+        let by_rss = top_n(processes, self.top_n, |a, b| a.rss.cmp(&b.rss));
+        for (rank, process) in by_rss.iter().enumerate() {
+            let labels = rank_labels(labels, rank);
+
+            self.top_rss
+                .get_or_create(&labels)
+                .set(i64::try_from(process.rss).unwrap_or(i64::MAX));
+            self.top_rss_info
+                .get_or_create(&info_labels(&labels, process))
+                .set(1);
+        }
+
+        let by_pcpu = top_n(processes, self.top_n, |a, b| a.pcpu.total_cmp(&b.pcpu));
+        for (rank, process) in by_pcpu.iter().enumerate() {
+            let labels = rank_labels(labels, rank);
+
+            self.top_pcpu.get_or_create(&labels).set(process.pcpu);
+            self.top_pcpu_info
+                .get_or_create(&info_labels(&labels, process))
+                .set(1);
+        }
+    }
+
+    // Removes all process time series.
+    fn clear(&self) {
+        self.by_state.clear();
+        self.top_rss.clear();
+        self.top_rss_info.clear();
+        self.top_pcpu.clear();
+        self.top_pcpu_info.clear();
+    }
+}
+
+// Returns the labels for a top process, identified only by its rank. The
+// process ID isn't a label, as it would create a new time series every time a
+// process restarts.
+fn rank_labels(labels: &JailLabels, rank: usize) -> JailLabels {
+    let mut labels = labels.clone();
+    labels.push("rank", &(rank + 1).to_string());
+    labels
+}
+
+// Returns the labels for the info series naming the command of a top
+// process. Every command a jail runs creates a new series, so the command is
+// kept off the value series and these are removed on each scrape.
+fn info_labels(labels: &JailLabels, process: &Process) -> JailLabels {
+    let mut labels = labels.clone();
+    labels.push("command", &process.command);
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing::scraped;
+    use crate::exporter::registry::encode;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    // Source returning captured command output.
+    struct FixtureSource;
+
+    impl ProcessSource for FixtureSource {
+        fn processes(&self) -> Result<String, ExporterError> {
+            Ok(fs::read_to_string("test-data/ps_jid.txt")?)
+        }
+    }
+
+    #[test]
+    fn parse_ps_ok() {
+        let output = fs::read_to_string("test-data/ps_jid.txt").unwrap();
+        let processes = parse_ps(&output);
+
+        assert_eq!(processes.len(), 9);
+        assert_eq!(processes[4], Process {
+            jid:     1,
+            state:   Some(ProcessState::Sleeping),
+            rss:     204_800 * 1024,
+            pcpu:    12.5,
+            pid:     2001,
+            command: "postgres".into(),
+        });
+    }
+
+    #[test]
+    fn process_state_from_stat() {
+        assert_eq!(ProcessState::from_stat("Ss"), Some(ProcessState::Sleeping));
+        assert_eq!(ProcessState::from_stat("I"), Some(ProcessState::Sleeping));
+        assert_eq!(ProcessState::from_stat("R+"), Some(ProcessState::Running));
+        assert_eq!(ProcessState::from_stat("Z"), Some(ProcessState::Zombie));
+        assert_eq!(ProcessState::from_stat("T"), Some(ProcessState::Stopped));
+        assert_eq!(ProcessState::from_stat("DL"), Some(ProcessState::Waiting));
+        assert_eq!(ProcessState::from_stat("W"), None);
+    }
+
+    #[test]
+    fn top_n_ties_broken_by_pid() {
+        let output = fs::read_to_string("test-data/ps_jid.txt").unwrap();
+        let processes = parse_ps(&output);
+        let jail: Vec<&Process> = processes.iter().filter(|p| p.jid == 1).collect();
+
+        let top: Vec<i32> = top_n(&jail, 3, |a, b| a.rss.cmp(&b.rss))
+            .iter()
+            .map(|p| p.pid)
+            .collect();
+
+        assert_eq!(top, vec![2001, 2003, 2004]);
+    }
+
+    #[test]
+    fn collect_fixture() {
+        let mut registry = Registry::default();
+        let metrics = ProcessMetrics::new(&mut registry, 2, Box::new(FixtureSource));
+
+        metrics.collect(&[scraped(1, "db"), scraped(3, "idle")]).unwrap();
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        assert!(buffer.contains("processes{name=\"db\",state=\"zombie\"} 2"));
+        assert!(buffer.contains("processes{name=\"db\",state=\"running\"} 1"));
+        assert!(buffer.contains("processes{name=\"idle\",state=\"zombie\"} 0"));
+        assert!(buffer.contains(
+            "processes_top_rss_bytes{name=\"db\",rank=\"1\"} 209715200"
+        ));
+        assert!(buffer.contains(
+            "processes_top_rss_info{name=\"db\",rank=\"1\",command=\"postgres\"} 1"
+        ));
+        assert!(buffer.contains(
+            "processes_top_pcpu{name=\"db\",rank=\"1\"} 50.0"
+        ));
+        assert!(buffer.contains(
+            "processes_top_pcpu_info{name=\"db\",rank=\"1\",command=\"postgres\"} 1"
+        ));
+
+        // Capped at the top 2 for each jail.
+        assert!(!buffer.contains("rank=\"3\""));
+
+        // Processes of jails that weren't scraped aren't reported.
+        assert!(!buffer.contains("sshd"));
+    }
+
+    #[test]
+    fn top_n_capped() {
+        let mut registry = Registry::default();
+        let metrics = ProcessMetrics::new(&mut registry, 100, Box::new(FixtureSource));
+
+        assert_eq!(metrics.top_n, usize::from(MAX_TOP_N));
+    }
+}
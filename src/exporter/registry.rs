@@ -0,0 +1,706 @@
+// registry: The registry that the exporter's metrics are registered with.
+//
+// prometheus_client only encodes its metrics as OpenMetrics text, and doesn't
+// let the values of families or histograms be read back. Metrics registered
+// here are kept readable, so that the other exposition formats can be
+// encoded straight from their values, and are handed to prometheus_client
+// for the OpenMetrics text.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use parking_lot::{
+    MappedRwLockReadGuard,
+    RwLock,
+    RwLockReadGuard,
+    RwLockWriteGuard,
+};
+use prometheus_client::encoding::{
+    EncodeLabelSet,
+    EncodeMetric,
+    MetricEncoder,
+};
+use prometheus_client::metrics::{
+    counter::Counter,
+    gauge::Gauge,
+    MetricType,
+    TypedMetric,
+};
+use prometheus_client::registry::Unit;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::iter;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+
+/// A label set whose label names and values can be read back.
+pub trait LabelPairs {
+    /// Returns the labels, in the order that they're encoded.
+    fn label_pairs(&self) -> Vec<(String, String)>;
+}
+
+/// The value of a counter or gauge, as the kind of number it is kept as.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Number {
+    /// A whole number, such as rctl usage.
+    Int(i64),
+
+    /// A floating point number, such as a ratio.
+    Float(f64),
+}
+
+impl Number {
+    /// Returns the value as a floating point number.
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Self::Int(value)   => value as f64,
+            Self::Float(value) => value,
+        }
+    }
+}
+
+/// The value of a single time series.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    /// The value of a counter.
+    Counter(Number),
+
+    /// The value of a gauge.
+    Gauge(Number),
+
+    /// An info metric, whose value is always 1.
+    Info,
+
+    /// The observations of a histogram.
+    Histogram {
+        /// The sum of the observations.
+        sum: f64,
+
+        /// The number of observations.
+        count: u64,
+
+        /// The upper bound of each bucket, ending with `+Inf`, and the
+        /// number of observations less than or equal to it.
+        buckets: Vec<(f64, u64)>,
+    },
+}
+
+/// A single time series of a metric family.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Series {
+    /// The labels of the time series.
+    pub labels: Vec<(String, String)>,
+
+    /// The value of the time series.
+    pub value: Value,
+}
+
+/// A metric whose time series can be read back.
+pub trait Snapshot {
+    /// Returns the current value of each of the metric's time series.
+    fn snapshot(&self) -> Vec<Series>;
+}
+
+/// A metric that can be registered with the [`Registry`].
+pub trait Metric: EncodeMetric + Snapshot + Send + Sync + fmt::Debug + 'static {}
+
+impl<T> Metric for T
+where T: EncodeMetric + Snapshot + Send + Sync + fmt::Debug + 'static {}
+
+/// The time series of a registered metric family, at the time it was read.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FamilySnapshot {
+    /// The family name as in the OpenMetrics format, with its prefix and
+    /// unit.
+    pub name: String,
+
+    /// The description of the family.
+    pub help: String,
+
+    /// The OpenMetrics type of the family, such as `counter`.
+    pub metric_type: String,
+
+    /// The unit of the family, if it has one.
+    pub unit: Option<String>,
+
+    /// The time series of the family, each carrying the registry labels.
+    pub series: Vec<Series>,
+}
+
+// A metric registered under its full name.
+#[derive(Debug)]
+struct Registration {
+    name:   String,
+    help:   String,
+    unit:   Option<Unit>,
+    metric: Arc<dyn Metric>,
+}
+
+// A handle to a registered metric, as registered with prometheus_client.
+#[derive(Debug)]
+struct Registered(Arc<dyn Metric>);
+
+impl EncodeMetric for Registered {
+    fn encode(&self, encoder: MetricEncoder) -> Result<(), fmt::Error> {
+        self.0.encode(encoder)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        self.0.metric_type()
+    }
+}
+
+/// A registry of metrics, with the same interface as `prometheus_client`'s
+/// registry.
+#[derive(Debug, Default)]
+pub struct Registry {
+    prefix:         Option<String>,
+    labels:         Vec<(String, String)>,
+    metrics:        Vec<Registration>,
+    sub_registries: Vec<Registry>,
+}
+
+impl Registry {
+    /// Returns a registry which prefixes the names of its metrics, and
+    /// attaches the given labels to all of their time series.
+    pub fn with_prefix_and_labels(
+        prefix: impl Into<String>,
+        labels: impl Iterator<Item = (Cow<'static, str>, Cow<'static, str>)>,
+    ) -> Self {
+        Self {
+            prefix: Some(prefix.into()),
+            labels: labels
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Registers a metric.
+    pub fn register<N, H>(&mut self, name: N, help: H, metric: impl Metric)
+    where N: Into<String>,
+          H: Into<String>,
+    {
+        self.register_metric(name.into(), help.into(), None, metric);
+    }
+
+    /// Registers a metric with the given unit, which is appended to its name.
+    pub fn register_with_unit<N, H>(
+        &mut self,
+        name: N,
+        help: H,
+        unit: Unit,
+        metric: impl Metric,
+    )
+    where N: Into<String>,
+          H: Into<String>,
+    {
+        self.register_metric(name.into(), help.into(), Some(unit), metric);
+    }
+
+    /// Returns a sub-registry, whose metric names are prefixed with the
+    /// given prefix after the prefix of this registry.
+    pub fn sub_registry_with_prefix<P: AsRef<str>>(&mut self, prefix: P) -> &mut Self {
+        let prefix = match &self.prefix {
+            Some(parent) => format!("{parent}_{}", prefix.as_ref()),
+            None         => prefix.as_ref().to_string(),
+        };
+
+        self.sub_registries.push(Self {
+            prefix: Some(prefix),
+            labels: self.labels.clone(),
+            ..Default::default()
+        });
+
+        self.sub_registries
+            .last_mut()
+            .expect("sub-registry was just added")
+    }
+
+    /// Returns the current time series of every registered family, in the
+    /// order that they're encoded as OpenMetrics.
+    pub fn snapshot(&self) -> Vec<FamilySnapshot> {
+        self.registrations()
+            .map(|registration| {
+                let series = registration.metric
+                    .snapshot()
+                    .into_iter()
+                    .map(|series| {
+                        let labels = self.labels
+                            .iter()
+                            .cloned()
+                            .chain(series.labels)
+                            .collect();
+
+                        Series {
+                            labels: labels,
+                            value:  series.value,
+                        }
+                    })
+                    .collect();
+
+                let unit = registration.unit
+                    .as_ref()
+                    .map(|unit| unit.as_str().to_string());
+
+                let name = match &unit {
+                    Some(unit) => format!("{}_{unit}", registration.name),
+                    None       => registration.name.clone(),
+                };
+
+                let metric_type = registration.metric.metric_type();
+
+                FamilySnapshot {
+                    name:        name,
+                    help:        registration.help.clone(),
+                    metric_type: metric_type.as_str().to_string(),
+                    unit:        unit,
+                    series:      series,
+                }
+            })
+            .collect()
+    }
+
+    // Registers a metric under its full name. The help is finished with a
+    // full stop, as prometheus_client does.
+    fn register_metric(
+        &mut self,
+        name: String,
+        help: String,
+        unit: Option<Unit>,
+        metric: impl Metric,
+    ) {
+        let name = match &self.prefix {
+            Some(prefix) => format!("{prefix}_{name}"),
+            None         => name,
+        };
+
+        self.metrics.push(Registration {
+            name:   name,
+            help:   help + ".",
+            unit:   unit,
+            metric: Arc::new(metric),
+        });
+    }
+
+    // Returns the metrics of this registry followed by those of its
+    // sub-registries, which is the order prometheus_client encodes them in.
+    fn registrations(&self) -> Box<dyn Iterator<Item = &Registration> + '_> {
+        let sub_registries = self.sub_registries
+            .iter()
+            .flat_map(Self::registrations);
+
+        Box::new(self.metrics.iter().chain(sub_registries))
+    }
+}
+
+/// Encodes the metrics of the registry as OpenMetrics text.
+pub fn encode<W: fmt::Write>(writer: &mut W, registry: &Registry) -> fmt::Result {
+    let labels = registry.labels
+        .iter()
+        .map(|(name, value)| (Cow::Owned(name.clone()), Cow::Owned(value.clone())));
+
+    let mut encoded = prometheus_client::registry::Registry::with_labels(labels);
+
+    // The names are already prefixed, and the help already finished, so
+    // the trailing full stop that prometheus_client adds is taken off.
+    for registration in registry.registrations() {
+        let help = registration.help
+            .strip_suffix('.')
+            .unwrap_or(&registration.help);
+
+        let metric = Registered(Arc::clone(&registration.metric));
+
+        match &registration.unit {
+            Some(unit) => {
+                encoded.register_with_unit(
+                    registration.name.as_str(),
+                    help,
+                    unit.clone(),
+                    metric,
+                );
+            },
+            None => encoded.register(registration.name.as_str(), help, metric),
+        }
+    }
+
+    prometheus_client::encoding::text::encode(writer, &encoded)
+}
+
+/// A family of metrics, one for each label set, like `prometheus_client`'s
+/// family.
+#[derive(Debug)]
+pub struct Family<S, M> {
+    metrics: Arc<RwLock<HashMap<S, M>>>,
+}
+
+impl<S, M> Clone for Family<S, M> {
+    fn clone(&self) -> Self {
+        Self {
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+}
+
+impl<S, M> Default for Family<S, M> {
+    fn default() -> Self {
+        Self {
+            metrics: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl<S: Clone + Hash + Eq, M: Default> Family<S, M> {
+    /// Returns the metric for the label set, creating it if needed.
+    pub fn get_or_create(&self, label_set: &S) -> MappedRwLockReadGuard<'_, M> {
+        // The read lock is released before taking the write lock, if the
+        // metric doesn't exist yet.
+        if let Ok(metric) = RwLockReadGuard::try_map(self.metrics.read(), |metrics| {
+            metrics.get(label_set)
+        }) {
+            return metric;
+        }
+
+        let mut metrics = self.metrics.write();
+
+        metrics
+            .entry(label_set.clone())
+            .or_default();
+
+        RwLockReadGuard::map(RwLockWriteGuard::downgrade(metrics), |metrics| {
+            metrics
+                .get(label_set)
+                .expect("metric was just created")
+        })
+    }
+
+    /// Removes the metric for the label set, returning true if there was
+    /// one.
+    pub fn remove(&self, label_set: &S) -> bool {
+        self.metrics.write().remove(label_set).is_some()
+    }
+
+    /// Removes the metrics for all label sets.
+    pub fn clear(&self) {
+        self.metrics.write().clear();
+    }
+}
+
+impl<S, M: TypedMetric> TypedMetric for Family<S, M> {
+    const TYPE: MetricType = M::TYPE;
+}
+
+impl<S, M> EncodeMetric for Family<S, M>
+where S: Clone + Hash + Eq + EncodeLabelSet,
+      M: EncodeMetric + TypedMetric,
+{
+    fn encode(&self, mut encoder: MetricEncoder) -> Result<(), fmt::Error> {
+        for (label_set, metric) in self.metrics.read().iter() {
+            metric.encode(encoder.encode_family(label_set)?)?;
+        }
+
+        Ok(())
+    }
+
+    fn metric_type(&self) -> MetricType {
+        M::TYPE
+    }
+}
+
+impl<S: LabelPairs, M: Snapshot> Snapshot for Family<S, M> {
+    fn snapshot(&self) -> Vec<Series> {
+        let mut snapshot = Vec::new();
+
+        for (label_set, metric) in self.metrics.read().iter() {
+            for series in metric.snapshot() {
+                let labels = label_set
+                    .label_pairs()
+                    .into_iter()
+                    .chain(series.labels)
+                    .collect();
+
+                snapshot.push(Series {
+                    labels: labels,
+                    value:  series.value,
+                });
+            }
+        }
+
+        snapshot
+    }
+}
+
+/// A histogram, like `prometheus_client`'s histogram.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    inner: Arc<RwLock<HistogramInner>>,
+}
+
+#[derive(Debug)]
+struct HistogramInner {
+    sum:   f64,
+    count: u64,
+
+    // The upper bound of each bucket, with the number of observations in
+    // it. The last bucket is bounded by f64::MAX, which stands for +Inf.
+    buckets: Vec<(f64, u64)>,
+}
+
+impl Histogram {
+    /// Returns a histogram with the given bucket upper bounds, and a final
+    /// `+Inf` bucket.
+    pub fn new(buckets: impl Iterator<Item = f64>) -> Self {
+        let buckets = buckets
+            .chain(iter::once(f64::MAX))
+            .map(|upper_bound| (upper_bound, 0))
+            .collect();
+
+        let inner = HistogramInner {
+            sum:     0.0,
+            count:   0,
+            buckets: buckets,
+        };
+
+        Self {
+            inner: Arc::new(RwLock::new(inner)),
+        }
+    }
+
+    /// Observes the given value.
+    pub fn observe(&self, value: f64) {
+        let mut inner = self.inner.write();
+
+        inner.sum += value;
+        inner.count += 1;
+
+        let bucket = inner.buckets
+            .iter_mut()
+            .find(|(upper_bound, _)| *upper_bound >= value);
+
+        if let Some((_, count)) = bucket {
+            *count += 1;
+        }
+    }
+}
+
+impl TypedMetric for Histogram {
+    const TYPE: MetricType = MetricType::Histogram;
+}
+
+impl EncodeMetric for Histogram {
+    fn encode(&self, mut encoder: MetricEncoder) -> Result<(), fmt::Error> {
+        let inner = self.inner.read();
+
+        encoder.encode_histogram::<()>(inner.sum, inner.count, &inner.buckets, None)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        Self::TYPE
+    }
+}
+
+impl Snapshot for Histogram {
+    fn snapshot(&self) -> Vec<Series> {
+        let inner = self.inner.read();
+
+        // Buckets are read back with cumulative counts, as they're exposed.
+        let buckets = inner.buckets
+            .iter()
+            .scan(0, |cumulative, (upper_bound, count)| {
+                *cumulative += count;
+
+                let upper_bound = if *upper_bound == f64::MAX {
+                    f64::INFINITY
+                }
+                else {
+                    *upper_bound
+                };
+
+                Some((upper_bound, *cumulative))
+            })
+            .collect();
+
+        vec![Series {
+            labels: Vec::new(),
+            value:  Value::Histogram {
+                sum:     inner.sum,
+                count:   inner.count,
+                buckets: buckets,
+            },
+        }]
+    }
+}
+
+/// An info metric, like `prometheus_client`'s info metric.
+#[derive(Debug)]
+pub struct Info<S>(S);
+
+impl<S> Info<S> {
+    /// Returns an info metric with the given labels.
+    pub fn new(label_set: S) -> Self {
+        Self(label_set)
+    }
+}
+
+impl<S> TypedMetric for Info<S> {
+    const TYPE: MetricType = MetricType::Info;
+}
+
+impl<S: EncodeLabelSet + fmt::Debug> EncodeMetric for Info<S> {
+    fn encode(&self, mut encoder: MetricEncoder) -> Result<(), fmt::Error> {
+        encoder.encode_info(&self.0)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        Self::TYPE
+    }
+}
+
+impl<S: LabelPairs> Snapshot for Info<S> {
+    fn snapshot(&self) -> Vec<Series> {
+        vec![Series {
+            labels: self.0.label_pairs(),
+            value:  Value::Info,
+        }]
+    }
+}
+
+// A time series without labels.
+fn single(value: Value) -> Vec<Series> {
+    vec![Series {
+        labels: Vec::new(),
+        value:  value,
+    }]
+}
+
+impl Snapshot for Counter {
+    fn snapshot(&self) -> Vec<Series> {
+        let value = i64::try_from(self.get()).unwrap_or(i64::MAX);
+
+        single(Value::Counter(Number::Int(value)))
+    }
+}
+
+impl Snapshot for Gauge {
+    fn snapshot(&self) -> Vec<Series> {
+        single(Value::Gauge(Number::Int(self.get())))
+    }
+}
+
+impl Snapshot for Gauge<f64, AtomicU64> {
+    fn snapshot(&self) -> Vec<Series> {
+        single(Value::Gauge(Number::Float(self.get())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+    struct TestLabels {
+        state: String,
+    }
+
+    impl LabelPairs for TestLabels {
+        fn label_pairs(&self) -> Vec<(String, String)> {
+            vec![("state".into(), self.state.clone())]
+        }
+    }
+
+    fn labels() -> impl Iterator<Item = (Cow<'static, str>, Cow<'static, str>)> {
+        iter::once((Cow::Borrowed("dc"), Cow::Borrowed("ams1")))
+    }
+
+    // The registry encodes the same OpenMetrics text as prometheus_client's
+    // registry would.
+    #[test]
+    fn encode_matches_prometheus_client() {
+        let mut registry = Registry::with_prefix_and_labels("jail", labels());
+        let mut expected = prometheus_client::registry::Registry::with_prefix_and_labels(
+            "jail",
+            labels(),
+        );
+
+        let family = Family::<TestLabels, Gauge>::default();
+        let upstream = prometheus_client::metrics::family::Family::<TestLabels, Gauge>::default();
+        let counter = Counter::default();
+
+        registry.register("procs", "Processes", family.clone());
+        expected.register("procs", "Processes", upstream.clone());
+
+        let sub = registry.sub_registry_with_prefix("exporter");
+        sub.register_with_unit("cpu", "CPU time", Unit::Seconds, counter.clone());
+
+        let sub = expected.sub_registry_with_prefix("exporter");
+        sub.register_with_unit("cpu", "CPU time", Unit::Seconds, counter.clone());
+
+        let labels = TestLabels { state: "running".into() };
+        family.get_or_create(&labels).set(3);
+        upstream.get_or_create(&labels).set(3);
+        counter.inc_by(2);
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        let mut expected_buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut expected_buffer, &expected)
+            .unwrap();
+
+        assert_eq!(buffer, expected_buffer);
+    }
+
+    #[test]
+    fn snapshot_ok() {
+        let mut registry = Registry::with_prefix_and_labels("jail", labels());
+        let family = Family::<TestLabels, Gauge>::default();
+        let histogram = Histogram::new([0.1, 1.0].into_iter());
+
+        registry.register("procs", "Processes", family.clone());
+        registry
+            .sub_registry_with_prefix("exporter")
+            .register_with_unit("duration", "Duration", Unit::Seconds, histogram.clone());
+
+        family.get_or_create(&TestLabels { state: "running".into() }).set(3);
+        histogram.observe(0.25);
+        histogram.observe(0.5);
+        histogram.observe(4.0);
+
+        let snapshot = registry.snapshot();
+
+        assert_eq!(snapshot, vec![
+            FamilySnapshot {
+                name:        "jail_procs".into(),
+                help:        "Processes.".into(),
+                metric_type: "gauge".into(),
+                unit:        None,
+                series:      vec![Series {
+                    labels: vec![
+                        ("dc".into(), "ams1".into()),
+                        ("state".into(), "running".into()),
+                    ],
+                    value:  Value::Gauge(Number::Int(3)),
+                }],
+            },
+            FamilySnapshot {
+                name:        "jail_exporter_duration_seconds".into(),
+                help:        "Duration.".into(),
+                metric_type: "histogram".into(),
+                unit:        Some("seconds".into()),
+                series:      vec![Series {
+                    labels: vec![("dc".into(), "ams1".into())],
+                    value:  Value::Histogram {
+                        sum:     4.75,
+                        count:   3,
+                        buckets: vec![
+                            (0.1, 0),
+                            (1.0, 2),
+                            (f64::INFINITY, 3),
+                        ],
+                    },
+                }],
+            },
+        ]);
+    }
+}
@@ -0,0 +1,228 @@
+// resources: Describes how each rctl resource is exported.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use prometheus_client::registry::Unit;
+use rctl::Resource;
+use super::METRIC_PREFIX;
+
+#[cfg(feature = "otlp")]
+use crate::collectors::RESOURCES;
+
+/// Describes the time series registered for an rctl resource.
+pub struct ResourceMetadata {
+    /// Metric name, before the registry prefix and unit are applied.
+    pub name: &'static str,
+
+    /// Metric help text.
+    pub help: &'static str,
+
+    /// Unit of the metric, if it has one.
+    pub unit: Option<Unit>,
+
+    /// Resources that only ever increase are exported as counters, the rest
+    /// are gauges.
+    pub counter: bool,
+}
+
+impl ResourceMetadata {
+    const fn counter(
+        name: &'static str,
+        help: &'static str,
+        unit: Option<Unit>,
+    ) -> Self {
+        Self {
+            name:    name,
+            help:    help,
+            unit:    unit,
+            counter: true,
+        }
+    }
+
+    const fn gauge(
+        name: &'static str,
+        help: &'static str,
+        unit: Option<Unit>,
+    ) -> Self {
+        Self {
+            name:    name,
+            help:    help,
+            unit:    unit,
+            counter: false,
+        }
+    }
+}
+
+/// Returns the metadata for the given resource.
+// Descriptions of these metrics are taken from rctl(8) where possible.
+pub fn metadata(resource: &Resource) -> ResourceMetadata {
+    match resource {
+        Resource::CoreDumpSize => ResourceMetadata::gauge(
+            "coredumpsize",
+            "core dump size, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::CpuTime => ResourceMetadata::counter(
+            "cputime",
+            "CPU time, in seconds",
+            Some(Unit::Seconds),
+        ),
+        Resource::DataSize => ResourceMetadata::gauge(
+            "datasize",
+            "data size, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::MaxProcesses => ResourceMetadata::gauge(
+            "maxproc",
+            "number of processes",
+            None,
+        ),
+        Resource::MemoryLocked => ResourceMetadata::gauge(
+            "memorylocked",
+            "locked memory, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::MemoryUse => ResourceMetadata::gauge(
+            "memoryuse",
+            "resident set size, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::MsgqQueued => ResourceMetadata::gauge(
+            "msgqqueued",
+            "number of queued SysV messages",
+            None,
+        ),
+        Resource::MsgqSize => ResourceMetadata::gauge(
+            "msgqsize",
+            "SysV message queue size, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::NMsgq => ResourceMetadata::gauge(
+            "nmsgq",
+            "number of SysV message queues",
+            None,
+        ),
+        Resource::Nsem => ResourceMetadata::gauge(
+            "nsem",
+            "number of SysV semaphores",
+            None,
+        ),
+        Resource::NSemop => ResourceMetadata::gauge(
+            "nsemop",
+            "number of SysV semaphores modified in a single semop(2) call",
+            None,
+        ),
+        Resource::NShm => ResourceMetadata::gauge(
+            "nshm",
+            "number of SysV shared memory segments",
+            None,
+        ),
+        Resource::NThreads => ResourceMetadata::gauge(
+            "nthr",
+            "number of threads",
+            None,
+        ),
+        Resource::OpenFiles => ResourceMetadata::gauge(
+            "openfiles",
+            "file descriptor table size",
+            None,
+        ),
+        Resource::PercentCpu => ResourceMetadata::gauge(
+            "pcpu_used",
+            "%CPU, in percents of a single CPU core",
+            None,
+        ),
+        Resource::PseudoTerminals => ResourceMetadata::gauge(
+            "pseudoterminals",
+            "number of PTYs",
+            None,
+        ),
+        Resource::ReadBps => ResourceMetadata::gauge(
+            "readbps",
+            "filesystem reads, in bytes per second",
+            None,
+        ),
+        Resource::ReadIops => ResourceMetadata::gauge(
+            "readiops",
+            "filesystem reads, in operations per second",
+            None,
+        ),
+        Resource::ShmSize => ResourceMetadata::gauge(
+            "shmsize",
+            "SysV shared memory size, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::StackSize => ResourceMetadata::gauge(
+            "stacksize",
+            "stack size, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::SwapUse => ResourceMetadata::gauge(
+            "swapuse",
+            "swap space that may be reserved or used, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::VMemoryUse => ResourceMetadata::gauge(
+            "vmemoryuse",
+            "address space limit, in bytes",
+            Some(Unit::Bytes),
+        ),
+        Resource::Wallclock => ResourceMetadata::counter(
+            "wallclock",
+            "wallclock time, in seconds",
+            Some(Unit::Seconds),
+        ),
+        Resource::WriteBps => ResourceMetadata::gauge(
+            "writebps",
+            "filesystem writes, in bytes per second",
+            None,
+        ),
+        Resource::WriteIops => ResourceMetadata::gauge(
+            "writeiops",
+            "filesystem writes, in operations per second",
+            None,
+        ),
+    }
+}
+
+/// Returns the name of the resource's metric family, as it appears in the
+/// OpenMetrics output.
+pub fn family_name(resource: &Resource) -> String {
+    let ResourceMetadata { name, unit, .. } = metadata(resource);
+
+    match unit {
+        Some(unit) => format!("{METRIC_PREFIX}_{name}_{}", unit.as_str()),
+        None       => format!("{METRIC_PREFIX}_{name}"),
+    }
+}
+
+/// Returns the names of the metric families of the resources exported as
+/// counters, such as `cputime`.
+#[cfg(feature = "otlp")]
+pub fn counter_family_names() -> Vec<String> {
+    RESOURCES
+        .iter()
+        .filter(|resource| metadata(resource).counter)
+        .map(family_name)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn family_name_ok() {
+        assert_eq!(family_name(&Resource::CpuTime), "jail_cputime_seconds");
+        assert_eq!(family_name(&Resource::MaxProcesses), "jail_maxproc");
+    }
+
+    #[cfg(feature = "otlp")]
+    #[test]
+    fn counter_family_names_ok() {
+        assert_eq!(counter_family_names(), vec![
+            "jail_cputime_seconds",
+            "jail_wallclock_seconds",
+        ]);
+    }
+}
@@ -0,0 +1,370 @@
+// selfmetrics: Metrics describing the jail_exporter process itself.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::{
+    register_counter_with_registry,
+    register_gauge_with_registry,
+    register_histogram_with_registry,
+};
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::metrics::{
+    counter::Counter,
+    gauge::Gauge,
+    histogram::exponential_buckets,
+};
+use prometheus_client::registry::Unit;
+use rctl::{
+    Resource,
+    Subject,
+};
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
+};
+use std::time::Duration;
+use sysctl::{
+    Ctl,
+    CtlValue,
+    Sysctl,
+};
+use tracing::debug;
+use super::registry::{
+    Family,
+    Histogram,
+    LabelPairs,
+    Registry,
+};
+
+// MIB of the kern.proc.filedesc sysctl. Followed by a PID, it returns a
+// kinfo_file record for each file descriptor of the process.
+const CTL_KERN_PROC_FILEDESC: [i32; 3] = [1, 14, 33];
+
+// Offsets of the kf_structsize and kf_fd fields of a kinfo_file record.
+const KF_STRUCTSIZE_OFFSET: usize = 0;
+const KF_FD_OFFSET: usize = 8;
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ScrapeErrorLabels {
+    // The stage of the scrape that failed.
+    r#type: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct HttpRequestLabels {
+    // Path of the request.
+    pub path: String,
+
+    // HTTP status code returned for the request.
+    pub status: String,
+}
+
+impl LabelPairs for ScrapeErrorLabels {
+    fn label_pairs(&self) -> Vec<(String, String)> {
+        vec![("type".into(), self.r#type.clone())]
+    }
+}
+
+impl LabelPairs for HttpRequestLabels {
+    fn label_pairs(&self) -> Vec<(String, String)> {
+        vec![
+            ("path".into(), self.path.clone()),
+            ("status".into(), self.status.clone()),
+        ]
+    }
+}
+
+/// Type alias for the HTTP request counters handed to the httpd.
+pub type HttpRequests = Family<HttpRequestLabels, Counter>;
+
+/// Time series describing the exporter itself, registered under the
+/// `exporter` prefix.
+pub struct SelfMetrics {
+    scrape_duration:          Gauge<f64, AtomicU64>,
+    scrapes:                  Counter,
+    scrape_errors:            Family<ScrapeErrorLabels, Counter>,
+    jail_collection_duration: Histogram,
+    jails_reaped:             Counter,
+    process_cpu:              Counter,
+    process_open_fds:         Gauge,
+    process_resident_memory:  Gauge,
+    http_requests:            HttpRequests,
+}
+
+impl SelfMetrics {
+    /// Registers the exporter metrics within a sub-registry of the given
+    /// registry.
+    pub fn new(registry: &mut Registry) -> Self {
+        let registry = registry.sub_registry_with_prefix("exporter");
+
+        // The macros can't tell a unit apart from a label set, so single
+        // metrics with a unit are registered by hand.
+        let scrape_duration = Gauge::<f64, AtomicU64>::default();
+        registry.register_with_unit(
+            "scrape_duration",
+            "time taken to collect jail metrics during the last scrape",
+            Unit::Seconds,
+            scrape_duration.clone(),
+        );
+
+        let process_cpu = Counter::default();
+        registry.register_with_unit(
+            "process_cpu",
+            "CPU time used by the exporter process",
+            Unit::Seconds,
+            process_cpu.clone(),
+        );
+
+        let process_resident_memory = Gauge::default();
+        registry.register_with_unit(
+            "process_resident_memory",
+            "resident set size of the exporter process",
+            Unit::Bytes,
+            process_resident_memory.clone(),
+        );
+
+        Self {
+            scrape_duration:         scrape_duration,
+            process_cpu:             process_cpu,
+            process_resident_memory: process_resident_memory,
+
+            scrapes: register_counter_with_registry!(
+                "scrapes",
+                "number of scrapes performed, including failed scrapes",
+                registry,
+            ),
+
+            scrape_errors: register_counter_with_registry!(
+                "scrape_errors",
+                "number of errors encountered while scraping, by type",
+                ScrapeErrorLabels,
+                registry,
+            ),
+
+            jail_collection_duration: register_histogram_with_registry!(
+                "jail_collection_duration",
+                "time taken to collect the metrics of a single jail",
+                exponential_buckets(0.0001, 2.0, 12),
+                Unit::Seconds,
+                registry,
+            ),
+
+            jails_reaped: register_counter_with_registry!(
+                "jails_reaped",
+                "number of dead jails whose time series were removed",
+                registry,
+            ),
+
+            process_open_fds: register_gauge_with_registry!(
+                "process_open_fds",
+                "number of open file descriptors of the exporter process",
+                registry,
+            ),
+
+            http_requests: register_counter_with_registry!(
+                "http_requests",
+                "number of HTTP requests handled, by path and status",
+                HttpRequestLabels,
+                registry,
+            ),
+        }
+    }
+
+    /// Returns a handle to the HTTP request counters.
+    pub fn http_requests(&self) -> HttpRequests {
+        self.http_requests.clone()
+    }
+
+    /// Records the time taken to collect the metrics of a single jail.
+    pub fn observe_jail_collection(&self, duration: Duration) {
+        self.jail_collection_duration.observe(duration.as_secs_f64());
+    }
+
+    /// Records a scrape, whether or not it succeeded.
+    pub fn observe_scrape(&self, duration: Duration) {
+        self.scrapes.inc();
+        self.scrape_duration.set(duration.as_secs_f64());
+    }
+
+    /// Records the number of jails reaped during a scrape.
+    pub fn reaped(&self, count: usize) {
+        self.jails_reaped.inc_by(count as u64);
+    }
+
+    /// Records an error of the given type.
+    pub fn scrape_error(&self, kind: &str) {
+        let labels = &ScrapeErrorLabels {
+            r#type: kind.to_string(),
+        };
+
+        self.scrape_errors.get_or_create(labels).inc();
+    }
+
+    /// Updates the process metrics using RACCT statistics for our own PID,
+    /// and the open file descriptors from its file descriptor table.
+    pub fn update_process(&self) {
+        debug!("Updating exporter process metrics");
+
+        let Ok(pid) = i32::try_from(std::process::id()) else {
+            return;
+        };
+
+        match open_fds(pid) {
+            Ok(count) => {
+                let count = i64::try_from(count).unwrap_or(i64::MAX);
+                self.process_open_fds.set(count);
+            },
+            Err(e) => {
+                debug!("Couldn't count open file descriptors: {}", e);
+                self.scrape_error("process");
+            },
+        }
+
+        let usage = match Subject::process_id(pid).usage() {
+            Ok(usage) => usage,
+            Err(e)    => {
+                // Failing to get our own usage shouldn't fail the scrape.
+                debug!("Couldn't get process RACCT statistics: {}", e);
+                self.scrape_error("process");
+                return;
+            },
+        };
+
+        for (resource, value) in usage {
+            let unsigned = value as u64;
+            let signed = i64::try_from(unsigned.clamp(0, i64::MAX as u64))
+                .expect("i64 from u64");
+
+            match resource {
+                Resource::CpuTime => {
+                    self.process_cpu
+                        .inner()
+                        .store(unsigned, Ordering::Relaxed);
+                },
+                Resource::MemoryUse => {
+                    self.process_resident_memory.set(signed);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+// Returns the number of open file descriptors of the process. racct's
+// openfiles is the size of the file descriptor table rather than the number
+// of descriptors in it, so the table is read with kern.proc.filedesc.
+fn open_fds(pid: i32) -> Result<usize, ExporterError> {
+    let mut oid = CTL_KERN_PROC_FILEDESC.to_vec();
+    oid.push(pid);
+
+    match Ctl::Oid(oid).value()? {
+        CtlValue::Node(records) | CtlValue::Struct(records) => {
+            Ok(count_open_fds(&records))
+        },
+        _ => Err(ExporterError::SysctlValueError("kern.proc.filedesc".into())),
+    }
+}
+
+// Counts the file descriptors in the packed kinfo_file records returned by
+// kern.proc.filedesc. Records for the working directory, root directory and
+// the like have negative descriptor numbers and aren't counted.
+fn count_open_fds(records: &[u8]) -> usize {
+    let mut count = 0;
+    let mut offset = 0;
+
+    while let Some(record) = records.get(offset..) {
+        let size = read_i32(record, KF_STRUCTSIZE_OFFSET)
+            .and_then(|size| usize::try_from(size).ok())
+            .filter(|size| *size > KF_FD_OFFSET);
+
+        let (Some(size), Some(fd)) = (size, read_i32(record, KF_FD_OFFSET)) else {
+            break;
+        };
+
+        if fd >= 0 {
+            count += 1;
+        }
+
+        offset += size;
+    }
+
+    count
+}
+
+// Reads a native endian i32 at the given offset.
+fn read_i32(bytes: &[u8], offset: usize) -> Option<i32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+
+    Some(i32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::registry::encode;
+    use std::iter;
+
+    #[test]
+    fn self_metrics_namespace() {
+        let mut registry = Registry::with_prefix_and_labels("jail", iter::empty());
+        let metrics = SelfMetrics::new(&mut registry);
+
+        metrics.observe_scrape(Duration::from_millis(250));
+        metrics.scrape_error("racct");
+        metrics.reaped(2);
+
+        let labels = HttpRequestLabels {
+            path:   "/metrics".into(),
+            status: "200".into(),
+        };
+        metrics.http_requests().get_or_create(&labels).inc();
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        let expected = [
+            "jail_exporter_scrape_duration_seconds 0.25",
+            "jail_exporter_scrapes_total 1",
+            "jail_exporter_scrape_errors_total{type=\"racct\"} 1",
+            "jail_exporter_jails_reaped_total 2",
+            "jail_exporter_http_requests_total{path=\"/metrics\",status=\"200\"} 1",
+        ];
+
+        for line in expected {
+            assert!(buffer.contains(line), "missing: {line}\n{buffer}");
+        }
+    }
+
+    // Returns a packed kinfo_file record for the descriptor.
+    fn record(fd: i32, size: i32) -> Vec<u8> {
+        let mut record = vec![0; size as usize];
+        record[0..4].copy_from_slice(&size.to_ne_bytes());
+        record[8..12].copy_from_slice(&fd.to_ne_bytes());
+
+        record
+    }
+
+    #[test]
+    fn count_open_fds_ok() {
+        // cwd, root and jail records, followed by three descriptors.
+        let records = [
+            record(-1, 152),
+            record(-2, 152),
+            record(-3, 152),
+            record(0, 160),
+            record(1, 160),
+            record(5, 184),
+        ].concat();
+
+        assert_eq!(count_open_fds(&records), 3);
+    }
+
+    #[test]
+    fn count_open_fds_truncated() {
+        let mut records = [record(0, 160), record(1, 160)].concat();
+        records.truncate(200);
+
+        assert_eq!(count_open_fds(&records), 2);
+        assert_eq!(count_open_fds(&[]), 0);
+    }
+}
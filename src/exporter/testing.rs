@@ -0,0 +1,116 @@
+// testing: Helpers shared by the tests of the exporter's collectors and
+// exposition formats.
+#![forbid(unsafe_code)]
+use prometheus_client::metrics::{
+    counter::Counter,
+    gauge::Gauge,
+};
+use prometheus_client::registry::Unit;
+use std::borrow::Cow;
+use std::iter;
+use super::{
+    JailLabels,
+    ScrapedJail,
+};
+use super::registry::{
+    Family,
+    Histogram,
+    Info,
+    Registry,
+};
+
+// Returns a scraped jail whose time series are keyed on its name.
+pub fn scraped(jid: i32, name: &str) -> ScrapedJail {
+    let mut labels = JailLabels::default();
+    labels.push("name", name);
+
+    ScrapedJail {
+        jid:    jid,
+        name:   name.to_string(),
+        labels: labels,
+    }
+}
+
+// Returns jail labels holding the given name and value pairs.
+pub fn labels(pairs: &[(&str, &str)]) -> JailLabels {
+    let mut labels = JailLabels::default();
+
+    for (name, value) in pairs {
+        labels.push(name, value);
+    }
+
+    labels
+}
+
+// Returns a registry holding two jails, web and db, along with metrics that
+// don't belong to a jail, which the exposition formats' golden files are
+// encoded from.
+pub fn golden_registry() -> Registry {
+    let static_labels = iter::once((Cow::Borrowed("env"), Cow::Borrowed("prod")));
+    let mut registry = Registry::with_prefix_and_labels("jail", static_labels);
+
+    let version = labels(&[("rustversion", "1.74.1"), ("version", "0.17.0")]);
+    let cputime = Family::<JailLabels, Counter>::default();
+    let memoryuse = Family::<JailLabels, Gauge>::default();
+    let processes = Family::<JailLabels, Gauge>::default();
+    let physmem = Gauge::<i64>::default();
+    let num = Gauge::<i64>::default();
+    let duration = Histogram::new(iter::once(0.1));
+
+    registry.register(
+        "exporter_build",
+        "A metric with constant '1' value labelled by version from \
+         which jail_exporter was built",
+        Info::new(version),
+    );
+    registry.register_with_unit(
+        "cputime",
+        "CPU time, in seconds",
+        Unit::Seconds,
+        cputime.clone(),
+    );
+    registry.register_with_unit(
+        "memoryuse",
+        "resident set size, in bytes",
+        Unit::Bytes,
+        memoryuse.clone(),
+    );
+    registry.register(
+        "processes",
+        "Number of processes in the jail, by state",
+        processes.clone(),
+    );
+    registry.sub_registry_with_prefix("host").register_with_unit(
+        "physmem",
+        "Physical memory of the host, in bytes",
+        Unit::Bytes,
+        physmem.clone(),
+    );
+    registry.register("num", "Current number of running jails", num.clone());
+    registry.sub_registry_with_prefix("exporter").register_with_unit(
+        "scrape_duration",
+        "Duration of scrapes, in seconds",
+        Unit::Seconds,
+        duration.clone(),
+    );
+
+    let web = [("name", "web"), ("parent", "host"), ("depth", "1")];
+    let db = [("name", r#"db "primary""#), ("depth", "0")];
+
+    cputime.get_or_create(&labels(&web)).inc_by(3600);
+    cputime.get_or_create(&labels(&db)).inc_by(86400);
+    memoryuse.get_or_create(&labels(&db)).set(1_073_741_824);
+    memoryuse.get_or_create(&labels(&web)).set(268_435_456);
+    physmem.set(17_179_869_184);
+    num.set(2);
+    duration.observe(0.05);
+
+    for (state, count) in [("sleeping", 12), ("running", 1)] {
+        let mut labels = labels(&web);
+        labels.push("state", state);
+
+        processes.get_or_create(&labels).set(count);
+    }
+
+    registry
+}
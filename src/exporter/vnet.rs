@@ -0,0 +1,505 @@
+// vnet: Network interface counters for VNET jails.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::register_counter_with_registry;
+use jail::RunningJail;
+use jail::param::Value;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::registry::Unit;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use tracing::debug;
+use super::command;
+use super::registry::{
+    Family,
+    Registry,
+};
+use super::{
+    JailLabels,
+    ScrapedJail,
+};
+
+// Commands used to gather interface statistics and descriptions.
+const NETSTAT_COMMAND: &str = "/usr/bin/netstat";
+const IFCONFIG_COMMAND: &str = "/sbin/ifconfig";
+
+// Value of the vnet jail parameter for jails with their own network stack.
+const JAIL_SYS_NEW: i32 = 1;
+
+/// A source of network interface information. This is abstracted so that the
+/// collector can be tested against captured command output.
+pub trait VnetSource: Send + Sync {
+    /// Returns the `netstat -ibdn` output from within the given jail's VNET.
+    fn jail_interfaces(&self, jid: i32) -> Result<String, ExporterError>;
+
+    /// Returns the `netstat -ibdn` output for the host.
+    fn host_interfaces(&self) -> Result<String, ExporterError>;
+
+    /// Returns the `ifconfig -a` output for the host.
+    fn host_descriptions(&self) -> Result<String, ExporterError>;
+}
+
+/// Gets interface information by running `netstat(1)` and `ifconfig(8)`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VnetCommand;
+
+impl VnetSource for VnetCommand {
+    fn jail_interfaces(&self, jid: i32) -> Result<String, ExporterError> {
+        let jid = jid.to_string();
+
+        command::output(NETSTAT_COMMAND, &["-j", &jid, "-ibdn"])
+    }
+
+    fn host_interfaces(&self) -> Result<String, ExporterError> {
+        command::output(NETSTAT_COMMAND, &["-ibdn"])
+    }
+
+    fn host_descriptions(&self) -> Result<String, ExporterError> {
+        command::output(IFCONFIG_COMMAND, &["-a"])
+    }
+}
+
+/// Counters of a single network interface.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceStats {
+    /// Bytes received.
+    pub rx_bytes: Option<u64>,
+
+    /// Packets received.
+    pub rx_packets: Option<u64>,
+
+    /// Input errors.
+    pub rx_errors: Option<u64>,
+
+    /// Input packets dropped.
+    pub rx_drops: Option<u64>,
+
+    /// Bytes sent.
+    pub tx_bytes: Option<u64>,
+
+    /// Packets sent.
+    pub tx_packets: Option<u64>,
+
+    /// Output errors.
+    pub tx_errors: Option<u64>,
+
+    /// Output packets dropped.
+    pub tx_drops: Option<u64>,
+}
+
+impl InterfaceStats {
+    /// Returns the stats with receive and transmit swapped. The host side of
+    /// an epair receives what the jail side transmits, and vice versa.
+    pub fn mirrored(&self) -> Self {
+        Self {
+            rx_bytes:   self.tx_bytes,
+            rx_packets: self.tx_packets,
+            rx_errors:  self.tx_errors,
+            rx_drops:   self.tx_drops,
+            tx_bytes:   self.rx_bytes,
+            tx_packets: self.rx_packets,
+            tx_errors:  self.rx_errors,
+            tx_drops:   self.rx_drops,
+        }
+    }
+}
+
+/// Parses the output of `netstat -ibdn`, returning the counters of each
+/// interface keyed on its name. Only the link level rows are used, the
+/// per-address rows are skipped.
+pub fn parse_netstat(output: &str) -> HashMap<String, InterfaceStats> {
+    let mut interfaces = HashMap::new();
+    let mut lines = output.lines();
+
+    let Some(header) = lines.next() else {
+        return interfaces;
+    };
+
+    // The counter columns follow the address column. The address can be
+    // empty, so counters are read from the right hand side of each row.
+    let header: Vec<&str> = header.split_whitespace().collect();
+    let Some(address) = header.iter().position(|c| *c == "Address") else {
+        debug!("Unexpected netstat header: {:?}", header);
+        return interfaces;
+    };
+    let columns = &header[address + 1..];
+
+    for line in lines {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        let is_link = fields.get(2).is_some_and(|f| f.starts_with("<Link#"));
+        if !is_link || fields.len() < columns.len() + 3 {
+            continue;
+        }
+
+        let values = &fields[fields.len() - columns.len()..];
+        let mut stats = InterfaceStats::default();
+
+        for (column, value) in columns.iter().zip(values) {
+            let value = value.parse::<u64>().ok();
+
+            match *column {
+                "Ipkts"  => stats.rx_packets = value,
+                "Ierrs"  => stats.rx_errors = value,
+                "Idrop"  => stats.rx_drops = value,
+                "Ibytes" => stats.rx_bytes = value,
+                "Opkts"  => stats.tx_packets = value,
+                "Oerrs"  => stats.tx_errors = value,
+                "Obytes" => stats.tx_bytes = value,
+                "Drop"   => stats.tx_drops = value,
+                _        => {},
+            }
+        }
+
+        interfaces.insert(fields[0].to_string(), stats);
+    }
+
+    interfaces
+}
+
+/// Parses the output of `ifconfig -a`, returning the description of each
+/// interface that has one.
+pub fn parse_descriptions(output: &str) -> HashMap<String, String> {
+    let mut descriptions = HashMap::new();
+    let mut interface = None;
+
+    for line in output.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            interface = line.split_once(':').map(|(name, _)| name.to_string());
+            continue;
+        }
+
+        let description = line.trim().strip_prefix("description: ");
+
+        if let (Some(interface), Some(description)) = (&interface, description) {
+            descriptions.insert(interface.clone(), description.to_string());
+        }
+    }
+
+    descriptions
+}
+
+/// Returns a boolean indicating if the host side interface belongs to the
+/// named jail. This is the case when the interface description mentions the
+/// jail, as set by iocage (`associated with jail: NAME`), or the interface
+/// name ends with the jail name, as named by bastille (`e0a_NAME`).
+pub fn is_host_side(interface: &str, description: Option<&str>, jail: &str) -> bool {
+    let described = description.is_some_and(|description| {
+        description
+            .split("jail:")
+            .skip(1)
+            .any(|rest| rest.split_whitespace().next() == Some(jail))
+    });
+
+    let named = interface
+        .strip_suffix(jail)
+        .is_some_and(|prefix| prefix.ends_with('_'));
+
+    described || named
+}
+
+/// Network interface time series, registered under the `network` prefix.
+pub struct VnetMetrics {
+    rx_bytes:   Family<JailLabels, Counter>,
+    rx_packets: Family<JailLabels, Counter>,
+    rx_errors:  Family<JailLabels, Counter>,
+    rx_drops:   Family<JailLabels, Counter>,
+    tx_bytes:   Family<JailLabels, Counter>,
+    tx_packets: Family<JailLabels, Counter>,
+    tx_errors:  Family<JailLabels, Counter>,
+    tx_drops:   Family<JailLabels, Counter>,
+
+    // Where the interface information comes from.
+    source: Box<dyn VnetSource>,
+}
+
+impl VnetMetrics {
+    /// Registers the network metrics within a sub-registry of the given
+    /// registry, getting information from the given source.
+    pub fn new(registry: &mut Registry, source: Box<dyn VnetSource>) -> Self {
+        let registry = registry.sub_registry_with_prefix("network");
+
+        Self {
+            rx_bytes: register_counter_with_registry!(
+                "receive",
+                "bytes received by the interface",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            rx_packets: register_counter_with_registry!(
+                "receive_packets",
+                "packets received by the interface",
+                JailLabels,
+                registry,
+            ),
+
+            rx_errors: register_counter_with_registry!(
+                "receive_errors",
+                "input errors on the interface",
+                JailLabels,
+                registry,
+            ),
+
+            rx_drops: register_counter_with_registry!(
+                "receive_drops",
+                "input packets dropped by the interface",
+                JailLabels,
+                registry,
+            ),
+
+            tx_bytes: register_counter_with_registry!(
+                "transmit",
+                "bytes sent by the interface",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            tx_packets: register_counter_with_registry!(
+                "transmit_packets",
+                "packets sent by the interface",
+                JailLabels,
+                registry,
+            ),
+
+            tx_errors: register_counter_with_registry!(
+                "transmit_errors",
+                "output errors on the interface",
+                JailLabels,
+                registry,
+            ),
+
+            tx_drops: register_counter_with_registry!(
+                "transmit_drops",
+                "output packets dropped by the interface",
+                JailLabels,
+                registry,
+            ),
+
+            source: source,
+        }
+    }
+
+    /// Sets the interface counters of the given jails that have their own
+    /// VNET. Interfaces are collected afresh on each scrape, so any existing
+    /// time series are removed first.
+    pub fn collect(&self, jails: &[ScrapedJail]) -> Result<(), ExporterError> {
+        debug!("Collecting VNET metrics");
+
+        let vnet_jails: Vec<&ScrapedJail> = jails
+            .iter()
+            .filter(|jail| {
+                let vnet = RunningJail::from_jid_unchecked(jail.jid).param("vnet");
+                matches!(vnet, Ok(Value::Int(JAIL_SYS_NEW)))
+            })
+            .collect();
+
+        self.collect_jails(&vnet_jails)
+    }
+
+    // Sets the interface counters of the given VNET jails.
+    fn collect_jails(&self, jails: &[&ScrapedJail]) -> Result<(), ExporterError> {
+        self.clear();
+
+        // Host side interfaces, only fetched if a jail needs them.
+        let mut host: Option<(HashMap<String, InterfaceStats>, HashMap<String, String>)> = None;
+
+        for jail in jails {
+            match self.source.jail_interfaces(jail.jid) {
+                Ok(output) => {
+                    for (interface, stats) in parse_netstat(&output) {
+                        self.set(&jail.labels, &interface, "jail", &stats);
+                    }
+                },
+                Err(e) => {
+                    // Fall back to the host side of the jail's interfaces.
+                    debug!("Couldn't get interfaces within jail {}: {}", jail.jid, e);
+
+                    if host.is_none() {
+                        let interfaces = parse_netstat(&self.source.host_interfaces()?);
+                        let descriptions = parse_descriptions(
+                            &self.source.host_descriptions()?,
+                        );
+
+                        host = Some((interfaces, descriptions));
+                    }
+
+                    let (interfaces, descriptions) = host.as_ref()
+                        .expect("host interfaces");
+
+                    let host_side = interfaces.iter().filter(|(interface, _)| {
+                        let description = descriptions
+                            .get(*interface)
+                            .map(String::as_str);
+
+                        is_host_side(interface, description, &jail.name)
+                    });
+
+                    for (interface, stats) in host_side {
+                        self.set(&jail.labels, interface, "host", &stats.mirrored());
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // Sets the time series for a single interface.
+    fn set(
+        &self,
+        labels: &JailLabels,
+        interface: &str,
+        view: &str,
+        stats: &InterfaceStats,
+    ) {
+        let mut labels = labels.clone();
+        labels.push("interface", interface);
+        labels.push("view", view);
+
+        let counters = [
+            (&self.rx_bytes, stats.rx_bytes),
+            (&self.rx_packets, stats.rx_packets),
+            (&self.rx_errors, stats.rx_errors),
+            (&self.rx_drops, stats.rx_drops),
+            (&self.tx_bytes, stats.tx_bytes),
+            (&self.tx_packets, stats.tx_packets),
+            (&self.tx_errors, stats.tx_errors),
+            (&self.tx_drops, stats.tx_drops),
+        ];
+
+        for (counter, value) in counters {
+            if let Some(value) = value {
+                counter
+                    .get_or_create(&labels)
+                    .inner()
+                    .store(value, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Removes all network time series.
+    fn clear(&self) {
+        self.rx_bytes.clear();
+        self.rx_packets.clear();
+        self.rx_errors.clear();
+        self.rx_drops.clear();
+        self.tx_bytes.clear();
+        self.tx_packets.clear();
+        self.tx_errors.clear();
+        self.tx_drops.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::testing::scraped;
+    use crate::exporter::registry::encode;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    // Source returning captured command output. Jail 2 has no accessible
+    // inside view.
+    struct FixtureSource;
+
+    impl VnetSource for FixtureSource {
+        fn jail_interfaces(&self, jid: i32) -> Result<String, ExporterError> {
+            if jid == 2 {
+                return Err(ExporterError::CommandError("netstat".into()));
+            }
+
+            Ok(fs::read_to_string("test-data/netstat_ibdn_jail.txt")?)
+        }
+
+        fn host_interfaces(&self) -> Result<String, ExporterError> {
+            Ok(fs::read_to_string("test-data/netstat_ibdn_host.txt")?)
+        }
+
+        fn host_descriptions(&self) -> Result<String, ExporterError> {
+            Ok(fs::read_to_string("test-data/ifconfig_a.txt")?)
+        }
+    }
+
+    #[test]
+    fn parse_netstat_ok() {
+        let output = fs::read_to_string("test-data/netstat_ibdn_jail.txt")
+            .unwrap();
+        let interfaces = parse_netstat(&output);
+
+        assert_eq!(interfaces.len(), 2);
+        assert_eq!(interfaces["epair0b"], InterfaceStats {
+            rx_bytes:   Some(567_890),
+            rx_packets: Some(1234),
+            rx_errors:  Some(1),
+            rx_drops:   Some(2),
+            tx_bytes:   Some(123_456),
+            tx_packets: Some(987),
+            tx_errors:  Some(3),
+            tx_drops:   Some(4),
+        });
+    }
+
+    #[test]
+    fn parse_netstat_no_address() {
+        let output = fs::read_to_string("test-data/netstat_ibdn_host.txt")
+            .unwrap();
+        let interfaces = parse_netstat(&output);
+
+        assert_eq!(interfaces["tun0"].rx_packets, Some(10));
+        assert_eq!(interfaces["tun0"].tx_drops, Some(0));
+    }
+
+    #[test]
+    fn parse_descriptions_ok() {
+        let output = fs::read_to_string("test-data/ifconfig_a.txt").unwrap();
+        let descriptions = parse_descriptions(&output);
+
+        assert_eq!(
+            descriptions.get("epair1a").map(String::as_str),
+            Some("associated with jail: db as nic: epair1b"),
+        );
+        assert_eq!(descriptions.get("em0"), None);
+    }
+
+    #[test]
+    fn is_host_side_ok() {
+        let iocage = Some("associated with jail: db as nic: epair1b");
+
+        assert!(is_host_side("epair1a", iocage, "db"));
+        assert!(!is_host_side("epair1a", iocage, "d"));
+        assert!(is_host_side("e0a_db", None, "db"));
+        assert!(!is_host_side("e0a_db2", None, "db"));
+        assert!(!is_host_side("em0", None, "db"));
+    }
+
+    #[test]
+    fn collect_jails_fixture() {
+        let mut registry = Registry::default();
+        let vnet = VnetMetrics::new(&mut registry, Box::new(FixtureSource));
+
+        let www = scraped(1, "www");
+        let db = scraped(2, "db");
+        vnet.collect_jails(&[&www, &db]).unwrap();
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        // Inside view of www.
+        assert!(buffer.contains(
+            "network_receive_bytes_total{name=\"www\",interface=\"epair0b\",view=\"jail\"} 567890"
+        ));
+
+        // Host view of db, mirrored to the jail's point of view.
+        assert!(buffer.contains(
+            "network_receive_bytes_total{name=\"db\",interface=\"epair1a\",view=\"host\"} 2000"
+        ));
+        assert!(buffer.contains(
+            "network_transmit_bytes_total{name=\"db\",interface=\"epair1a\",view=\"host\"} 1000"
+        ));
+        assert!(!buffer.contains("interface=\"em0\""));
+    }
+}
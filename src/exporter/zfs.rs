@@ -0,0 +1,485 @@
+// zfs: ZFS dataset metrics for jail roots.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::register_gauge_with_registry;
+use jail::RunningJail;
+use prometheus_client::metrics::gauge::Gauge;
+use prometheus_client::registry::Unit;
+use std::collections::{
+    BTreeSet,
+    HashMap,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::sync::atomic::AtomicU64;
+use tracing::debug;
+use super::command;
+use super::filesystem::MountTable;
+use super::registry::{
+    Family,
+    Registry,
+};
+use super::{
+    JailLabels,
+    ScrapedJail,
+};
+
+// Command used to query ZFS datasets.
+const ZFS_COMMAND: &str = "/sbin/zfs";
+
+// Dataset properties that we export.
+const PROPERTIES: &str = "used,available,referenced,quota,refquota,compressratio";
+
+/// A source of ZFS information. This is abstracted so that the collector can
+/// be tested against captured command output.
+pub trait ZfsSource: Send + Sync {
+    /// Returns the table of mounted filesystems.
+    fn mounts(&self) -> Result<MountTable, ExporterError>;
+
+    /// Returns the `zfs get -Hp -o name,property,value` output for the
+    /// exported properties of the given datasets.
+    fn properties(&self, datasets: &[&str]) -> Result<String, ExporterError>;
+
+    /// Returns the names of the snapshots of the given datasets, one per line.
+    fn snapshots(&self, datasets: &[&str]) -> Result<String, ExporterError>;
+}
+
+/// Gets ZFS information by running `mount(8)` and `zfs(8)`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZfsCommand;
+
+impl ZfsSource for ZfsCommand {
+    fn mounts(&self) -> Result<MountTable, ExporterError> {
+        MountTable::load()
+    }
+
+    fn properties(&self, datasets: &[&str]) -> Result<String, ExporterError> {
+        let mut args = vec!["get", "-Hp", "-o", "name,property,value", PROPERTIES];
+        args.extend(datasets);
+
+        command::output(ZFS_COMMAND, &args)
+    }
+
+    fn snapshots(&self, datasets: &[&str]) -> Result<String, ExporterError> {
+        let mut args = vec!["list", "-Hp", "-t", "snapshot", "-o", "name", "-d", "1"];
+        args.extend(datasets);
+
+        command::output(ZFS_COMMAND, &args)
+    }
+}
+
+/// Exported properties of a single ZFS dataset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DatasetStats {
+    /// Space used by the dataset and its descendants, in bytes.
+    pub used: Option<u64>,
+
+    /// Space available to the dataset and its descendants, in bytes.
+    pub available: Option<u64>,
+
+    /// Space referenced by the dataset, in bytes.
+    pub referenced: Option<u64>,
+
+    /// Limit on the space used by the dataset and its descendants, in bytes.
+    /// Zero when no quota is set.
+    pub quota: Option<u64>,
+
+    /// Limit on the space referenced by the dataset, in bytes. Zero when no
+    /// quota is set.
+    pub refquota: Option<u64>,
+
+    /// Compression ratio achieved for the space used by the dataset.
+    pub compressratio: Option<f64>,
+
+    /// Number of snapshots of the dataset.
+    pub snapshots: u64,
+}
+
+/// Parses the output of `zfs get -Hp -o name,property,value`. Lines that
+/// can't be parsed, and properties without a value (`-`), are skipped.
+pub fn parse_properties(output: &str) -> HashMap<String, DatasetStats> {
+    let mut datasets: HashMap<String, DatasetStats> = HashMap::new();
+
+    for line in output.lines() {
+        let mut fields = line.split('\t');
+
+        let (Some(name), Some(property), Some(value)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            debug!("Skipping unparsable zfs output: {}", line);
+            continue;
+        };
+
+        let stats = datasets.entry(name.to_string()).or_default();
+        let bytes = value.parse::<u64>().ok();
+
+        match property {
+            "used"       => stats.used = bytes,
+            "available"  => stats.available = bytes,
+            "referenced" => stats.referenced = bytes,
+            "quota"      => stats.quota = bytes,
+            "refquota"   => stats.refquota = bytes,
+            "compressratio" => {
+                stats.compressratio = value
+                    .trim_end_matches('x')
+                    .parse::<f64>()
+                    .ok();
+            },
+            _ => debug!("Skipping unknown zfs property: {}", property),
+        }
+    }
+
+    datasets
+}
+
+/// Counts the snapshots in the output of `zfs list -t snapshot -o name`,
+/// keyed on the name of their dataset.
+pub fn count_snapshots(output: &str) -> HashMap<String, u64> {
+    let mut counts = HashMap::new();
+
+    let datasets = output
+        .lines()
+        .filter_map(|line| line.split_once('@'))
+        .map(|(dataset, _)| dataset);
+
+    for dataset in datasets {
+        *counts.entry(dataset.to_string()).or_default() += 1;
+    }
+
+    counts
+}
+
+/// ZFS dataset time series, registered under the `zfs` prefix.
+pub struct ZfsMetrics {
+    used:          Family<JailLabels, Gauge>,
+    available:     Family<JailLabels, Gauge>,
+    referenced:    Family<JailLabels, Gauge>,
+    quota:         Family<JailLabels, Gauge>,
+    refquota:      Family<JailLabels, Gauge>,
+    compressratio: Family<JailLabels, Gauge<f64, AtomicU64>>,
+    snapshots:     Family<JailLabels, Gauge>,
+
+    // Where the ZFS information comes from.
+    source: Box<dyn ZfsSource>,
+}
+
+impl ZfsMetrics {
+    /// Registers the ZFS metrics within a sub-registry of the given registry,
+    /// getting information from the given source.
+    pub fn new(registry: &mut Registry, source: Box<dyn ZfsSource>) -> Self {
+        let registry = registry.sub_registry_with_prefix("zfs");
+
+        let compressratio = Family::<JailLabels, Gauge<f64, AtomicU64>>::default();
+        registry.register(
+            "compressratio",
+            "compression ratio achieved for the jail's dataset",
+            compressratio.clone(),
+        );
+
+        Self {
+            used: register_gauge_with_registry!(
+                "used",
+                "space used by the jail's dataset and its descendants, in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            available: register_gauge_with_registry!(
+                "available",
+                "space available to the jail's dataset, in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            referenced: register_gauge_with_registry!(
+                "referenced",
+                "space referenced by the jail's dataset, in bytes",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            quota: register_gauge_with_registry!(
+                "quota",
+                "quota of the jail's dataset, in bytes, or 0 if unset",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            refquota: register_gauge_with_registry!(
+                "refquota",
+                "refquota of the jail's dataset, in bytes, or 0 if unset",
+                JailLabels,
+                Unit::Bytes,
+                registry,
+            ),
+
+            snapshots: register_gauge_with_registry!(
+                "snapshots",
+                "number of snapshots of the jail's dataset",
+                JailLabels,
+                registry,
+            ),
+
+            compressratio: compressratio,
+            source:        source,
+        }
+    }
+
+    /// Sets the dataset metrics of the given jails. Datasets are collected
+    /// afresh on each scrape, so any existing time series are removed first.
+    pub fn collect(&self, jails: &[ScrapedJail]) -> Result<(), ExporterError> {
+        debug!("Collecting ZFS metrics");
+
+        let mut roots = Vec::with_capacity(jails.len());
+
+        for jail in jails {
+            let root = RunningJail::from_jid_unchecked(jail.jid).path()?;
+            roots.push((&jail.labels, root));
+        }
+
+        self.collect_roots(&roots)
+    }
+
+    // Sets the dataset metrics for jails with the given labels and root
+    // paths.
+    fn collect_roots(
+        &self,
+        roots: &[(&JailLabels, PathBuf)],
+    ) -> Result<(), ExporterError> {
+        self.clear();
+
+        let table = self.source.mounts()?;
+
+        // Map each jail to the ZFS dataset mounted on its root, following
+        // nullfs mounts. Jails that aren't on ZFS are skipped, as are jails
+        // whose root is only a directory within a dataset, as the dataset's
+        // usage isn't the jail's. The same goes for jails rooted at `/`,
+        // which share the host's root dataset.
+        let jails: Vec<(&JailLabels, &str)> = roots
+            .iter()
+            .filter(|(_, root)| root != Path::new("/"))
+            .filter_map(|(labels, root)| {
+                let mount = table.mounted_at(root)?;
+
+                (mount.fstype == "zfs").then_some((*labels, mount.device.as_str()))
+            })
+            .collect();
+
+        if jails.is_empty() {
+            return Ok(());
+        }
+
+        let datasets: Vec<&str> = jails
+            .iter()
+            .map(|(_, dataset)| *dataset)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+
+        let mut stats = parse_properties(&self.source.properties(&datasets)?);
+        let snapshots = count_snapshots(&self.source.snapshots(&datasets)?);
+
+        for (dataset, count) in snapshots {
+            if let Some(stats) = stats.get_mut(&dataset) {
+                stats.snapshots = count;
+            }
+        }
+
+        for (labels, dataset) in jails {
+            let Some(stats) = stats.get(dataset) else {
+                debug!("No zfs properties for dataset: {}", dataset);
+                continue;
+            };
+
+            let mut labels = labels.clone();
+            labels.push("dataset", dataset);
+
+            self.set(&labels, stats);
+        }
+
+        Ok(())
+    }
+
+    // Sets the time series for a single dataset.
+    fn set(&self, labels: &JailLabels, stats: &DatasetStats) {
+        let gauges = [
+            (&self.used, stats.used),
+            (&self.available, stats.available),
+            (&self.referenced, stats.referenced),
+            (&self.quota, stats.quota),
+            (&self.refquota, stats.refquota),
+            (&self.snapshots, Some(stats.snapshots)),
+        ];
+
+        for (gauge, value) in gauges {
+            if let Some(value) = value {
+                let value = i64::try_from(value).unwrap_or(i64::MAX);
+                gauge.get_or_create(labels).set(value);
+            }
+        }
+
+        if let Some(ratio) = stats.compressratio {
+            self.compressratio.get_or_create(labels).set(ratio);
+        }
+    }
+
+    // Removes all ZFS time series.
+    fn clear(&self) {
+        self.used.clear();
+        self.available.clear();
+        self.referenced.clear();
+        self.quota.clear();
+        self.refquota.clear();
+        self.compressratio.clear();
+        self.snapshots.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::registry::encode;
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    // Source returning captured command output.
+    struct FixtureSource;
+
+    impl ZfsSource for FixtureSource {
+        fn mounts(&self) -> Result<MountTable, ExporterError> {
+            let output = fs::read_to_string("test-data/mount_p.txt")?;
+            Ok(MountTable::parse(&output))
+        }
+
+        fn properties(&self, _: &[&str]) -> Result<String, ExporterError> {
+            Ok(fs::read_to_string("test-data/zfs_get.txt")?)
+        }
+
+        fn snapshots(&self, _: &[&str]) -> Result<String, ExporterError> {
+            Ok(fs::read_to_string("test-data/zfs_list_snapshots.txt")?)
+        }
+    }
+
+    #[test]
+    fn parse_properties_ok() {
+        let output = fs::read_to_string("test-data/zfs_get.txt").unwrap();
+        let datasets = parse_properties(&output);
+
+        assert_eq!(datasets["zroot/jails/www"], DatasetStats {
+            used:          Some(1_610_612_736),
+            available:     Some(53_687_091_200),
+            referenced:    Some(1_073_741_824),
+            quota:         Some(10_737_418_240),
+            refquota:      Some(0),
+            compressratio: Some(1.52),
+            snapshots:     0,
+        });
+    }
+
+    #[test]
+    fn parse_properties_missing_value() {
+        let output = "zroot/jails/db\tquota\t-\n\
+                      zroot/jails/db\tcompressratio\t2.00x\n\
+                      garbage\n";
+        let datasets = parse_properties(output);
+        let stats = &datasets["zroot/jails/db"];
+
+        assert_eq!(stats.quota, None);
+        assert_eq!(stats.compressratio, Some(2.0));
+        assert_eq!(datasets.len(), 1);
+    }
+
+    #[test]
+    fn count_snapshots_ok() {
+        let output = fs::read_to_string("test-data/zfs_list_snapshots.txt")
+            .unwrap();
+        let counts = count_snapshots(&output);
+
+        assert_eq!(counts.get("zroot/jails/www"), Some(&3));
+        assert_eq!(counts.get("zroot/usr/ports"), Some(&1));
+    }
+
+    #[test]
+    fn collect_roots_fixture() {
+        let mut registry = Registry::default();
+        let zfs = ZfsMetrics::new(&mut registry, Box::new(FixtureSource));
+
+        let mut www = JailLabels::default();
+        www.push("name", "www");
+
+        let mut plain = JailLabels::default();
+        plain.push("name", "plain");
+
+        zfs.collect_roots(&[
+            (&www, PathBuf::from("/jails/www")),
+            (&plain, PathBuf::from("/mnt/My Files/plain")),
+        ]).unwrap();
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        assert!(buffer.contains(
+            "zfs_used_bytes{name=\"www\",dataset=\"zroot/jails/www\"} 1610612736"
+        ));
+        assert!(buffer.contains(
+            "zfs_snapshots{name=\"www\",dataset=\"zroot/jails/www\"} 3"
+        ));
+        assert!(buffer.contains(
+            "zfs_compressratio{name=\"www\",dataset=\"zroot/jails/www\"} 1.52"
+        ));
+
+        // The plain jail lives on UFS, so has no dataset.
+        assert!(!buffer.contains("name=\"plain\""));
+    }
+
+    #[test]
+    fn collect_roots_plain_directory() {
+        let mut registry = Registry::default();
+        let zfs = ZfsMetrics::new(&mut registry, Box::new(FixtureSource));
+
+        let mut plain = JailLabels::default();
+        plain.push("name", "plain");
+
+        let mut distfiles = JailLabels::default();
+        distfiles.push("name", "distfiles");
+
+        let mut ports = JailLabels::default();
+        ports.push("name", "ports");
+
+        let mut host = JailLabels::default();
+        host.push("name", "host");
+
+        zfs.collect_roots(&[
+            (&plain, PathBuf::from("/jails/plain")),
+            (&distfiles, PathBuf::from("/jails/www/usr/ports/distfiles")),
+            (&ports, PathBuf::from("/jails/www/usr/ports")),
+            (&host, PathBuf::from("/")),
+        ]).unwrap();
+
+        let mut buffer = String::new();
+        encode(&mut buffer, &registry).unwrap();
+
+        // Directories on the root and ports datasets aren't given the usage
+        // of the whole dataset.
+        assert!(!buffer.contains("name=\"plain\""));
+        assert!(!buffer.contains("name=\"distfiles\""));
+
+        // Nor is a jail sharing the host's root.
+        assert!(!buffer.contains("name=\"host\""));
+
+        // A nullfs mount of the ports dataset's mountpoint is.
+        assert!(buffer.contains(
+            "zfs_used_bytes{name=\"ports\",dataset=\"zroot/usr/ports\"} 4294967296"
+        ));
+        assert!(buffer.contains(
+            "zfs_snapshots{name=\"ports\",dataset=\"zroot/usr/ports\"} 1"
+        ));
+    }
+}
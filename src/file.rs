@@ -2,15 +2,30 @@
 #![forbid(unsafe_code)]
 #![forbid(missing_docs)]
 use crate::errors::ExporterError;
-use crate::exporter::Exporter;
+use crate::exporter::{
+    Exporter,
+    Format,
+};
+use crate::push;
 use std::fmt;
+use std::fs::Permissions;
 use std::io::{
     self,
     Write,
 };
+use std::os::unix::fs::{
+    fchown,
+    PermissionsExt,
+};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tempfile::NamedTempFile;
-use tracing::debug;
+use tokio::task;
+use tracing::{
+    debug,
+    error,
+};
 
 #[derive(Clone, Debug)]
 pub enum FileExporterOutput {
@@ -30,8 +45,16 @@ impl fmt::Display for FileExporterOutput {
     }
 }
 
+#[derive(Clone)]
 pub struct FileExporter {
-    dest: FileExporterOutput,
+    dest:   FileExporterOutput,
+    format: Format,
+
+    // Permissions and ownership of the output file. The temporary file's
+    // defaults of 0600 and the current user are kept if these are unset.
+    group: Option<u32>,
+    mode:  Option<u32>,
+    owner: Option<u32>,
 }
 
 impl FileExporter {
@@ -39,17 +62,53 @@ impl FileExporter {
         debug!("New FileExporter output to: {output}");
 
         Self {
-            dest: output,
+            dest:   output,
+            format: Format::default(),
+            group:  None,
+            mode:   None,
+            owner:  None,
         }
     }
 
+    // Sets the format the metrics are written in.
+    pub fn format(mut self, format: Format) -> Self {
+        debug!("Setting FileExporter format to: {}", format);
+
+        self.format = format;
+        self
+    }
+
+    // Sets the gid of the group owning the output file.
+    pub fn group(mut self, gid: u32) -> Self {
+        debug!("Setting FileExporter group to: {}", gid);
+
+        self.group = Some(gid);
+        self
+    }
+
+    // Sets the permission mode of the output file.
+    pub fn mode(mut self, mode: u32) -> Self {
+        debug!("Setting FileExporter mode to: {:o}", mode);
+
+        self.mode = Some(mode);
+        self
+    }
+
+    // Sets the uid of the user owning the output file.
+    pub fn owner(mut self, uid: u32) -> Self {
+        debug!("Setting FileExporter owner to: {}", uid);
+
+        self.owner = Some(uid);
+        self
+    }
+
     // Handles choosing the correct output type based on path
-    fn write(&self, metrics: &str) -> Result<(), ExporterError> {
+    fn write(&self, metrics: &[u8]) -> Result<(), ExporterError> {
         debug!("Writing metrics to: {}", self.dest);
 
         match &self.dest {
             FileExporterOutput::Stdout => {
-                io::stdout().write_all(metrics.as_bytes())?;
+                io::stdout().write_all(metrics)?;
             },
             FileExporterOutput::File(path) => {
                 // We already vetted the parent in the CLI validator, so unwrap
@@ -59,7 +118,18 @@ impl FileExporter {
                 // We do this since we need the temporary file to be on the
                 // same filesystem as the final persisted file.
                 let mut file = NamedTempFile::new_in(parent)?;
-                file.write_all(metrics.as_bytes())?;
+                file.write_all(metrics)?;
+
+                // Set these before persisting, so that the file never
+                // appears with the wrong permissions.
+                if let Some(mode) = self.mode {
+                    file.as_file().set_permissions(Permissions::from_mode(mode))?;
+                }
+
+                if self.owner.is_some() || self.group.is_some() {
+                    fchown(file.as_file(), self.owner, self.group)?;
+                }
+
                 file.persist(path)?;
             },
         }
@@ -67,16 +137,53 @@ impl FileExporter {
         Ok(())
     }
 
-    pub fn export(self) -> Result<(), ExporterError> {
+    pub fn export(&self, exporter: &Exporter) -> Result<(), ExporterError> {
         debug!("Exporting metrics to file");
 
-        // Get an exporter and export the metrics.
-        let exporter = Exporter::new();
-        let metrics  = exporter.export()?;
+        // Export the metrics.
+        let metrics = exporter.export_as(self.format)?;
 
         // Write metrics
         self.write(&metrics)?;
 
         Ok(())
     }
+
+    // Exports the metrics on a thread set aside for blocking work, since
+    // collection runs commands and makes syscalls that block, as does
+    // writing the file.
+    async fn export_blocking(
+        &self,
+        exporter: &Arc<Exporter>,
+    ) -> Result<(), ExporterError> {
+        let file = self.clone();
+        let exporter = Arc::clone(exporter);
+
+        task::spawn_blocking(move || file.export(&exporter))
+            .await
+            .map_err(|e| ExporterError::JoinError(e.to_string()))?
+    }
+
+    // Exports the metrics every interval until we receive SIGINT or SIGTERM.
+    // The same exporter is used throughout, so counters and the state used to
+    // remove the time series of dead jails carry over between writes.
+    pub async fn run(
+        &self,
+        exporter: &Arc<Exporter>,
+        interval: Duration,
+    ) -> Result<(), ExporterError> {
+        debug!("Exporting metrics to file every {:?}", interval);
+
+        push::every("file exporter", interval, self, |file| {
+            let exporter = Arc::clone(exporter);
+
+            Box::pin(async move {
+                // A failed write shouldn't stop later ones, the previous
+                // file is left in place until then.
+                if let Err(e) = file.export_blocking(&exporter).await {
+                    error!("Failed to export metrics to {}: {}", file.dest, e);
+                }
+            })
+        }).await
+    }
 }
@@ -2,6 +2,7 @@
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
 use axum::body::Bytes;
+use axum::middleware;
 use axum::routing;
 use axum::Router;
 use parking_lot::Mutex;
@@ -13,15 +14,15 @@ use tracing::{
     info,
 };
 
-#[cfg(feature = "auth")]
-use axum::middleware;
-
 #[cfg(feature = "auth")]
 pub mod auth;
 
 mod collector;
+mod compression;
 mod errors;
 mod handlers;
+mod negotiate;
+mod requests;
 mod templates;
 
 #[cfg(feature = "auth")]
@@ -29,12 +30,25 @@ pub use auth::BasicAuthConfig;
 
 use handlers::{
     index,
+    influx,
+    jails,
     metrics,
 };
+use requests::{
+    count_requests,
+    RequestCounter,
+};
 use templates::render_index_page;
 pub use collector::Collector;
+pub use compression::DEFAULT_COMPRESSION_MIN_SIZE;
 pub use errors::HttpdError;
-use super::Exporter;
+use crate::exporter::Exporter;
+
+// Path of the JSON API listing the metrics of each jail.
+pub const JAILS_API_PATH: &str = "/api/v1/jails";
+
+// Path of the InfluxDB line protocol encoding of each jail's rctl usage.
+pub const INFLUX_API_PATH: &str = "/api/v1/influx";
 
 // This AppState is used to pass the rendered index template to the index
 // function.
@@ -52,8 +66,10 @@ pub struct AppExporter {
 // Used for the httpd builder
 #[derive(Debug)]
 pub struct Server {
-    bind_address:   String,
-    telemetry_path: String,
+    bind_address:         String,
+    compression:          bool,
+    compression_min_size: u16,
+    telemetry_path:       String,
 
     #[cfg(feature = "auth")]
     basic_auth_config: Option<BasicAuthConfig>,
@@ -62,8 +78,10 @@ pub struct Server {
 impl Default for Server {
     fn default() -> Self {
         Self {
-            bind_address:   "127.0.0.1:9452".into(),
-            telemetry_path: "/metrics".into(),
+            bind_address:         "127.0.0.1:9452".into(),
+            compression:          true,
+            compression_min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            telemetry_path:       "/metrics".into(),
 
             #[cfg(feature = "auth")]
             basic_auth_config: None,
@@ -95,6 +113,23 @@ impl Server {
         self
     }
 
+    // Sets whether metrics responses may be compressed.
+    pub fn compression(mut self, compression: bool) -> Self {
+        debug!("Setting server compression to: {}", compression);
+
+        self.compression = compression;
+        self
+    }
+
+    // Sets the size, in bytes, a metrics response must exceed before it is
+    // compressed.
+    pub fn compression_min_size(mut self, min_size: u16) -> Self {
+        debug!("Setting server compression_min_size to: {}", min_size);
+
+        self.compression_min_size = min_size;
+        self
+    }
+
     // Sets the telemetry path for the metrics.
     pub fn telemetry_path(mut self, telemetry_path: String) -> Self {
         debug!("Setting server telemetry_path to: {}", telemetry_path);
@@ -116,6 +151,9 @@ impl Server {
         // enabling.
         let enable_http_auth = basic_auth_config.has_users();
 
+        // HTTP request counters, if the exporter metrics are enabled.
+        let http_requests = exporter.http_requests();
+
         // These states are shared between threads and allows us to pass
         // arbitrary items to request handlers.
         let app_exporter = AppExporter {
@@ -140,16 +178,27 @@ impl Server {
             auth::validate_credentials,
         );
 
+        // Only the metrics are worth compressing, the index page is tiny.
+        let mut metrics_route = routing::get(metrics);
+        let mut jails_route = routing::get(jails);
+        let mut influx_route = routing::get(influx);
+
+        if self.compression {
+            let layer = compression::layer(self.compression_min_size);
+            metrics_route = metrics_route.layer(layer.clone());
+            jails_route = jails_route.layer(layer.clone());
+            influx_route = influx_route.layer(layer);
+        }
+
         // Route handlers
         debug!("Creating HTTP server app");
 
-        // This mut will be unused if not compiled with the auth feature.
-        // Silence that warning.
-        #[allow(unused_mut)]
         let mut app = Router::new()
             .route("/", routing::get(index))
             .with_state(state)
-            .route(&self.telemetry_path, routing::get(metrics))
+            .route(&self.telemetry_path, metrics_route)
+            .route(JAILS_API_PATH, jails_route)
+            .route(INFLUX_API_PATH, influx_route)
             .with_state(app_exporter);
 
         // If we have some users, enable the authentication layer
@@ -158,6 +207,23 @@ impl Server {
             app = app.route_layer(auth_layer);
         }
 
+        // Count requests if we have somewhere to store the counts.
+        if let Some(http_requests) = http_requests {
+            let paths = vec![
+                "/".to_string(),
+                self.telemetry_path.clone(),
+                JAILS_API_PATH.to_string(),
+                INFLUX_API_PATH.to_string(),
+            ];
+
+            let counter = RequestCounter::new(http_requests, paths);
+
+            app = app.layer(middleware::from_fn_with_state(
+                Arc::new(counter),
+                count_requests,
+            ));
+        }
+
         // Finally add a tracing layer
         let app = app
             .layer(TraceLayer::new_for_http());
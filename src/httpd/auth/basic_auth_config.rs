@@ -46,6 +46,18 @@ impl BasicAuthConfig {
         Ok(config)
     }
 
+    // Returns a BasicAuthConfig for users given elsewhere, such as in the
+    // exporter configuration file.
+    pub fn from_users(users: HashMap<String, String>) -> Result<Self, ExporterError> {
+        let config = Self {
+            basic_auth_users: Some(users),
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
     // Returns a boolean indicating if we have any users configured.
     pub fn has_users(&self) -> bool {
         self.basic_auth_users.is_some()
@@ -115,4 +127,16 @@ mod tests {
 
         assert!(config.is_ok());
     }
+
+    // Users given in the exporter configuration file are validated too.
+    #[test]
+    fn basic_user_config_from_users_invalid() {
+        let users = HashMap::from([
+            ("foo:bar".to_string(), "$2b$04$nFPE4cwFjOFGUmdp.o2NTuh/blJDaEwikX1qoitVe144TsS2l5whS".to_string()),
+        ]);
+
+        let config = BasicAuthConfig::from_users(users);
+
+        assert!(config.is_err());
+    }
 }
@@ -1,6 +1,7 @@
 // collector: This trait must be implemented so the HTTPd can export metrics
 use super::errors::HttpdError;
+use crate::exporter::Format;
 
 pub trait Collector {
-    fn collect(&self) -> Result<String, HttpdError>;
+    fn collect(&self, format: Format) -> Result<Vec<u8>, HttpdError>;
 }
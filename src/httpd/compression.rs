@@ -0,0 +1,103 @@
+// compression: Compression of metrics responses.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use tower_http::compression::{
+    predicate::SizeAbove,
+    CompressionLayer,
+};
+
+/// The default size, in bytes, that a response must exceed before it is
+/// compressed.
+pub const DEFAULT_COMPRESSION_MIN_SIZE: u16 = 1024;
+
+/// Returns a layer compressing responses larger than `min_size` bytes with
+/// gzip, zstd or deflate, depending on the request's Accept-Encoding header.
+pub fn layer(min_size: u16) -> CompressionLayer<SizeAbove> {
+    CompressionLayer::new()
+        .gzip(true)
+        .zstd(true)
+        .deflate(true)
+        .compress_when(SizeAbove::new(min_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{
+            header::{
+                ACCEPT_ENCODING,
+                CONTENT_ENCODING,
+            },
+            Request,
+        },
+        routing::get,
+        Router,
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+
+    // Body of the large response, over the default minimum size.
+    fn large_body() -> String {
+        "jail_num 1\n".repeat(200)
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/large", get(|| async { large_body() }))
+            .route("/small", get(|| async { "jail_num 1\n" }))
+            .layer(layer(DEFAULT_COMPRESSION_MIN_SIZE))
+    }
+
+    async fn content_encoding(path: &str, accept_encoding: &str) -> Option<String> {
+        let request = Request::builder()
+            .uri(path)
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(Body::empty())
+            .unwrap();
+
+        let response = app().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn compression_gzip() {
+        let encoding = content_encoding("/large", "gzip").await;
+
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+    }
+
+    #[tokio::test]
+    async fn compression_zstd() {
+        let encoding = content_encoding("/large", "zstd").await;
+
+        assert_eq!(encoding.as_deref(), Some("zstd"));
+    }
+
+    #[tokio::test]
+    async fn compression_deflate() {
+        let encoding = content_encoding("/large", "deflate").await;
+
+        assert_eq!(encoding.as_deref(), Some("deflate"));
+    }
+
+    #[tokio::test]
+    async fn compression_not_accepted() {
+        let encoding = content_encoding("/large", "identity").await;
+
+        assert_eq!(encoding, None);
+    }
+
+    #[tokio::test]
+    async fn compression_below_min_size() {
+        let encoding = content_encoding("/small", "gzip").await;
+
+        assert_eq!(encoding, None);
+    }
+}
@@ -14,18 +14,17 @@ use axum::response::{
 };
 use parking_lot::Mutex;
 use std::sync::Arc;
+use tokio::task;
 use super::{
     AppState,
     AppExporter,
 };
 use super::Collector;
 use super::HttpdError;
+use super::negotiate;
+use crate::exporter::Format;
 use tracing::debug;
 
-// If we don't set this as the content-type header, Prometheus will not ingest
-// the metrics properly, complaining about the INFO metric type.
-const OPENMETRICS_HEADER: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
-
 // Displays the index page. This is a page which simply links to the actual
 // telemetry path.
 #[allow(clippy::unused_async)]
@@ -35,30 +34,90 @@ pub async fn index(State(data): State<Arc<AppState>>) -> impl IntoResponse {
     Html(data.index_page.clone())
 }
 
+// Collects the metrics in the given format. Collection runs commands and
+// makes syscalls that block, so it is moved off the async worker threads,
+// along with the lock on the exporter that is held while it runs.
+async fn collect(
+    data: Arc<Mutex<AppExporter>>,
+    format: Format,
+) -> Result<Vec<u8>, HttpdError> {
+    task::spawn_blocking(move || {
+        let data = data.lock();
+
+        // Get the exporter from the state
+        let exporter = &(data.exporter);
+
+        // Exporter could fail.
+        exporter.collect(format)
+    })
+    .await
+    .map_err(|e| HttpdError::CollectorError(e.to_string()))?
+}
+
 // Returns a HttpResponse containing the Prometheus Exporter output, or an
 // InternalServerError if things fail for some reason.
-#[allow(clippy::unused_async)]
-pub async fn metrics(State(data): State<Arc<Mutex<AppExporter>>>)
--> Result<impl IntoResponse, HttpdError> {
+pub async fn metrics(
+    State(data): State<Arc<Mutex<AppExporter>>>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, HttpdError> {
     debug!("Processing metrics request");
 
-    let data = data.lock();
+    // Prometheus will only ingest info metrics properly if the content type
+    // matches the format, so the format is picked first.
+    let format = negotiate::format(request_headers.get(header::ACCEPT));
 
-    // Get the exporter from the state
-    let exporter = &(data.exporter);
-
-    // Exporter could fail.
-    let metrics = exporter.collect()?;
+    let metrics = collect(data, format).await?;
 
     let mut headers = HeaderMap::new();
     headers.insert(
         header::CONTENT_TYPE,
-        HeaderValue::from_static(OPENMETRICS_HEADER),
+        HeaderValue::from_static(format.content_type()),
+    );
+    headers.insert(
+        header::VARY,
+        HeaderValue::from_static("accept"),
     );
 
     Ok((StatusCode::OK, headers, metrics))
 }
 
+// Returns a HttpResponse containing the metrics in a single format, for the
+// routes that don't negotiate one.
+async fn export(
+    data: Arc<Mutex<AppExporter>>,
+    format: Format,
+) -> Result<impl IntoResponse, HttpdError> {
+    let body = collect(data, format).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+
+    Ok((StatusCode::OK, headers, body))
+}
+
+// Returns a HttpResponse containing the metrics of each jail as JSON, for
+// tools wanting structured data rather than a Prometheus format.
+pub async fn jails(
+    State(data): State<Arc<Mutex<AppExporter>>>,
+) -> Result<impl IntoResponse, HttpdError> {
+    debug!("Processing jails request");
+
+    export(data, Format::Json).await
+}
+
+// Returns a HttpResponse containing the rctl usage of each jail in the
+// InfluxDB line protocol, for Telegraf's inputs.http to poll.
+pub async fn influx(
+    State(data): State<Arc<Mutex<AppExporter>>>,
+) -> Result<impl IntoResponse, HttpdError> {
+    debug!("Processing influx request");
+
+    export(data, Format::Influx).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,7 +130,12 @@ mod tests {
         routing::get,
         Router,
     };
+    use crate::exporter::{
+        ExporterBuilder,
+        JailFilter,
+    };
     use pretty_assertions::assert_eq;
+    use regex::Regex;
     use tower::ServiceExt;
 
     fn app(state: Arc<AppState>) -> Router {
@@ -113,4 +177,96 @@ mod tests {
 
         assert_eq!(body, "Test Body".as_bytes());
     }
+
+    // Returns an app serving the metrics of an exporter that filters out
+    // every jail, so that the responses don't depend on the host's jails.
+    fn metrics_app() -> Router {
+        let exclude = Regex::new(".*").unwrap();
+        let exporter = ExporterBuilder::new()
+            .jail_filter(JailFilter::new(None, Some(exclude)))
+            .build();
+
+        let state = AppExporter {
+            exporter: exporter,
+        };
+
+        Router::new()
+            .route("/metrics", get(metrics))
+            .with_state(Arc::new(Mutex::new(state)))
+    }
+
+    // Requests the metrics with the given Accept header, returning the
+    // Content-Type and Vary headers and the body of the response.
+    async fn get_metrics(accept: Option<&str>) -> (String, String, String) {
+        let mut request = Request::builder().uri("/metrics");
+
+        if let Some(accept) = accept {
+            request = request.header(header::ACCEPT, accept);
+        }
+
+        let request = request.body(Body::empty()).unwrap();
+        let response = metrics_app().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        let header_value = |name| {
+            response
+                .headers()
+                .get(name)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let content_type = header_value(CONTENT_TYPE);
+        let vary = header_value(header::VARY);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (content_type, vary, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    // Checks that the body is in the classic text format.
+    fn assert_text(body: &str) {
+        assert!(body.contains("# TYPE jail_exporter_build_info gauge\n"));
+        assert!(!body.lines().any(|line| line == "# EOF"));
+        assert!(!body.lines().any(|line| {
+            line.starts_with("# TYPE ") && line.ends_with(" info")
+        }));
+    }
+
+    #[tokio::test]
+    async fn metrics_openmetrics() {
+        let accept = "application/openmetrics-text; version=1.0.0";
+        let (content_type, vary, body) = get_metrics(Some(accept)).await;
+
+        assert_eq!(
+            content_type,
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        );
+        assert_eq!(vary, "accept");
+        assert!(body.contains("# TYPE jail_exporter_build info\n"));
+        assert!(body.ends_with("# EOF\n"));
+    }
+
+    #[tokio::test]
+    async fn metrics_text() {
+        let accept = "text/plain; version=0.0.4";
+        let (content_type, vary, body) = get_metrics(Some(accept)).await;
+
+        assert_eq!(content_type, "text/plain; version=0.0.4; charset=utf-8");
+        assert_eq!(vary, "accept");
+        assert_text(&body);
+    }
+
+    #[tokio::test]
+    async fn metrics_no_accept() {
+        let (content_type, vary, body) = get_metrics(None).await;
+
+        assert_eq!(content_type, "text/plain; version=0.0.4; charset=utf-8");
+        assert_eq!(vary, "accept");
+        assert_text(&body);
+    }
 }
@@ -0,0 +1,168 @@
+// negotiate: Picks the exposition format from the Accept header.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use axum::http::HeaderValue;
+use crate::exporter::Format;
+use tracing::debug;
+
+// The format used when the client doesn't ask for one that we have.
+const FALLBACK_FORMAT: Format = Format::Text;
+
+// Media type parameters, as name and value pairs.
+type Params<'a> = Vec<(&'a str, &'a str)>;
+
+// Returns the value of the named media type parameter.
+fn param<'a>(params: &Params<'a>, name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(param, _)| param.eq_ignore_ascii_case(name))
+        .map(|(_, value)| *value)
+}
+
+// Returns the format named by a media type, ignoring wildcards. Parameters
+// are only needed to tell protobuf encodings apart.
+#[cfg_attr(not(feature = "protobuf"), allow(unused_variables))]
+fn media_type_format(media_type: &str, params: &Params) -> Option<Format> {
+    match media_type.to_ascii_lowercase().as_str() {
+        "application/openmetrics-text" => Some(Format::OpenMetrics),
+        "text/plain"                   => Some(Format::Text),
+
+        // Only the delimited encoding of the Prometheus protobuf messages is
+        // supported.
+        #[cfg(feature = "protobuf")]
+        "application/vnd.google.protobuf" => {
+            let proto = param(params, "proto");
+            let encoding = param(params, "encoding");
+
+            let supported = proto == Some("io.prometheus.client.MetricFamily")
+                && encoding == Some("delimited");
+
+            supported.then_some(Format::Protobuf)
+        },
+
+        _ => None,
+    }
+}
+
+// Returns the quality of a media range from its parameters, defaulting to 1.
+fn quality(params: &Params) -> f32 {
+    param(params, "q")
+        .and_then(|q| q.parse::<f32>().ok())
+        .filter(|q| (0.0..=1.0).contains(q))
+        .unwrap_or(1.0)
+}
+
+/// Returns the format to expose the metrics in, given the request's Accept
+/// header. The acceptable format with the highest quality wins, with ties
+/// going to the one listed first. Clients that don't ask for a format we
+/// have, including those accepting anything, get the classic text format.
+pub fn format(accept: Option<&HeaderValue>) -> Format {
+    let Some(accept) = accept.and_then(|accept| accept.to_str().ok()) else {
+        return FALLBACK_FORMAT;
+    };
+
+    let mut best: Option<(Format, f32)> = None;
+
+    for media_range in accept.split(',') {
+        let mut parts = media_range.split(';');
+        let media_type = parts.next().unwrap_or_default().trim();
+
+        let params: Params = parts
+            .filter_map(|param| param.split_once('='))
+            .map(|(name, value)| (name.trim(), value.trim().trim_matches('"')))
+            .collect();
+
+        let Some(format) = media_type_format(media_type, &params) else {
+            continue;
+        };
+
+        let q = quality(&params);
+
+        // A quality of 0 means that the format isn't acceptable.
+        if q <= 0.0 {
+            continue;
+        }
+
+        if best.map_or(true, |(_, best_q)| q > best_q) {
+            best = Some((format, q));
+        }
+    }
+
+    let format = best.map_or(FALLBACK_FORMAT, |(format, _)| format);
+    debug!("Negotiated {} format from Accept: {}", format, accept);
+
+    format
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn negotiate(accept: &'static str) -> Format {
+        format(Some(&HeaderValue::from_static(accept)))
+    }
+
+    #[test]
+    fn format_no_accept() {
+        assert_eq!(format(None), Format::Text);
+    }
+
+    #[test]
+    fn format_prometheus_scrape() {
+        // The Accept header sent by Prometheus.
+        let accept = "application/openmetrics-text;version=1.0.0;q=0.5,\
+                      application/openmetrics-text;version=0.0.1;q=0.4,\
+                      text/plain;version=0.0.4;q=0.3,*/*;q=0.2";
+
+        assert_eq!(negotiate(accept), Format::OpenMetrics);
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn format_protobuf() {
+        // The Accept header sent by Prometheus with protobuf scraping.
+        let accept = "application/vnd.google.protobuf;\
+                      proto=io.prometheus.client.MetricFamily;\
+                      encoding=delimited;q=0.5,\
+                      application/openmetrics-text;version=1.0.0;q=0.4,\
+                      text/plain;version=0.0.4;q=0.3,*/*;q=0.2";
+
+        assert_eq!(negotiate(accept), Format::Protobuf);
+    }
+
+    #[cfg(feature = "protobuf")]
+    #[test]
+    fn format_protobuf_text_encoding() {
+        let accept = "application/vnd.google.protobuf;\
+                      proto=io.prometheus.client.MetricFamily;encoding=text";
+
+        assert_eq!(negotiate(accept), Format::Text);
+    }
+
+    #[test]
+    fn format_text_preferred() {
+        let accept = "application/openmetrics-text;q=0.2, text/plain;version=0.0.4";
+
+        assert_eq!(negotiate(accept), Format::Text);
+    }
+
+    #[test]
+    fn format_wildcard() {
+        assert_eq!(negotiate("*/*"), Format::Text);
+    }
+
+    #[test]
+    fn format_not_acceptable() {
+        let accept = "application/openmetrics-text;q=0, */*";
+
+        assert_eq!(negotiate(accept), Format::Text);
+    }
+
+    #[test]
+    fn format_tie_goes_to_first() {
+        let accept = "application/openmetrics-text, text/plain";
+
+        assert_eq!(negotiate(accept), Format::OpenMetrics);
+    }
+}
@@ -0,0 +1,117 @@
+// requests: This module counts the HTTP requests handled by the httpd.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::exporter::{
+    HttpRequestLabels,
+    HttpRequests,
+};
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+
+// Path label used for any request that doesn't match one of our routes. This
+// stops random requests from creating an unbounded number of time series.
+const OTHER_PATH: &str = "other";
+
+// State for the request counting middleware.
+pub struct RequestCounter {
+    // Counters shared with the exporter registry.
+    requests: HttpRequests,
+
+    // Paths which are given their own label value.
+    paths: Vec<String>,
+}
+
+impl RequestCounter {
+    pub fn new(requests: HttpRequests, paths: Vec<String>) -> Self {
+        Self {
+            requests,
+            paths,
+        }
+    }
+
+    // Returns the path label to use for the given request path.
+    fn path_label(&self, path: &str) -> String {
+        if self.paths.iter().any(|p| p == path) {
+            path.to_string()
+        }
+        else {
+            OTHER_PATH.to_string()
+        }
+    }
+}
+
+// Counts requests by path and response status.
+pub async fn count_requests(
+    State(state): State<Arc<RequestCounter>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = state.path_label(req.uri().path());
+
+    let response = next.run(req).await;
+
+    let labels = HttpRequestLabels {
+        path:   path,
+        status: response.status().as_u16().to_string(),
+    };
+
+    state.requests.get_or_create(&labels).inc();
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        middleware,
+        Router,
+    };
+    use axum::routing::get;
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt;
+
+    fn app(requests: HttpRequests) -> Router {
+        let counter = RequestCounter::new(requests, vec!["/metrics".into()]);
+
+        Router::new()
+            .route("/metrics", get(|| async { "Test" }))
+            .layer(
+                middleware::from_fn_with_state(
+                    Arc::new(counter),
+                    count_requests,
+                ),
+            )
+    }
+
+    #[tokio::test]
+    async fn count_requests_by_path_and_status() {
+        let requests = HttpRequests::default();
+
+        for uri in ["/metrics", "/metrics", "/random"] {
+            let req = Request::builder()
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+
+            app(requests.clone()).oneshot(req).await.unwrap();
+        }
+
+        let ok = HttpRequestLabels {
+            path:   "/metrics".into(),
+            status: "200".into(),
+        };
+
+        let not_found = HttpRequestLabels {
+            path:   "other".into(),
+            status: "404".into(),
+        };
+
+        assert_eq!(requests.get_or_create(&ok).get(), 2);
+        assert_eq!(requests.get_or_create(&not_found).get(), 1);
+    }
+}
@@ -6,12 +6,21 @@
 /// Register a Counter Family with the Registry
 #[macro_export]
 macro_rules! register_counter_with_registry {
+    // Single counter with no specified unit
+    ($NAME:expr, $HELP:expr, $REGISTRY:ident $(,)?) => {{
+        use prometheus_client::metrics::counter::Counter;
+
+        let counter = Counter::default();
+
+        $REGISTRY.register($NAME, $HELP, counter.clone());
+
+        counter
+    }};
+
     // Counter family with no specific unit
     ($NAME:expr, $HELP:expr, $LABELS:ty, $REGISTRY:ident $(,)?) => {{
-        use prometheus_client::metrics::{
-            counter::Counter,
-            family::Family,
-        };
+        use prometheus_client::metrics::counter::Counter;
+        use $crate::exporter::registry::Family;
 
         let family = Family::<$LABELS, Counter>::default();
 
@@ -22,10 +31,8 @@ macro_rules! register_counter_with_registry {
 
     // Counter family with a specified unit
     ($NAME:expr, $HELP:expr, $LABELS:ty, $UNIT:expr, $REGISTRY:ident $(,)?) => {{
-        use prometheus_client::metrics::{
-            counter::Counter,
-            family::Family,
-        };
+        use prometheus_client::metrics::counter::Counter;
+        use $crate::exporter::registry::Family;
 
         let family = Family::<$LABELS, Counter>::default();
 
@@ -56,10 +63,8 @@ macro_rules! register_gauge_with_registry {
 
     // Gauge family with no specified unit
     ($NAME:expr, $HELP:expr, $LABELS:ty, $REGISTRY:ident $(,)?) => {{
-        use prometheus_client::metrics::{
-            family::Family,
-            gauge::Gauge,
-        };
+        use prometheus_client::metrics::gauge::Gauge;
+        use $crate::exporter::registry::Family;
 
         let family = Family::<$LABELS, Gauge>::default();
 
@@ -70,10 +75,8 @@ macro_rules! register_gauge_with_registry {
 
     // Gauge family with a specified unit
     ($NAME:expr, $HELP:expr, $LABELS:ty, $UNIT:expr, $REGISTRY:ident $(,)?) => {{
-        use prometheus_client::metrics::{
-            family::Family,
-            gauge::Gauge,
-        };
+        use prometheus_client::metrics::gauge::Gauge;
+        use $crate::exporter::registry::Family;
 
         let family = Family::<$LABELS, Gauge>::default();
 
@@ -93,10 +96,30 @@ macro_rules! register_gauge_with_registry {
 macro_rules! register_info_with_registry {
     // Single info metric with specified labels.
     ($NAME:expr, $HELP:expr, $LABELS:expr, $REGISTRY:ident $(,)?) => {{
-        use prometheus_client::metrics::info::Info;
+        use $crate::exporter::registry::Info;
 
         let info = Info::new($LABELS);
 
         $REGISTRY.register($NAME, $HELP, info);
     }};
 }
+
+/// Register a Histogram with the Registry
+#[macro_export]
+macro_rules! register_histogram_with_registry {
+    // Single histogram with a specified unit
+    ($NAME:expr, $HELP:expr, $BUCKETS:expr, $UNIT:expr, $REGISTRY:ident $(,)?) => {{
+        use $crate::exporter::registry::Histogram;
+
+        let histogram = Histogram::new($BUCKETS);
+
+        $REGISTRY.register_with_unit(
+            $NAME,
+            $HELP,
+            $UNIT,
+            histogram.clone(),
+        );
+
+        histogram
+    }};
+}
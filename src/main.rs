@@ -10,16 +10,20 @@
 use tracing::debug;
 use uzers::UsersCache;
 
-#[cfg(feature = "auth")]
-use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
+mod checkconfig;
 mod cli;
+mod collectors;
+mod config;
 mod errors;
 mod exporter;
 mod file;
 mod httpd;
 mod racctrctl;
 mod rctlstate;
+mod shutdown;
 mod user;
 
 #[macro_use]
@@ -28,11 +32,20 @@ mod macros;
 #[cfg(feature = "bcrypt_cmd")]
 mod bcrypt;
 
+mod push;
+
 #[cfg(feature = "rc_script")]
 mod rcscript;
 
 use errors::ExporterError;
-use exporter::Exporter;
+use exporter::{
+    ExporterBuilder,
+    Format,
+    JailFilter,
+    JailIdentity,
+    JailUsage,
+};
+use regex::Regex;
 use file::{
     FileExporter,
     FileExporterOutput,
@@ -41,13 +54,43 @@ use file::{
 #[cfg(feature = "auth")]
 use httpd::auth::BasicAuthConfig;
 
+#[cfg(feature = "auth")]
+use std::path::PathBuf;
+
+#[cfg(feature = "graphite")]
+use push::Graphite;
+
+#[cfg(feature = "otlp")]
+use push::{
+    Otlp,
+    Temporality,
+};
+
+#[cfg(feature = "pushgateway")]
+use push::Pushgateway;
+
+#[cfg(feature = "remote_write")]
+use push::RemoteWrite;
+
+#[cfg(feature = "push")]
+use reqwest::Url;
+
+#[cfg(any(feature = "otlp", feature = "pushgateway"))]
+use sysctl::Sysctl;
+
+// Sysctl holding the hostname, the default Pushgateway instance label and the
+// OTLP host.name resource attribute.
+#[cfg(any(feature = "otlp", feature = "pushgateway"))]
+const CTL_KERN_HOSTNAME: &str = "kern.hostname";
+
 #[tokio::main]
 async fn main() -> Result<(), ExporterError> {
     // We do as much as we can without checking if we're running as root.
     tracing_subscriber::fmt::init();
 
-    // Parse the commandline arguments.
-    let matches = cli::parse_args();
+    // Parse the commandline arguments, applying the configuration file if we
+    // were given one.
+    let (matches, config) = cli::parse_args()?;
 
     #[cfg(feature = "rc_script")]
     // If we have been asked to dump the rc(8) script, do that, and exit.
@@ -65,6 +108,23 @@ async fn main() -> Result<(), ExporterError> {
         ::std::process::exit(0);
     }
 
+    // If we have been asked to check a configuration file, do that, and exit.
+    // This doesn't need root or RACCT/RCTL, so that configuration can be
+    // checked on a build host.
+    if let Some(subcmd) = matches.subcommand_matches("check-config") {
+        checkconfig::check_from(subcmd)?;
+
+        ::std::process::exit(0);
+    }
+
+    // If we have been asked for the effective configuration, print it, and
+    // exit.
+    if matches.get_flag("PRINT_CONFIG") {
+        print!("{}", cli::effective_config(&matches, &config)?);
+
+        ::std::process::exit(0);
+    }
+
     // Root is required beyond this point.
     // Check that we're running as root.
     user::is_running_as_root(&mut UsersCache::new())?;
@@ -72,14 +132,248 @@ async fn main() -> Result<(), ExporterError> {
     // Check if RACCT/RCTL is available and if it's not, exit.
     racctrctl::is_available()?;
 
+    // Work out which collectors are enabled. Unknown collector names in the
+    // configuration are an error.
+    let collectors = cli::collectors(&matches, &config)?;
+
+    // Jail name include and exclude patterns.
+    let jail_filter = JailFilter::new(
+        matches.get_one::<Regex>("JAIL_INCLUDE").cloned(),
+        matches.get_one::<Regex>("JAIL_EXCLUDE").cloned(),
+    );
+
+    // Labels used to identify each jail's time series.
+    let jail_identity = *matches.get_one::<JailIdentity>("JAIL_IDENTITY")
+        .expect("jail.identity has a default");
+    debug!("jail.identity: {}", jail_identity);
+
+    // Smoothing of the CPU utilisation worked out from the cputime.
+    let cpu_smoothing = *matches.get_one::<f64>("COLLECTOR_CPUTIME_SMOOTHING")
+        .expect("collector.cputime.smoothing has a default");
+    debug!("collector.cputime.smoothing: {}", cpu_smoothing);
+
+    // Time that the CPU utilisation is worked out over.
+    let cpu_window = *matches.get_one::<Duration>("COLLECTOR_CPUTIME_WINDOW")
+        .expect("collector.cputime.window has a default");
+    debug!("collector.cputime.window: {:?}", cpu_window);
+
+    // Whether filesystems shared between jails are reported once.
+    let fs_deduplicate = matches.get_flag("COLLECTOR_FILESYSTEM_DEDUPLICATE");
+    debug!("collector.filesystem.deduplicate: {}", fs_deduplicate);
+
+    // Number of processes reported by RSS and CPU for each jail.
+    let processes_top_n = *matches.get_one::<u8>("COLLECTOR_PROCESSES_TOP_N")
+        .expect("collector.processes.top-n has a default");
+    debug!("collector.processes.top-n: {}", processes_top_n);
+
+    // Hierarchical jail labels, and how the usage of jails with children is
+    // reported.
+    let jail_hierarchy = matches.get_flag("JAIL_HIERARCHY");
+    let jail_usage = *matches.get_one::<JailUsage>("JAIL_USAGE")
+        .expect("jail.usage has a default");
+    debug!("jail.hierarchy: {}", jail_hierarchy);
+    debug!("jail.usage: {}", jail_usage);
+
+    // Labels attached to every time series, and labels taken from each
+    // jail's meta parameter.
+    let static_labels = config.static_labels()?;
+    let meta_labels = matches.get_flag("JAIL_META_LABELS");
+    let meta_labels_limit = *matches.get_one::<usize>("JAIL_META_LABELS_LIMIT")
+        .expect("jail.meta-labels-limit has a default");
+    debug!("jail.meta-labels: {}", meta_labels);
+    debug!("jail.meta-labels-limit: {}", meta_labels_limit);
+
+    // Configure the exporter used by both the file exporter and the HTTPd.
+    let exporter_metrics = !matches.get_flag("WEB_DISABLE_EXPORTER_METRICS");
+    debug!("web.disable-exporter-metrics: {}", !exporter_metrics);
+
+    let exporter = ExporterBuilder::new()
+        .collectors(collectors)
+        .cpu_smoothing(cpu_smoothing)
+        .cpu_window(cpu_window)
+        .exporter_metrics(exporter_metrics)
+        .filesystem_deduplicate(fs_deduplicate)
+        .jail_filter(jail_filter)
+        .jail_hierarchy(jail_hierarchy)
+        .jail_identity(jail_identity)
+        .jail_usage(jail_usage)
+        .meta_labels(meta_labels)
+        .meta_labels_limit(meta_labels_limit)
+        .processes_top_n(processes_top_n)
+        .static_labels(static_labels)
+        .build();
+
     // If an output file was specified, we do that. We will never launch the
     // HTTPd when we're passed an OUTPUT_FILE_PATH.
     if let Some(output_path) = matches.get_one::<FileExporterOutput>("OUTPUT_FILE_PATH") {
         debug!("output.file-path: {}", output_path);
 
-        let exporter = FileExporter::new(output_path.clone());
+        let output_format = *matches.get_one::<Format>("OUTPUT_FORMAT")
+            .expect("output.format has a default");
+        debug!("output.format: {}", output_format);
+
+        let mut file_exporter = FileExporter::new(output_path.clone())
+            .format(output_format);
+
+        if let Some(mode) = matches.get_one::<u32>("OUTPUT_FILE_MODE") {
+            debug!("output.file-mode: {:o}", mode);
+            file_exporter = file_exporter.mode(*mode);
+        }
+
+        if let Some(owner) = matches.get_one::<u32>("OUTPUT_FILE_OWNER") {
+            debug!("output.file-owner: {}", owner);
+            file_exporter = file_exporter.owner(*owner);
+        }
+
+        if let Some(group) = matches.get_one::<u32>("OUTPUT_FILE_GROUP") {
+            debug!("output.file-group: {}", group);
+            file_exporter = file_exporter.group(*group);
+        }
+
+        // With an interval we keep rewriting the file until we're stopped,
+        // otherwise we write it once and exit.
+        if let Some(interval) = matches.get_one::<Duration>("OUTPUT_INTERVAL") {
+            debug!("output.interval: {:?}", interval);
+
+            return file_exporter.run(&Arc::new(exporter), *interval).await;
+        }
 
-        return exporter.export();
+        return file_exporter.export(&exporter);
+    }
+
+    #[cfg(feature = "graphite")]
+    // If Graphite or StatsD was specified, we send to it instead of launching
+    // the HTTPd.
+    if let Some(graphite_url) = matches.get_one::<Url>("GRAPHITE_URL") {
+        debug!("graphite.url: {}", graphite_url);
+
+        let replacement = *matches.get_one::<char>("GRAPHITE_NAME_REPLACEMENT")
+            .expect("graphite.name-replacement has a default");
+        debug!("graphite.name-replacement: {}", replacement);
+
+        let retries = *matches.get_one::<u32>("GRAPHITE_RETRIES")
+            .expect("graphite.retries has a default");
+        debug!("graphite.retries: {}", retries);
+
+        let graphite = Graphite::new(graphite_url)
+            .name_replacement(replacement)
+            .retries(retries);
+
+        let interval = *matches.get_one::<Duration>("GRAPHITE_INTERVAL")
+            .expect("graphite.interval has a default");
+        debug!("graphite.interval: {:?}", interval);
+
+        return graphite.run(&Arc::new(exporter), interval).await;
+    }
+
+    #[cfg(feature = "otlp")]
+    // If an OTLP endpoint was specified, we send to it instead of launching
+    // the HTTPd.
+    if let Some(otlp_url) = matches.get_one::<Url>("OTLP_URL") {
+        debug!("otlp.url: {}", otlp_url);
+
+        let host_name = sysctl::Ctl::new(CTL_KERN_HOSTNAME)?.value_string()?;
+        debug!("host.name: {}", host_name);
+
+        let retries = *matches.get_one::<u32>("OTLP_RETRIES")
+            .expect("otlp.retries has a default");
+        debug!("otlp.retries: {}", retries);
+
+        let temporality = *matches.get_one::<Temporality>("OTLP_TEMPORALITY")
+            .expect("otlp.temporality has a default");
+        debug!("otlp.temporality: {}", temporality);
+
+        let mut otlp = Otlp::new(otlp_url.clone(), host_name)
+            .retries(retries)
+            .temporality(temporality);
+
+        if let Some(credentials) = cli::push_credentials(&matches, "OTLP") {
+            otlp = otlp.credentials(credentials);
+        }
+
+        let interval = *matches.get_one::<Duration>("OTLP_INTERVAL")
+            .expect("otlp.interval has a default");
+        debug!("otlp.interval: {:?}", interval);
+
+        return otlp.run(&Arc::new(exporter), interval).await;
+    }
+
+    #[cfg(feature = "pushgateway")]
+    // If a Pushgateway was specified, we push to it instead of launching the
+    // HTTPd.
+    if let Some(push_url) = matches.get_one::<Url>("PUSH_URL") {
+        debug!("push.url: {}", push_url);
+
+        let job = matches.get_one::<String>("PUSH_JOB")
+            .expect("push.job has a default");
+        debug!("push.job: {}", job);
+
+        let instance = match matches.get_one::<String>("PUSH_INSTANCE") {
+            Some(instance) => instance.clone(),
+            None           => {
+                sysctl::Ctl::new(CTL_KERN_HOSTNAME)?.value_string()?
+            },
+        };
+        debug!("push.instance: {}", instance);
+
+        let retries = *matches.get_one::<u32>("PUSH_RETRIES")
+            .expect("push.retries has a default");
+        debug!("push.retries: {}", retries);
+
+        let delete_on_shutdown = matches.get_flag("PUSH_DELETE_ON_SHUTDOWN");
+        debug!("push.delete-on-shutdown: {}", delete_on_shutdown);
+
+        let mut pushgateway = Pushgateway::new(push_url.clone())
+            .delete_on_shutdown(delete_on_shutdown)
+            .instance(instance)
+            .job(job.clone())
+            .retries(retries);
+
+        if let Some(credentials) = cli::push_credentials(&matches, "PUSH") {
+            pushgateway = pushgateway.credentials(credentials);
+        }
+
+        let interval = *matches.get_one::<Duration>("PUSH_INTERVAL")
+            .expect("push.interval has a default");
+        debug!("push.interval: {:?}", interval);
+
+        return pushgateway.run(&Arc::new(exporter), interval).await;
+    }
+
+    #[cfg(feature = "remote_write")]
+    // If a remote_write endpoint was specified, we send to it instead of
+    // launching the HTTPd.
+    if let Some(remote_write_url) = matches.get_one::<Url>("REMOTE_WRITE_URL") {
+        debug!("remote-write.url: {}", remote_write_url);
+
+        let external_labels: Vec<(String, String)> = matches
+            .get_many::<(String, String)>("REMOTE_WRITE_EXTERNAL_LABEL")
+            .map(|labels| labels.cloned().collect())
+            .unwrap_or_default();
+        debug!("remote-write.external-label: {:?}", external_labels);
+
+        let queue_size = *matches.get_one::<usize>("REMOTE_WRITE_QUEUE_SIZE")
+            .expect("remote-write.queue-size has a default");
+        debug!("remote-write.queue-size: {}", queue_size);
+
+        let retries = *matches.get_one::<u32>("REMOTE_WRITE_RETRIES")
+            .expect("remote-write.retries has a default");
+        debug!("remote-write.retries: {}", retries);
+
+        let mut remote_write = RemoteWrite::new(remote_write_url.clone())
+            .external_labels(external_labels)
+            .queue_size(queue_size)
+            .retries(retries);
+
+        if let Some(credentials) = cli::push_credentials(&matches, "REMOTE_WRITE") {
+            remote_write = remote_write.credentials(credentials);
+        }
+
+        let interval = *matches.get_one::<Duration>("REMOTE_WRITE_INTERVAL")
+            .expect("remote-write.interval has a default");
+        debug!("remote-write.interval: {:?}", interval);
+
+        return remote_write.run(&Arc::new(exporter), interval).await;
     }
 
     // Get the bind_address for the httpd::Server below.
@@ -102,23 +396,38 @@ async fn main() -> Result<(), ExporterError> {
 
     debug!("web.telemetry-path: {}", telemetry_path);
 
+    // Compression of metrics responses.
+    let compression = !matches.get_flag("WEB_DISABLE_COMPRESSION");
+    debug!("web.disable-compression: {}", !compression);
+
+    let compression_min_size = *matches.get_one::<u16>("WEB_COMPRESSION_MIN_SIZE")
+        .expect("web.compression-min-size has a default");
+    debug!("web.compression-min-size: {}", compression_min_size);
+
     // Start configuring HTTP server.
     // unused_mut here silences a warning if the crate is compiled without auth
     // support.
     #[allow(unused_mut)]
     let mut server = httpd::Server::new()
         .bind_address(bind_address)
+        .compression(compression)
+        .compression_min_size(compression_min_size)
         .telemetry_path(telemetry_path);
 
     #[cfg(feature = "auth")]
-    // Set the configuration file for HTTP Basic Auth
+    // Set the configuration for HTTP Basic Auth, from its own file or the
+    // users in the configuration file.
     if let Some(path) = matches.get_one::<PathBuf>("WEB_AUTH_CONFIG") {
         let config = BasicAuthConfig::from_yaml(path)?;
 
         server = server.auth_config(config);
     }
+    else if let Some(users) = &config.basic_auth_users {
+        let config = BasicAuthConfig::from_users(users.clone())?;
+
+        server = server.auth_config(config);
+    }
 
-    let exporter = Exporter::new();
     server.run(exporter).await?;
 
     Ok(())
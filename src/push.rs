@@ -0,0 +1,277 @@
+// push: Pushing metrics to remote receivers, for hosts that can't be scraped,
+// along with the loop that the push modes and the file exporter send on.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::shutdown::Shutdown;
+#[cfg(feature = "push_http")]
+use reqwest::{
+    Client,
+    RequestBuilder,
+    StatusCode,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::time::{
+    self,
+    MissedTickBehavior,
+};
+use tracing::info;
+#[cfg(feature = "push_http")]
+use tracing::{
+    debug,
+    warn,
+};
+
+#[cfg(feature = "push")]
+use crate::exporter::Exporter;
+#[cfg(feature = "push")]
+use std::sync::Arc;
+#[cfg(feature = "push")]
+use tokio::task;
+
+#[cfg(feature = "graphite")]
+mod graphite;
+
+#[cfg(feature = "otlp")]
+mod otlp;
+
+#[cfg(feature = "pushgateway")]
+mod pushgateway;
+
+#[cfg(feature = "remote_write")]
+mod remote_write;
+
+#[cfg(all(test, feature = "push_http"))]
+mod mock;
+
+#[cfg(feature = "graphite")]
+pub use graphite::{
+    is_path_safe,
+    Graphite,
+    Protocol,
+    DEFAULT_GRAPHITE_NAME_REPLACEMENT,
+};
+
+#[cfg(feature = "otlp")]
+pub use otlp::{
+    Otlp,
+    Temporality,
+};
+
+#[cfg(feature = "pushgateway")]
+pub use pushgateway::{
+    Pushgateway,
+    DEFAULT_PUSH_JOB,
+};
+
+#[cfg(feature = "remote_write")]
+pub use remote_write::{
+    RemoteWrite,
+    DEFAULT_REMOTE_WRITE_QUEUE_SIZE,
+};
+
+/// The default number of times a failed push is retried.
+#[cfg(feature = "push")]
+pub const DEFAULT_PUSH_RETRIES: u32 = 3;
+
+// Delay before the first retry, which is doubled for each retry after it.
+#[cfg(feature = "push")]
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+// Longest delay between retries.
+#[cfg(feature = "push")]
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// Longest time a single push may take, including reading the response.
+#[cfg(feature = "push")]
+const PUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Credentials used to authenticate with a remote receiver.
+#[cfg(feature = "push_http")]
+#[derive(Clone)]
+pub enum Credentials {
+    /// HTTP Basic authentication.
+    Basic {
+        /// The username.
+        username: String,
+
+        /// The password.
+        password: String,
+    },
+
+    /// A bearer token.
+    Bearer(String),
+}
+
+#[cfg(feature = "push_http")]
+impl Credentials {
+    /// Adds the credentials to a request.
+    pub fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Self::Basic { username, password } => {
+                request.basic_auth(username, Some(password))
+            },
+            Self::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// Returns the HTTP client used for pushes.
+#[cfg(feature = "push_http")]
+pub fn client() -> Client {
+    Client::builder()
+        .timeout(PUSH_TIMEOUT)
+        .user_agent(concat!("jail_exporter/", env!("CARGO_PKG_VERSION")))
+        .build()
+        .expect("TLS backend to initialise")
+}
+
+// Returns the delay before the given retry, counting from 0.
+#[cfg(feature = "push")]
+fn backoff(retry: u32) -> Duration {
+    INITIAL_BACKOFF
+        .checked_mul(2u32.saturating_pow(retry))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+// Returns a boolean indicating if a request answered with the given status
+// might succeed if sent again.
+#[cfg(feature = "push_http")]
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Sends the request made by `request` until it succeeds, retrying up to
+/// `retries` times with an exponential backoff. Requests rejected with a
+/// client error, other than 429 Too Many Requests, aren't retried since the
+/// same request would be rejected again.
+#[cfg(feature = "push_http")]
+pub async fn send_with_retries<F>(retries: u32, request: F) -> Result<(), ExporterError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut retry = 0;
+
+    loop {
+        let error = match request().send().await {
+            Ok(response) if response.status().is_success() => {
+                debug!("Push succeeded with status: {}", response.status());
+
+                return Ok(());
+            },
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                let error = format!("{status}: {}", body.trim());
+
+                if !is_retryable(status) {
+                    return Err(ExporterError::PushRejected(error));
+                }
+
+                ExporterError::PushError(error)
+            },
+            // Connection errors and timeouts are always worth retrying.
+            Err(e) => ExporterError::PushError(e.to_string()),
+        };
+
+        if retry >= retries {
+            return Err(error);
+        }
+
+        let delay = backoff(retry);
+        warn!("Push failed, retrying in {:?}: {}", delay, error);
+
+        time::sleep(delay).await;
+        retry += 1;
+    }
+}
+
+/// Calls `send` with `state` every interval until we receive SIGINT or
+/// SIGTERM, which also interrupt a call that is still running, such as one
+/// waiting to retry. `send` logs its own failures, so that they don't stop
+/// later calls. `name` describes what is being stopped in the log.
+pub async fn every<S, F>(
+    name: &str,
+    interval: Duration,
+    mut state: S,
+    mut send: F,
+) -> Result<(), ExporterError>
+where
+    F: FnMut(&mut S) -> Pin<Box<dyn Future<Output = ()> + '_>>,
+{
+    // Listen for signals before the first call, so that none are missed
+    // while it runs.
+    let mut shutdown = Shutdown::new()?;
+
+    // If a call overruns the interval, wait a full interval before the next
+    // one rather than catching up.
+    let mut ticker = time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {},
+            signal = shutdown.recv() => {
+                info!("Received {}, stopping {}", signal, name);
+                break;
+            },
+        }
+
+        tokio::select! {
+            () = send(&mut state) => {},
+            signal = shutdown.recv() => {
+                info!("Received {}, stopping {}", signal, name);
+                break;
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the metrics with `collect` on a thread set aside for blocking
+/// work, since the collectors run commands and make syscalls that block.
+#[cfg(feature = "push")]
+pub async fn collect<T, F>(
+    exporter: Arc<Exporter>,
+    collect: F,
+) -> Result<T, ExporterError>
+where
+    F: FnOnce(&Exporter) -> Result<T, ExporterError> + Send + 'static,
+    T: Send + 'static,
+{
+    task::spawn_blocking(move || collect(&exporter))
+        .await
+        .map_err(|e| ExporterError::JoinError(e.to_string()))?
+}
+
+#[cfg(all(test, feature = "push"))]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn backoff_doubles() {
+        assert_eq!(backoff(0), Duration::from_millis(500));
+        assert_eq!(backoff(1), Duration::from_secs(1));
+        assert_eq!(backoff(2), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn backoff_capped() {
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[cfg(feature = "push_http")]
+    #[test]
+    fn is_retryable_ok() {
+        assert!(is_retryable(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_retryable(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,537 @@
+// graphite: Sending the rctl usage of each jail to Graphite or StatsD.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::exporter::{
+    Exporter,
+    ResourceUsage,
+};
+use reqwest::Url;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{
+    lookup_host,
+    TcpStream,
+    UdpSocket,
+};
+use tokio::time;
+use tracing::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use super::{
+    backoff,
+    collect,
+    every,
+    DEFAULT_PUSH_RETRIES,
+    PUSH_TIMEOUT,
+};
+
+/// The default character that characters which aren't path safe are replaced
+/// with in jail names.
+pub const DEFAULT_GRAPHITE_NAME_REPLACEMENT: char = '_';
+
+// Default ports of the Graphite plaintext protocol and of StatsD.
+const GRAPHITE_PORT: u16 = 2003;
+const STATSD_PORT: u16 = 8125;
+
+// First node of every metric path.
+const GRAPHITE_PREFIX: &str = "jail";
+
+// Largest StatsD datagram sent, which avoids fragmentation on the common
+// Ethernet MTU of 1500 bytes.
+const MAX_DATAGRAM_SIZE: usize = 1432;
+
+/// The protocol that metrics are sent with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// The Graphite plaintext protocol, over TCP.
+    Plaintext,
+
+    /// StatsD gauges, over UDP.
+    Statsd,
+}
+
+impl Protocol {
+    /// Returns the protocol for the scheme of a Graphite URL, `tcp` for the
+    /// plaintext protocol or `udp` for StatsD.
+    pub fn from_scheme(scheme: &str) -> Option<Self> {
+        match scheme {
+            "tcp" => Some(Self::Plaintext),
+            "udp" => Some(Self::Statsd),
+            _     => None,
+        }
+    }
+
+    // Returns the port used when the URL doesn't have one.
+    fn default_port(self) -> u16 {
+        match self {
+            Self::Plaintext => GRAPHITE_PORT,
+            Self::Statsd    => STATSD_PORT,
+        }
+    }
+}
+
+/// Returns a boolean indicating if a character can be used in a Graphite
+/// metric path node as is.
+pub fn is_path_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+// Replaces the characters of a jail name that aren't path safe, such as the
+// dots of hierarchical jail names, which would otherwise add nodes to the
+// metric path.
+fn sanitise(name: &str, replacement: char) -> String {
+    name.chars()
+        .map(|c| if is_path_safe(c) { c } else { replacement })
+        .collect()
+}
+
+// Returns the time now in seconds since the Unix epoch.
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+// Returns the metric path and value of each resource used by each jail.
+// Jails are named by their name, or by their jid if names aren't exported.
+// Jails whose names are the same once sanitised, such as `a.b` and `a_b`,
+// would write over each other's paths, so only the first of them is sent.
+fn paths(usage: &[ResourceUsage], replacement: char) -> Vec<(String, i64)> {
+    let mut names: HashMap<String, &str> = HashMap::new();
+    let mut paths = Vec::new();
+
+    for jail in usage {
+        let Some(name) = jail.label("name").or_else(|| jail.label("jid")) else {
+            continue;
+        };
+
+        let sanitised = sanitise(name, replacement);
+
+        match names.entry(sanitised.clone()) {
+            Entry::Occupied(entry) => {
+                warn!(
+                    "Not sending jail {}, its Graphite name {} is taken by jail {}",
+                    name,
+                    sanitised,
+                    entry.get(),
+                );
+
+                continue;
+            },
+            Entry::Vacant(entry) => {
+                entry.insert(name);
+            },
+        }
+
+        for (resource, value) in &jail.usage {
+            let path = format!(
+                "{GRAPHITE_PREFIX}.{sanitised}.{}",
+                resource.as_str(),
+            );
+
+            paths.push((path, *value));
+        }
+    }
+
+    paths
+}
+
+// Returns the lines sending each path and value with the given protocol.
+fn lines(
+    protocol: Protocol,
+    paths: &[(String, i64)],
+    timestamp: u64,
+) -> Vec<String> {
+    paths
+        .iter()
+        .map(|(path, value)| {
+            match protocol {
+                Protocol::Plaintext => format!("{path} {value} {timestamp}\n"),
+                Protocol::Statsd    => format!("{path}:{value}|g\n"),
+            }
+        })
+        .collect()
+}
+
+// Packs the lines into as few datagrams as possible without going over
+// MAX_DATAGRAM_SIZE, unless a single line is larger than that.
+fn datagrams(lines: &[String]) -> Vec<String> {
+    let mut datagrams: Vec<String> = Vec::new();
+    let mut datagram = String::new();
+
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + line.len() > MAX_DATAGRAM_SIZE {
+            datagrams.push(std::mem::take(&mut datagram));
+        }
+
+        datagram.push_str(line);
+    }
+
+    if !datagram.is_empty() {
+        datagrams.push(datagram);
+    }
+
+    datagrams
+}
+
+/// Sends the rctl usage of each jail to Graphite, as `jail.<name>.<resource>`
+/// over the plaintext protocol, or to StatsD, as gauges of the same name.
+///
+/// Graphite has no way to say that it received the metrics, and StatsD is
+/// sent over UDP, so collections that can't be sent are dropped rather than
+/// queued.
+pub struct Graphite {
+    host:        String,
+    port:        u16,
+    protocol:    Protocol,
+    replacement: char,
+    retries:     u32,
+}
+
+impl Graphite {
+    /// Returns a sender to the given `tcp://` Graphite or `udp://` StatsD
+    /// URL, which must have a host.
+    pub fn new(url: &Url) -> Self {
+        debug!("New Graphite receiver at: {}", url);
+
+        let protocol = Protocol::from_scheme(url.scheme())
+            .expect("Graphite URL scheme to be validated");
+
+        let host = url.host_str()
+            .expect("Graphite URL host to be validated")
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+
+        Self {
+            host:        host,
+            port:        url.port().unwrap_or_else(|| protocol.default_port()),
+            protocol:    protocol,
+            replacement: DEFAULT_GRAPHITE_NAME_REPLACEMENT,
+            retries:     DEFAULT_PUSH_RETRIES,
+        }
+    }
+
+    /// Sets the character that characters which aren't path safe are
+    /// replaced with in jail names.
+    pub fn name_replacement(mut self, replacement: char) -> Self {
+        debug!("Setting Graphite name_replacement to: {}", replacement);
+
+        self.replacement = replacement;
+        self
+    }
+
+    /// Sets the number of times a failed send over TCP is retried.
+    pub fn retries(mut self, retries: u32) -> Self {
+        debug!("Setting Graphite retries to: {}", retries);
+
+        self.retries = retries;
+        self
+    }
+
+    // Resolves the receiver's address.
+    async fn address(&self) -> Result<SocketAddr, ExporterError> {
+        lookup_host((self.host.as_str(), self.port))
+            .await?
+            .next()
+            .ok_or_else(|| {
+                ExporterError::PushError(format!("{} has no addresses", self.host))
+            })
+    }
+
+    // Connects to Graphite and writes the lines, giving up after PUSH_TIMEOUT.
+    async fn send_tcp(&self, lines: &[String]) -> Result<(), ExporterError> {
+        let send = async {
+            let mut stream = TcpStream::connect(self.address().await?).await?;
+
+            for line in lines {
+                stream.write_all(line.as_bytes()).await?;
+            }
+
+            stream.shutdown().await?;
+
+            Ok(())
+        };
+
+        time::timeout(PUSH_TIMEOUT, send)
+            .await
+            .map_err(|_| ExporterError::PushError("timed out".into()))?
+    }
+
+    // Sends the lines to StatsD, packed into datagrams.
+    async fn send_udp(&self, lines: &[String]) -> Result<(), ExporterError> {
+        let address = self.address().await?;
+
+        let bind: SocketAddr = if address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        }
+        else {
+            ([0u16; 8], 0).into()
+        };
+
+        let socket = UdpSocket::bind(bind).await?;
+        socket.connect(address).await?;
+
+        for datagram in datagrams(lines) {
+            socket.send(datagram.as_bytes()).await?;
+        }
+
+        Ok(())
+    }
+
+    // Sends the rctl usage taken at the given timestamp. Sends over TCP are
+    // retried with an exponential backoff.
+    async fn send(
+        &self,
+        usage: &[ResourceUsage],
+        timestamp: u64,
+    ) -> Result<(), ExporterError> {
+        let paths = paths(usage, self.replacement);
+        let lines = lines(self.protocol, &paths, timestamp);
+
+        if lines.is_empty() {
+            debug!("No jail usage to send");
+
+            return Ok(());
+        }
+
+        if self.protocol == Protocol::Statsd {
+            return self.send_udp(&lines).await;
+        }
+
+        let mut retry = 0;
+
+        loop {
+            let error = match self.send_tcp(&lines).await {
+                Ok(()) => {
+                    debug!("Sent {} metrics to Graphite", lines.len());
+
+                    return Ok(());
+                },
+                Err(e) => e,
+            };
+
+            if retry >= self.retries {
+                return Err(error);
+            }
+
+            let delay = backoff(retry);
+            warn!("Send to Graphite failed, retrying in {:?}: {}", delay, error);
+
+            time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    /// Collects and sends the rctl usage every interval until we receive
+    /// SIGINT or SIGTERM.
+    pub async fn run(
+        &self,
+        exporter: &Arc<Exporter>,
+        interval: Duration,
+    ) -> Result<(), ExporterError> {
+        info!(
+            "Sending metrics to {}:{} every {:?}",
+            self.host,
+            self.port,
+            interval,
+        );
+
+        every("Graphite", interval, self, |graphite| {
+            let exporter = Arc::clone(exporter);
+
+            Box::pin(async move {
+                let usage = match collect(exporter, Exporter::usage).await {
+                    Ok(usage) => usage,
+                    Err(e)    => {
+                        error!("Failed to collect metrics: {}", e);
+                        return;
+                    },
+                };
+
+                if let Err(e) = graphite.send(&usage, now_secs()).await {
+                    error!("Dropping collection that couldn't be sent: {}", e);
+                }
+            })
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rctl::Resource;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    fn usage() -> Vec<ResourceUsage> {
+        vec![
+            ResourceUsage {
+                labels: vec![
+                    ("depth".into(), "1".into()),
+                    ("name".into(), "host.web 1".into()),
+                ],
+                usage:  vec![
+                    (Resource::CpuTime, 3600),
+                    (Resource::MemoryUse, 268_435_456),
+                ],
+            },
+            ResourceUsage {
+                labels: vec![("jid".into(), "5".into())],
+                usage:  vec![(Resource::MaxProcesses, 2)],
+            },
+            ResourceUsage {
+                labels: vec![("env".into(), "prod".into())],
+                usage:  vec![(Resource::MaxProcesses, 3)],
+            },
+        ]
+    }
+
+    #[test]
+    fn sanitise_ok() {
+        assert_eq!(sanitise("web-1_a", '_'), "web-1_a");
+        assert_eq!(sanitise("host.web 1", '_'), "host_web_1");
+        assert_eq!(sanitise("host.web", '-'), "host-web");
+        assert_eq!(sanitise("wéb", '_'), "w_b");
+    }
+
+    #[test]
+    fn lines_plaintext() {
+        let paths = paths(&usage(), '_');
+        let lines = lines(Protocol::Plaintext, &paths, 1_700_000_000);
+
+        assert_eq!(lines, vec![
+            "jail.host_web_1.cputime 3600 1700000000\n",
+            "jail.host_web_1.memoryuse 268435456 1700000000\n",
+            "jail.5.maxproc 2 1700000000\n",
+        ]);
+    }
+
+    #[test]
+    fn lines_statsd() {
+        let paths = paths(&usage(), '-');
+        let lines = lines(Protocol::Statsd, &paths, 1_700_000_000);
+
+        assert_eq!(lines, vec![
+            "jail.host-web-1.cputime:3600|g\n",
+            "jail.host-web-1.memoryuse:268435456|g\n",
+            "jail.5.maxproc:2|g\n",
+        ]);
+    }
+
+    #[test]
+    fn paths_colliding_names() {
+        let usage = vec![
+            ResourceUsage {
+                labels: vec![("name".into(), "a.b".into())],
+                usage:  vec![(Resource::MaxProcesses, 2)],
+            },
+            ResourceUsage {
+                labels: vec![("name".into(), "a_b".into())],
+                usage:  vec![(Resource::MaxProcesses, 3)],
+            },
+        ];
+
+        assert_eq!(paths(&usage, '_'), vec![
+            ("jail.a_b.maxproc".to_string(), 2),
+        ]);
+    }
+
+    #[test]
+    fn datagrams_split() {
+        let line = format!("{}\n", "a".repeat(99));
+        let lines = vec![line.clone(); 20];
+
+        let datagrams = datagrams(&lines);
+
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0], line.repeat(14));
+        assert_eq!(datagrams[1], line.repeat(6));
+    }
+
+    #[test]
+    fn new_default_port() {
+        let url = Url::parse("udp://[::1]").unwrap();
+        let graphite = Graphite::new(&url);
+
+        assert_eq!(graphite.protocol, Protocol::Statsd);
+        assert_eq!(graphite.host, "::1");
+        assert_eq!(graphite.port, STATSD_PORT);
+    }
+
+    #[tokio::test]
+    async fn send_tcp_ok() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = String::new();
+            stream.read_to_string(&mut received).await.unwrap();
+
+            received
+        });
+
+        let url = Url::parse(&format!("tcp://{address}")).unwrap();
+        let graphite = Graphite::new(&url);
+
+        graphite.send(&usage(), 1_700_000_000).await.unwrap();
+
+        assert_eq!(received.await.unwrap(), concat!(
+            "jail.host_web_1.cputime 3600 1700000000\n",
+            "jail.host_web_1.memoryuse 268435456 1700000000\n",
+            "jail.5.maxproc 2 1700000000\n",
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_tcp_unavailable() {
+        // Bind and drop a listener to find a port that nothing listens on.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let url = Url::parse(&format!("tcp://{address}")).unwrap();
+        let graphite = Graphite::new(&url)
+            .retries(0);
+
+        let res = graphite.send(&usage(), 1_700_000_000).await;
+
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn send_udp_ok() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        let url = Url::parse(&format!("udp://{address}")).unwrap();
+        let graphite = Graphite::new(&url)
+            .name_replacement('-');
+
+        graphite.send(&usage(), 1_700_000_000).await.unwrap();
+
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        let size = socket.recv(&mut buffer).await.unwrap();
+
+        assert_eq!(std::str::from_utf8(&buffer[..size]).unwrap(), concat!(
+            "jail.host-web-1.cputime:3600|g\n",
+            "jail.host-web-1.memoryuse:268435456|g\n",
+            "jail.5.maxproc:2|g\n",
+        ));
+    }
+}
@@ -0,0 +1,108 @@
+// mock: A mock receiver that the push modes are tested against.
+#![forbid(unsafe_code)]
+// Each push mode uses a different part of the mock, and any of them may be
+// disabled.
+#![allow(dead_code)]
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{
+    HeaderMap,
+    Method,
+    StatusCode,
+    Uri,
+};
+use axum::Router;
+use parking_lot::Mutex;
+use reqwest::Url;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+// A request received by the mock receiver.
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub method:  Method,
+    pub path:    String,
+    pub headers: HeaderMap,
+    pub body:    Bytes,
+}
+
+impl Received {
+    // Returns the value of a header, if it was sent.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    // Returns the body as a string.
+    pub fn body_str(&self) -> &str {
+        std::str::from_utf8(&self.body).unwrap()
+    }
+}
+
+// The statuses to answer with in turn, falling back to 200, and the requests
+// received.
+#[derive(Default)]
+struct Mock {
+    statuses: Vec<StatusCode>,
+    received: Vec<Received>,
+}
+
+// A handle to a running mock receiver.
+#[derive(Clone)]
+pub struct MockReceiver(Arc<Mutex<Mock>>);
+
+async fn handler(
+    State(mock): State<MockReceiver>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let mut mock = mock.0.lock();
+
+    mock.received.push(Received {
+        method:  method,
+        path:    uri.path().to_string(),
+        headers: headers,
+        body:    body,
+    });
+
+    if mock.statuses.is_empty() {
+        StatusCode::OK
+    }
+    else {
+        mock.statuses.remove(0)
+    }
+}
+
+impl MockReceiver {
+    // Starts a mock receiver on a random local port, answering with the
+    // given statuses before answering with 200.
+    pub async fn start(statuses: Vec<StatusCode>) -> (Url, Self) {
+        let mock = Self(Arc::new(Mutex::new(Mock {
+            statuses: statuses,
+            received: Vec::new(),
+        })));
+
+        let app = Router::new()
+            .fallback(handler)
+            .with_state(mock.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+
+        let url = Url::parse(&format!("http://{address}/")).unwrap();
+
+        (url, mock)
+    }
+
+    // Returns the requests received so far.
+    pub fn received(&self) -> Vec<Received> {
+        self.0.lock().received.clone()
+    }
+}
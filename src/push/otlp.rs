@@ -0,0 +1,870 @@
+// otlp: Sending metrics to an OpenTelemetry collector over OTLP/HTTP.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::exporter::{
+    counter_family_names,
+    Exporter,
+    MetricFamily,
+    MetricSample,
+};
+use prost::Message;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{
+    Client,
+    RequestBuilder,
+    Url,
+};
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+use tracing::{
+    debug,
+    error,
+    info,
+};
+use super::{
+    client,
+    collect,
+    every,
+    send_with_retries,
+    Credentials,
+    DEFAULT_PUSH_RETRIES,
+};
+
+// Content type of OTLP/HTTP requests encoded as protobuf.
+const OTLP_CONTENT_TYPE: &str = "application/x-protobuf";
+
+// Name of the service and instrumentation scope that metrics are sent as.
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+const SERVICE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A key and string value, as defined by
+/// `opentelemetry.proto.common.v1.KeyValue`.
+#[derive(Clone, PartialEq, Message)]
+struct KeyValue {
+    /// The key.
+    #[prost(string, tag = "1")]
+    key: String,
+
+    /// The value.
+    #[prost(message, optional, tag = "2")]
+    value: Option<AnyValue>,
+}
+
+/// A value, as defined by `opentelemetry.proto.common.v1.AnyValue`. Only
+/// string values are sent.
+#[derive(Clone, PartialEq, Message)]
+struct AnyValue {
+    /// The string value.
+    #[prost(string, tag = "1")]
+    string_value: String,
+}
+
+/// The entity producing the metrics, as defined by
+/// `opentelemetry.proto.resource.v1.Resource`.
+#[derive(Clone, PartialEq, Message)]
+struct Resource {
+    /// The attributes of the resource, such as `host.name`.
+    #[prost(message, repeated, tag = "1")]
+    attributes: Vec<KeyValue>,
+}
+
+/// The library producing the metrics, as defined by
+/// `opentelemetry.proto.common.v1.InstrumentationScope`.
+#[derive(Clone, PartialEq, Message)]
+struct InstrumentationScope {
+    /// The name of the scope.
+    #[prost(string, tag = "1")]
+    name: String,
+
+    /// The version of the scope.
+    #[prost(string, tag = "2")]
+    version: String,
+}
+
+/// How the values of a sum relate to each other, as defined by
+/// `opentelemetry.proto.metrics.v1.AggregationTemporality`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum AggregationTemporality {
+    /// Each value is the change since the previous value.
+    Delta = 1,
+
+    /// Each value is the total since a fixed start time.
+    Cumulative = 2,
+}
+
+/// A data point, as defined by
+/// `opentelemetry.proto.metrics.v1.NumberDataPoint`.
+#[derive(Clone, PartialEq, Message)]
+struct NumberDataPoint {
+    /// The time that the value is counted from, in nanoseconds since the
+    /// Unix epoch. Only used by sums.
+    #[prost(fixed64, tag = "2")]
+    start_time_unix_nano: u64,
+
+    /// The time of the value, in nanoseconds since the Unix epoch.
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+
+    /// The value.
+    #[prost(double, tag = "4")]
+    as_double: f64,
+
+    /// The attributes telling the data points of a metric apart.
+    #[prost(message, repeated, tag = "7")]
+    attributes: Vec<KeyValue>,
+}
+
+/// A gauge, as defined by `opentelemetry.proto.metrics.v1.Gauge`.
+#[derive(Clone, PartialEq, Message)]
+struct Gauge {
+    /// The data points of the gauge.
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+}
+
+/// A sum, as defined by `opentelemetry.proto.metrics.v1.Sum`.
+#[derive(Clone, PartialEq, Message)]
+struct Sum {
+    /// The data points of the sum.
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+
+    /// How the values of the data points relate to each other.
+    #[prost(enumeration = "AggregationTemporality", tag = "2")]
+    aggregation_temporality: i32,
+
+    /// Whether the sum only ever increases.
+    #[prost(bool, tag = "3")]
+    is_monotonic: bool,
+}
+
+/// The data of a metric. Only the kinds of data that we send are present.
+#[derive(Clone, PartialEq, prost::Oneof)]
+enum Data {
+    /// A gauge.
+    #[prost(message, tag = "5")]
+    Gauge(Gauge),
+
+    /// A sum.
+    #[prost(message, tag = "7")]
+    Sum(Sum),
+}
+
+/// A metric, as defined by `opentelemetry.proto.metrics.v1.Metric`.
+#[derive(Clone, PartialEq, Message)]
+struct Metric {
+    /// The name of the metric.
+    #[prost(string, tag = "1")]
+    name: String,
+
+    /// The description of the metric.
+    #[prost(string, tag = "2")]
+    description: String,
+
+    /// The UCUM unit of the metric, such as `By`.
+    #[prost(string, tag = "3")]
+    unit: String,
+
+    /// The data of the metric.
+    #[prost(oneof = "Data", tags = "5, 7")]
+    data: Option<Data>,
+}
+
+/// The metrics of an instrumentation scope, as defined by
+/// `opentelemetry.proto.metrics.v1.ScopeMetrics`.
+#[derive(Clone, PartialEq, Message)]
+struct ScopeMetrics {
+    /// The scope producing the metrics.
+    #[prost(message, optional, tag = "1")]
+    scope: Option<InstrumentationScope>,
+
+    /// The metrics.
+    #[prost(message, repeated, tag = "2")]
+    metrics: Vec<Metric>,
+}
+
+/// The metrics of a resource, as defined by
+/// `opentelemetry.proto.metrics.v1.ResourceMetrics`.
+#[derive(Clone, PartialEq, Message)]
+struct ResourceMetrics {
+    /// The resource producing the metrics.
+    #[prost(message, optional, tag = "1")]
+    resource: Option<Resource>,
+
+    /// The metrics, by scope.
+    #[prost(message, repeated, tag = "2")]
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+/// An export request, as defined by
+/// `opentelemetry.proto.collector.metrics.v1.ExportMetricsServiceRequest`.
+#[derive(Clone, PartialEq, Message)]
+struct ExportMetricsServiceRequest {
+    /// The metrics, by resource.
+    #[prost(message, repeated, tag = "1")]
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+/// The temporality that the rctl counters, `cputime` and `wallclock`, are
+/// sent with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Temporality {
+    /// Each value is the total since the series was first seen or reset.
+    #[default]
+    Cumulative,
+
+    /// Each value is the change since the previous collection.
+    Delta,
+}
+
+impl fmt::Display for Temporality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let temporality = match self {
+            Self::Cumulative => "cumulative",
+            Self::Delta      => "delta",
+        };
+
+        write!(f, "{temporality}")
+    }
+}
+
+impl FromStr for Temporality {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cumulative" => Ok(Self::Cumulative),
+            "delta"      => Ok(Self::Delta),
+            _            => Err(format!("unknown temporality: {s}")),
+        }
+    }
+}
+
+// Identifies a series between collections, by its sample name and labels.
+type SeriesKey = (String, Vec<(String, String)>);
+
+// The counters of the last collection, which the deltas and start times of
+// the next collection are worked out from.
+struct Counters {
+    // When the counters were collected, or when we started if nothing has
+    // been collected yet.
+    time: u64,
+
+    // The value of each series, and the time that its cumulative value is
+    // counted from.
+    series: HashMap<SeriesKey, (f64, u64)>,
+}
+
+// Returns the time now in nanoseconds since the Unix epoch.
+fn now_nanos() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    u64::try_from(now.as_nanos()).unwrap_or(u64::MAX)
+}
+
+// Returns the UCUM unit for an OpenMetrics unit.
+fn unit(unit: Option<&str>) -> String {
+    match unit {
+        Some("bytes")   => "By".into(),
+        Some("seconds") => "s".into(),
+        Some("ratio")   => "1".into(),
+        Some(unit)      => unit.into(),
+        None            => String::new(),
+    }
+}
+
+// Returns a key and string value.
+fn key_value(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key:   key.into(),
+        value: Some(AnyValue {
+            string_value: value.into(),
+        }),
+    }
+}
+
+// Returns the attributes of a sample. Empty label values are the same as the
+// label being absent, so they aren't sent.
+fn attributes(sample: &MetricSample) -> Vec<KeyValue> {
+    sample.labels
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| key_value(name, value))
+        .collect()
+}
+
+/// Sends metrics to an OpenTelemetry collector over OTLP/HTTP, encoded as
+/// protobuf.
+///
+/// Gauges are sent as OTel gauges and counters as monotonic sums, with UCUM
+/// units. Histograms and summaries aren't sent. The rctl counters can be sent
+/// with delta temporality, in which case the first collection of each series
+/// only sets its starting point.
+pub struct Otlp {
+    client:      Client,
+    credentials: Option<Credentials>,
+    delta:       HashSet<String>,
+    host_name:   String,
+    previous:    Counters,
+    retries:     u32,
+    temporality: Temporality,
+    url:         Url,
+}
+
+impl Otlp {
+    /// Returns an OTLP exporter sending to the given metrics endpoint URL,
+    /// such as `http://collector:4318/v1/metrics`, as `host_name`.
+    pub fn new(url: Url, host_name: String) -> Self {
+        debug!("New OTLP endpoint at: {}", url);
+
+        Self {
+            client:      client(),
+            credentials: None,
+            delta:       counter_family_names().into_iter().collect(),
+            host_name:   host_name,
+            previous:    Counters {
+                time:   now_nanos(),
+                series: HashMap::new(),
+            },
+            retries:     DEFAULT_PUSH_RETRIES,
+            temporality: Temporality::default(),
+            url:         url,
+        }
+    }
+
+    /// Sets the credentials used to authenticate with the endpoint.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        debug!("Setting OTLP credentials");
+
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Sets the number of times a failed export request is retried.
+    pub fn retries(mut self, retries: u32) -> Self {
+        debug!("Setting OTLP retries to: {}", retries);
+
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the temporality of the rctl counters.
+    pub fn temporality(mut self, temporality: Temporality) -> Self {
+        debug!("Setting OTLP temporality to: {}", temporality);
+
+        self.temporality = temporality;
+        self
+    }
+
+    // Returns the data points of a sum taken at the given time, and records
+    // the value and start time of each series in `counters`. With delta
+    // temporality, each value is the change since the previous collection,
+    // and series seen for the first time have no data point.
+    fn sum_data_points(
+        &self,
+        family: &MetricFamily,
+        delta: bool,
+        now: u64,
+        counters: &mut Counters,
+    ) -> Vec<NumberDataPoint> {
+        family.samples
+            .iter()
+            .filter_map(|sample| {
+                let key = (sample.name.clone(), sample.labels.clone());
+                let last = self.previous.series.get(&key).copied();
+
+                // A value lower than the last means that the counter was
+                // reset, such as by the jail restarting. Series that are new
+                // or were reset are counted from the previous collection, or
+                // from when we started if there wasn't one.
+                let start = last
+                    .filter(|(last_value, _)| sample.value >= *last_value)
+                    .map_or(self.previous.time, |(_, start)| start);
+
+                counters.series.insert(key, (sample.value, start));
+
+                if !delta {
+                    return Some(NumberDataPoint {
+                        start_time_unix_nano: start,
+                        time_unix_nano:       now,
+                        as_double:            sample.value,
+                        attributes:           attributes(sample),
+                    });
+                }
+
+                let (last_value, _) = last?;
+
+                // After a reset, all of the value is new.
+                let value = if sample.value >= last_value {
+                    sample.value - last_value
+                }
+                else {
+                    sample.value
+                };
+
+                Some(NumberDataPoint {
+                    start_time_unix_nano: self.previous.time,
+                    time_unix_nano:       now,
+                    as_double:            value,
+                    attributes:           attributes(sample),
+                })
+            })
+            .collect()
+    }
+
+    // Builds the export request for the families collected at the given time,
+    // along with the counters to remember once it has been sent.
+    fn export_request(
+        &self,
+        families: &[MetricFamily],
+        now: u64,
+    ) -> (ExportMetricsServiceRequest, Counters) {
+        // Only the series still present are kept, so that jails which have
+        // gone away are forgotten.
+        let mut counters = Counters {
+            time:   now,
+            series: HashMap::new(),
+        };
+        let mut metrics = Vec::new();
+
+        for family in families {
+            let data = match family.metric_type.as_str() {
+                "counter" => {
+                    let delta = self.temporality == Temporality::Delta
+                        && self.delta.contains(&family.name);
+
+                    let temporality = if delta {
+                        AggregationTemporality::Delta
+                    }
+                    else {
+                        AggregationTemporality::Cumulative
+                    };
+
+                    Data::Sum(Sum {
+                        data_points:             self.sum_data_points(
+                            family,
+                            delta,
+                            now,
+                            &mut counters,
+                        ),
+                        aggregation_temporality: temporality as i32,
+                        is_monotonic:            true,
+                    })
+                },
+                "gauge" | "info" | "stateset" | "unknown" => {
+                    let data_points = family.samples
+                        .iter()
+                        .map(|sample| {
+                            NumberDataPoint {
+                                start_time_unix_nano: 0,
+                                time_unix_nano:       now,
+                                as_double:            sample.value,
+                                attributes:           attributes(sample),
+                            }
+                        })
+                        .collect();
+
+                    Data::Gauge(Gauge {
+                        data_points: data_points,
+                    })
+                },
+                metric_type => {
+                    debug!("Not sending {} family: {}", metric_type, family.name);
+                    continue;
+                },
+            };
+
+            let empty = match &data {
+                Data::Gauge(gauge) => gauge.data_points.is_empty(),
+                Data::Sum(sum)     => sum.data_points.is_empty(),
+            };
+
+            if empty {
+                continue;
+            }
+
+            metrics.push(Metric {
+                name:        family.name.clone(),
+                description: family.help.clone(),
+                unit:        unit(family.unit.as_deref()),
+                data:        Some(data),
+            });
+        }
+
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![
+                ResourceMetrics {
+                    resource:      Some(Resource {
+                        attributes: vec![
+                            key_value("host.name", &self.host_name),
+                            key_value("service.name", SERVICE_NAME),
+                            key_value("service.version", SERVICE_VERSION),
+                        ],
+                    }),
+                    scope_metrics: vec![
+                        ScopeMetrics {
+                            scope:   Some(InstrumentationScope {
+                                name:    SERVICE_NAME.into(),
+                                version: SERVICE_VERSION.into(),
+                            }),
+                            metrics: metrics,
+                        },
+                    ],
+                },
+            ],
+        };
+
+        (request, counters)
+    }
+
+    // Returns a request sending the given body, with any credentials.
+    fn request(&self, body: &[u8]) -> RequestBuilder {
+        let request = self.client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, OTLP_CONTENT_TYPE)
+            .body(body.to_vec());
+
+        match &self.credentials {
+            Some(credentials) => credentials.apply(request),
+            None              => request,
+        }
+    }
+
+    // Sends the families collected at the given time.
+    async fn export(
+        &mut self,
+        families: &[MetricFamily],
+        now: u64,
+    ) -> Result<(), ExporterError> {
+        let (request, counters) = self.export_request(families, now);
+        let body = request.encode_to_vec();
+
+        send_with_retries(self.retries, || self.request(&body)).await?;
+
+        // The counters only move on once the collection has been sent, so
+        // that the next delta also covers any collection that was dropped.
+        self.previous = counters;
+
+        Ok(())
+    }
+
+    /// Collects and sends the metrics every interval until we receive SIGINT
+    /// or SIGTERM. Collections that can't be sent are dropped.
+    pub async fn run(
+        &mut self,
+        exporter: &Arc<Exporter>,
+        interval: Duration,
+    ) -> Result<(), ExporterError> {
+        info!("Sending metrics to {} every {:?}", self.url, interval);
+
+        every("OTLP export", interval, self, |otlp| {
+            let exporter = Arc::clone(exporter);
+
+            Box::pin(async move {
+                let families = match collect(exporter, Exporter::families).await {
+                    Ok(families) => families,
+                    Err(e)       => {
+                        error!("Failed to collect metrics: {}", e);
+                        return;
+                    },
+                };
+
+                if let Err(e) = otlp.export(&families, now_nanos()).await {
+                    error!("Dropping collection that couldn't be sent: {}", e);
+                }
+            })
+        }).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{
+        Method,
+        StatusCode,
+    };
+    use crate::push::mock::MockReceiver;
+    use pretty_assertions::assert_eq;
+
+    fn url() -> Url {
+        Url::parse("http://collector:4318/v1/metrics").unwrap()
+    }
+
+    fn families(cputime: f64) -> Vec<MetricFamily> {
+        vec![
+            MetricFamily {
+                name:        "jail_cputime_seconds".into(),
+                help:        "CPU time, in seconds.".into(),
+                metric_type: "counter".into(),
+                unit:        Some("seconds".into()),
+                samples:     vec![
+                    MetricSample {
+                        name:   "jail_cputime_seconds_total".into(),
+                        labels: vec![
+                            ("name".into(), "web".into()),
+                            ("env".into(), "".into()),
+                        ],
+                        value:  cputime,
+                    },
+                ],
+            },
+            MetricFamily {
+                name:        "jail_memoryuse_bytes".into(),
+                help:        "resident set size, in bytes.".into(),
+                metric_type: "gauge".into(),
+                unit:        Some("bytes".into()),
+                samples:     vec![
+                    MetricSample {
+                        name:   "jail_memoryuse_bytes".into(),
+                        labels: vec![("name".into(), "web".into())],
+                        value:  1024.0,
+                    },
+                ],
+            },
+            MetricFamily {
+                name:        "jail_exporter_scrape_duration_seconds".into(),
+                help:        "Duration of scrapes.".into(),
+                metric_type: "histogram".into(),
+                unit:        Some("seconds".into()),
+                samples:     vec![
+                    MetricSample {
+                        name:   "jail_exporter_scrape_duration_seconds_count".into(),
+                        labels: vec![],
+                        value:  1.0,
+                    },
+                ],
+            },
+        ]
+    }
+
+    // Returns the metrics of an export request.
+    fn metrics(request: &ExportMetricsServiceRequest) -> &[Metric] {
+        &request.resource_metrics[0].scope_metrics[0].metrics
+    }
+
+    // Builds the export request for the families as if it had been sent.
+    fn sent(
+        otlp: &mut Otlp,
+        families: &[MetricFamily],
+        now: u64,
+    ) -> ExportMetricsServiceRequest {
+        let (request, counters) = otlp.export_request(families, now);
+        otlp.previous = counters;
+
+        request
+    }
+
+    // Returns the sum of a metric.
+    fn sum(metric: &Metric) -> &Sum {
+        match &metric.data {
+            Some(Data::Sum(sum)) => sum,
+            data                 => panic!("expected a sum, got {data:?}"),
+        }
+    }
+
+    #[test]
+    fn temporality_from_str() {
+        assert_eq!(Temporality::from_str("delta"), Ok(Temporality::Delta));
+        assert_eq!(
+            Temporality::from_str("cumulative"),
+            Ok(Temporality::Cumulative),
+        );
+        assert!(Temporality::from_str("both").is_err());
+    }
+
+    #[test]
+    fn unit_ok() {
+        assert_eq!(unit(Some("bytes")), "By");
+        assert_eq!(unit(Some("seconds")), "s");
+        assert_eq!(unit(Some("celsius")), "celsius");
+        assert_eq!(unit(None), "");
+    }
+
+    #[test]
+    fn export_request_cumulative() {
+        let mut otlp = Otlp::new(url(), "jailhost".into());
+        otlp.previous.time = 1000;
+
+        let request = sent(&mut otlp, &families(12.5), 2000);
+        let resource_metrics = &request.resource_metrics[0];
+
+        assert_eq!(resource_metrics.resource, Some(Resource {
+            attributes: vec![
+                key_value("host.name", "jailhost"),
+                key_value("service.name", "jail_exporter"),
+                key_value("service.version", env!("CARGO_PKG_VERSION")),
+            ],
+        }));
+
+        // The histogram isn't sent.
+        assert_eq!(metrics(&request), [
+            Metric {
+                name:        "jail_cputime_seconds".into(),
+                description: "CPU time, in seconds.".into(),
+                unit:        "s".into(),
+                data:        Some(Data::Sum(Sum {
+                    data_points:             vec![
+                        NumberDataPoint {
+                            start_time_unix_nano: 1000,
+                            time_unix_nano:       2000,
+                            as_double:            12.5,
+                            attributes:           vec![key_value("name", "web")],
+                        },
+                    ],
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic:            true,
+                })),
+            },
+            Metric {
+                name:        "jail_memoryuse_bytes".into(),
+                description: "resident set size, in bytes.".into(),
+                unit:        "By".into(),
+                data:        Some(Data::Gauge(Gauge {
+                    data_points: vec![
+                        NumberDataPoint {
+                            start_time_unix_nano: 0,
+                            time_unix_nano:       2000,
+                            as_double:            1024.0,
+                            attributes:           vec![key_value("name", "web")],
+                        },
+                    ],
+                })),
+            },
+        ]);
+    }
+
+    #[test]
+    fn export_request_cumulative_start_times() {
+        let mut otlp = Otlp::new(url(), "jailhost".into());
+        otlp.previous.time = 1000;
+
+        // Returns the start time of each series of the cputime sum.
+        let mut start_times = |samples: &[(&str, f64)], now: u64| {
+            let mut families = families(0.0);
+            families[0].samples = samples
+                .iter()
+                .map(|(name, value)| {
+                    MetricSample {
+                        name:   "jail_cputime_seconds_total".into(),
+                        labels: vec![("name".into(), name.to_string())],
+                        value:  *value,
+                    }
+                })
+                .collect();
+
+            let request = sent(&mut otlp, &families, now);
+
+            sum(&metrics(&request)[0]).data_points
+                .iter()
+                .map(|point| point.start_time_unix_nano)
+                .collect::<Vec<_>>()
+        };
+
+        // Series present from the first collection are counted from when
+        // we started.
+        assert_eq!(start_times(&[("web", 10.0)], 2000), vec![1000]);
+
+        // A new series is counted from the previous collection.
+        assert_eq!(start_times(&[("web", 12.0), ("db", 1.0)], 3000), vec![1000, 2000]);
+
+        // As is one that was reset.
+        assert_eq!(start_times(&[("web", 1.0), ("db", 2.0)], 4000), vec![3000, 2000]);
+    }
+
+    #[test]
+    fn export_request_delta() {
+        let mut otlp = Otlp::new(url(), "jailhost".into())
+            .temporality(Temporality::Delta);
+
+        // The first collection only sets the starting point.
+        let request = sent(&mut otlp, &families(10.0), 1000);
+        assert_eq!(metrics(&request).len(), 1);
+        assert_eq!(metrics(&request)[0].name, "jail_memoryuse_bytes");
+
+        let request = sent(&mut otlp, &families(12.5), 2000);
+        let cputime = sum(&metrics(&request)[0]);
+
+        assert_eq!(
+            cputime.aggregation_temporality,
+            AggregationTemporality::Delta as i32,
+        );
+        assert_eq!(cputime.data_points[0].start_time_unix_nano, 1000);
+        assert_eq!(cputime.data_points[0].time_unix_nano, 2000);
+        assert_eq!(cputime.data_points[0].as_double, 2.5);
+
+        // A lower value means that the counter was reset.
+        let request = sent(&mut otlp, &families(1.0), 3000);
+        let cputime = sum(&metrics(&request)[0]);
+
+        assert_eq!(cputime.data_points[0].start_time_unix_nano, 2000);
+        assert_eq!(cputime.data_points[0].as_double, 1.0);
+    }
+
+    #[tokio::test]
+    async fn export_ok() {
+        let (url, mock) = MockReceiver::start(vec![]).await;
+        let url = url.join("v1/metrics").unwrap();
+        let mut otlp = Otlp::new(url, "jailhost".into())
+            .credentials(Credentials::Bearer("token".into()));
+
+        otlp.export(&families(12.5), 2000).await.unwrap();
+
+        let received = mock.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, Method::POST);
+        assert_eq!(received[0].path, "/v1/metrics");
+        assert_eq!(received[0].header("authorization"), Some("Bearer token"));
+        assert_eq!(
+            received[0].header("content-type"),
+            Some("application/x-protobuf"),
+        );
+
+        let request = ExportMetricsServiceRequest::decode(&*received[0].body)
+            .unwrap();
+
+        assert_eq!(metrics(&request).len(), 2);
+        assert_eq!(sum(&metrics(&request)[0]).data_points[0].as_double, 12.5);
+    }
+
+    #[tokio::test]
+    async fn export_dropped_delta() {
+        let (url, mock) = MockReceiver::start(vec![
+            StatusCode::OK,
+            StatusCode::BAD_REQUEST,
+        ]).await;
+        let url = url.join("v1/metrics").unwrap();
+        let mut otlp = Otlp::new(url, "jailhost".into())
+            .temporality(Temporality::Delta);
+
+        otlp.export(&families(10.0), 1000).await.unwrap();
+        assert!(otlp.export(&families(12.5), 2000).await.is_err());
+        otlp.export(&families(15.0), 3000).await.unwrap();
+
+        let received = mock.received();
+        assert_eq!(received.len(), 3);
+
+        // The dropped collection is covered by the next delta.
+        let request = ExportMetricsServiceRequest::decode(&*received[2].body)
+            .unwrap();
+        let cputime = sum(&metrics(&request)[0]);
+
+        assert_eq!(cputime.data_points[0].start_time_unix_nano, 1000);
+        assert_eq!(cputime.data_points[0].time_unix_nano, 3000);
+        assert_eq!(cputime.data_points[0].as_double, 5.0);
+    }
+}
@@ -0,0 +1,363 @@
+// pushgateway: Pushing metrics to a Prometheus Pushgateway.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use base64::engine::general_purpose::URL_SAFE;
+use base64::Engine;
+use crate::errors::ExporterError;
+use crate::exporter::{
+    Exporter,
+    Format,
+};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{
+    Client,
+    Method,
+    RequestBuilder,
+    Url,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{
+    debug,
+    error,
+    info,
+};
+use super::{
+    client,
+    collect,
+    every,
+    send_with_retries,
+    Credentials,
+    DEFAULT_PUSH_RETRIES,
+};
+
+/// The default value of the `job` grouping label.
+pub const DEFAULT_PUSH_JOB: &str = "jail_exporter";
+
+/// Pushes metrics to a Prometheus Pushgateway, replacing those previously
+/// pushed to the same group.
+pub struct Pushgateway {
+    client:             Client,
+    credentials:        Option<Credentials>,
+    delete_on_shutdown: bool,
+    instance:           Option<String>,
+    job:                String,
+    retries:            u32,
+    url:                Url,
+}
+
+// Returns the path segments for a grouping label. Values that can't be put
+// in a path segment as they are, those containing a / or empty ones, are
+// base64 encoded as the Pushgateway allows.
+fn grouping_segments(name: &str, value: &str) -> [String; 2] {
+    if value.is_empty() {
+        [format!("{name}@base64"), String::from("=")]
+    }
+    else if value.contains('/') {
+        [format!("{name}@base64"), URL_SAFE.encode(value)]
+    }
+    else {
+        [name.to_string(), value.to_string()]
+    }
+}
+
+impl Pushgateway {
+    /// Returns a Pushgateway client pushing to the Pushgateway at the given
+    /// base URL.
+    pub fn new(url: Url) -> Self {
+        debug!("New Pushgateway at: {}", url);
+
+        Self {
+            client:             client(),
+            credentials:        None,
+            delete_on_shutdown: false,
+            instance:           None,
+            job:                DEFAULT_PUSH_JOB.into(),
+            retries:            DEFAULT_PUSH_RETRIES,
+            url:                url,
+        }
+    }
+
+    /// Sets the credentials used to authenticate with the Pushgateway.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        debug!("Setting Pushgateway credentials");
+
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Sets whether our group is deleted from the Pushgateway when we stop.
+    pub fn delete_on_shutdown(mut self, delete: bool) -> Self {
+        debug!("Setting Pushgateway delete_on_shutdown to: {}", delete);
+
+        self.delete_on_shutdown = delete;
+        self
+    }
+
+    /// Sets the `instance` grouping label.
+    pub fn instance(mut self, instance: String) -> Self {
+        debug!("Setting Pushgateway instance to: {}", instance);
+
+        self.instance = Some(instance);
+        self
+    }
+
+    /// Sets the `job` grouping label.
+    pub fn job(mut self, job: String) -> Self {
+        debug!("Setting Pushgateway job to: {}", job);
+
+        self.job = job;
+        self
+    }
+
+    /// Sets the number of times a failed push is retried.
+    pub fn retries(mut self, retries: u32) -> Self {
+        debug!("Setting Pushgateway retries to: {}", retries);
+
+        self.retries = retries;
+        self
+    }
+
+    // Returns the URL of our group, such as
+    // http://pushgateway:9091/metrics/job/jail_exporter/instance/host.
+    fn group_url(&self) -> Url {
+        let mut url = self.url.clone();
+
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .expect("Pushgateway URL to be validated as http(s)");
+
+            segments.pop_if_empty().push("metrics");
+            segments.extend(grouping_segments("job", &self.job));
+
+            if let Some(instance) = &self.instance {
+                segments.extend(grouping_segments("instance", instance));
+            }
+        }
+
+        url
+    }
+
+    // Returns a request to our group, with any credentials.
+    fn request(&self, method: Method) -> RequestBuilder {
+        let request = self.client.request(method, self.group_url());
+
+        match &self.credentials {
+            Some(credentials) => credentials.apply(request),
+            None              => request,
+        }
+    }
+
+    /// Replaces the metrics of our group with the given metrics, in the
+    /// classic Prometheus text format.
+    pub async fn push(&self, metrics: Vec<u8>) -> Result<(), ExporterError> {
+        debug!("Pushing metrics to: {}", self.group_url());
+
+        send_with_retries(self.retries, || {
+            self.request(Method::PUT)
+                .header(CONTENT_TYPE, Format::Text.content_type())
+                .body(metrics.clone())
+        }).await
+    }
+
+    /// Deletes our group, and all of its metrics, from the Pushgateway.
+    pub async fn delete(&self) -> Result<(), ExporterError> {
+        debug!("Deleting metrics from: {}", self.group_url());
+
+        send_with_retries(self.retries, || self.request(Method::DELETE)).await
+    }
+
+    /// Pushes the metrics every interval until we receive SIGINT or SIGTERM,
+    /// then deletes our group if asked to.
+    pub async fn run(
+        &self,
+        exporter: &Arc<Exporter>,
+        interval: Duration,
+    ) -> Result<(), ExporterError> {
+        info!("Pushing metrics to {} every {:?}", self.group_url(), interval);
+
+        every("pushes", interval, self, |pushgateway| {
+            let exporter = Arc::clone(exporter);
+
+            Box::pin(async move {
+                let push = async {
+                    let metrics = collect(exporter, |exporter| {
+                        exporter.export_as(Format::Text)
+                    }).await?;
+
+                    pushgateway.push(metrics).await
+                };
+
+                // A failed push shouldn't stop later ones.
+                if let Err(e) = push.await {
+                    error!("Failed to push metrics: {}", e);
+                }
+            })
+        }).await?;
+
+        if self.delete_on_shutdown {
+            self.delete().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use crate::push::mock::MockReceiver;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn group_url_ok() {
+        let url = Url::parse("http://pushgateway:9091").unwrap();
+        let pushgateway = Pushgateway::new(url)
+            .instance("host".into());
+
+        assert_eq!(
+            pushgateway.group_url().as_str(),
+            "http://pushgateway:9091/metrics/job/jail_exporter/instance/host",
+        );
+    }
+
+    #[test]
+    fn group_url_with_path() {
+        let url = Url::parse("https://example.com/pushgateway/").unwrap();
+        let pushgateway = Pushgateway::new(url)
+            .job("jails".into());
+
+        assert_eq!(
+            pushgateway.group_url().as_str(),
+            "https://example.com/pushgateway/metrics/job/jails",
+        );
+    }
+
+    #[test]
+    fn group_url_base64() {
+        let url = Url::parse("http://pushgateway:9091").unwrap();
+        let pushgateway = Pushgateway::new(url)
+            .job(String::new())
+            .instance("a/b".into());
+
+        assert_eq!(
+            pushgateway.group_url().path(),
+            "/metrics/job@base64/=/instance@base64/YS9i",
+        );
+    }
+
+    #[tokio::test]
+    async fn push_ok() {
+        let (url, mock) = MockReceiver::start(vec![]).await;
+        let pushgateway = Pushgateway::new(url)
+            .instance("host".into());
+
+        pushgateway.push(b"jail_num 1\n".to_vec()).await.unwrap();
+
+        let received = mock.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, Method::PUT);
+        assert_eq!(received[0].path, "/metrics/job/jail_exporter/instance/host");
+        assert_eq!(received[0].header("authorization"), None);
+        assert_eq!(
+            received[0].header("content-type"),
+            Some(Format::Text.content_type()),
+        );
+        assert_eq!(received[0].body_str(), "jail_num 1\n");
+    }
+
+    #[tokio::test]
+    async fn push_basic_auth() {
+        let (url, mock) = MockReceiver::start(vec![]).await;
+        let pushgateway = Pushgateway::new(url)
+            .credentials(Credentials::Basic {
+                username: "user".into(),
+                password: "pass".into(),
+            });
+
+        pushgateway.push(Vec::new()).await.unwrap();
+
+        let authorization = mock.received()[0]
+            .header("authorization")
+            .map(ToString::to_string);
+
+        assert_eq!(authorization.as_deref(), Some("Basic dXNlcjpwYXNz"));
+    }
+
+    #[tokio::test]
+    async fn push_bearer_token() {
+        let (url, mock) = MockReceiver::start(vec![]).await;
+        let pushgateway = Pushgateway::new(url)
+            .credentials(Credentials::Bearer("token".into()));
+
+        pushgateway.push(Vec::new()).await.unwrap();
+
+        let authorization = mock.received()[0]
+            .header("authorization")
+            .map(ToString::to_string);
+
+        assert_eq!(authorization.as_deref(), Some("Bearer token"));
+    }
+
+    #[tokio::test]
+    async fn push_retries_server_errors() {
+        let statuses = vec![
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ];
+
+        let (url, mock) = MockReceiver::start(statuses).await;
+        let pushgateway = Pushgateway::new(url);
+
+        pushgateway.push(Vec::new()).await.unwrap();
+
+        assert_eq!(mock.received().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn push_gives_up() {
+        let statuses = vec![
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ];
+
+        let (url, mock) = MockReceiver::start(statuses).await;
+        let pushgateway = Pushgateway::new(url)
+            .retries(1);
+
+        let res = pushgateway.push(Vec::new()).await;
+
+        assert!(matches!(res, Err(ExporterError::PushError(_))));
+        assert_eq!(mock.received().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn push_client_error_not_retried() {
+        let statuses = vec![StatusCode::BAD_REQUEST];
+
+        let (url, mock) = MockReceiver::start(statuses).await;
+        let pushgateway = Pushgateway::new(url);
+
+        let res = pushgateway.push(Vec::new()).await;
+
+        assert!(matches!(res, Err(ExporterError::PushRejected(_))));
+        assert_eq!(mock.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delete_ok() {
+        let (url, mock) = MockReceiver::start(vec![]).await;
+        let pushgateway = Pushgateway::new(url)
+            .instance("host".into());
+
+        pushgateway.delete().await.unwrap();
+
+        let received = mock.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, Method::DELETE);
+        assert_eq!(received[0].path, "/metrics/job/jail_exporter/instance/host");
+        assert_eq!(received[0].body_str(), "");
+    }
+}
@@ -0,0 +1,508 @@
+// remote_write: Sending metrics to a Prometheus remote_write endpoint.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use crate::errors::ExporterError;
+use crate::exporter::{
+    Exporter,
+    MetricFamily,
+    MetricSample,
+};
+use prost::Message;
+use reqwest::header::{
+    CONTENT_ENCODING,
+    CONTENT_TYPE,
+};
+use reqwest::{
+    Client,
+    RequestBuilder,
+    Url,
+};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{
+    Duration,
+    SystemTime,
+    UNIX_EPOCH,
+};
+use tracing::{
+    debug,
+    error,
+    info,
+    warn,
+};
+use super::{
+    client,
+    collect,
+    every,
+    send_with_retries,
+    Credentials,
+    DEFAULT_PUSH_RETRIES,
+};
+
+/// The default number of collections kept while the endpoint is unavailable.
+/// At the default interval of 15s, this covers an hour long outage.
+pub const DEFAULT_REMOTE_WRITE_QUEUE_SIZE: usize = 240;
+
+// Headers required by version 1.0 of the remote write specification.
+const REMOTE_WRITE_CONTENT_TYPE: &str = "application/x-protobuf";
+const REMOTE_WRITE_ENCODING: &str = "snappy";
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+const REMOTE_WRITE_VERSION_HEADER: &str = "X-Prometheus-Remote-Write-Version";
+
+// Label holding the metric name of a series.
+const METRIC_NAME_LABEL: &str = "__name__";
+
+/// A label name and value, as defined by `prometheus.Label`.
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    /// The label name.
+    #[prost(string, tag = "1")]
+    name: String,
+
+    /// The label value.
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+/// A sample, as defined by `prometheus.Sample`.
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    /// The sample value.
+    #[prost(double, tag = "1")]
+    value: f64,
+
+    /// The time of the sample, in milliseconds since the Unix epoch.
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+/// A time series, as defined by `prometheus.TimeSeries`.
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    /// The labels of the series, sorted by name.
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+
+    /// The samples of the series, oldest first.
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+/// A write request, as defined by `prometheus.WriteRequest`.
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    /// The time series being written.
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+// Returns the time now in milliseconds since the Unix epoch.
+fn now_millis() -> i64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    i64::try_from(now.as_millis()).unwrap_or(i64::MAX)
+}
+
+// Builds the time series of a sample taken at the given timestamp. External
+// labels are added unless the sample already has a label of the same name.
+fn time_series(
+    sample: &MetricSample,
+    external_labels: &[(String, String)],
+    timestamp: i64,
+) -> TimeSeries {
+    let mut labels = vec![
+        Label {
+            name:  METRIC_NAME_LABEL.into(),
+            value: sample.name.clone(),
+        },
+    ];
+
+    let external_labels = external_labels
+        .iter()
+        .filter(|(name, _)| {
+            !sample.labels.iter().any(|(label, _)| label == name)
+        });
+
+    // Empty label values are the same as the label being absent, so they
+    // aren't sent.
+    labels.extend(
+        sample.labels
+            .iter()
+            .chain(external_labels)
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| {
+                Label {
+                    name:  name.clone(),
+                    value: value.clone(),
+                }
+            })
+    );
+
+    labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    TimeSeries {
+        labels:  labels,
+        samples: vec![
+            Sample {
+                value:     sample.value,
+                timestamp: timestamp,
+            },
+        ],
+    }
+}
+
+// Builds a write request holding a sample of every series in the families.
+fn write_request(
+    families: &[MetricFamily],
+    external_labels: &[(String, String)],
+    timestamp: i64,
+) -> WriteRequest {
+    let timeseries = families
+        .iter()
+        .flat_map(|family| &family.samples)
+        .map(|sample| time_series(sample, external_labels, timestamp))
+        .collect();
+
+    WriteRequest {
+        timeseries: timeseries,
+    }
+}
+
+/// Sends metrics to a Prometheus remote_write endpoint, such as Mimir, Thanos
+/// or VictoriaMetrics, following version 1.0 of the remote write
+/// specification.
+///
+/// Each collection is sent as its own write request. Collections that can't
+/// be sent, because the endpoint is unavailable, are queued in memory and
+/// sent, oldest first, once it is available again.
+pub struct RemoteWrite {
+    client:          Client,
+    credentials:     Option<Credentials>,
+    external_labels: Vec<(String, String)>,
+    queue:           VecDeque<Vec<u8>>,
+    queue_size:      usize,
+    retries:         u32,
+    url:             Url,
+}
+
+impl RemoteWrite {
+    /// Returns a remote_write client sending to the given endpoint URL.
+    pub fn new(url: Url) -> Self {
+        debug!("New remote_write endpoint at: {}", url);
+
+        Self {
+            client:          client(),
+            credentials:     None,
+            external_labels: Vec::new(),
+            queue:           VecDeque::new(),
+            queue_size:      DEFAULT_REMOTE_WRITE_QUEUE_SIZE,
+            retries:         DEFAULT_PUSH_RETRIES,
+            url:             url,
+        }
+    }
+
+    /// Sets the credentials used to authenticate with the endpoint.
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        debug!("Setting remote_write credentials");
+
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Sets the labels added to every series sent.
+    pub fn external_labels(mut self, labels: Vec<(String, String)>) -> Self {
+        debug!("Setting remote_write external_labels to: {:?}", labels);
+
+        self.external_labels = labels;
+        self
+    }
+
+    /// Sets the number of collections kept while the endpoint is
+    /// unavailable. Once full, the oldest collection is dropped.
+    pub fn queue_size(mut self, queue_size: usize) -> Self {
+        debug!("Setting remote_write queue_size to: {}", queue_size);
+
+        self.queue_size = queue_size;
+        self
+    }
+
+    /// Sets the number of times a failed write request is retried before it
+    /// is left in the queue.
+    pub fn retries(mut self, retries: u32) -> Self {
+        debug!("Setting remote_write retries to: {}", retries);
+
+        self.retries = retries;
+        self
+    }
+
+    // Returns a request sending the given body, with any credentials.
+    fn request(&self, body: &[u8]) -> RequestBuilder {
+        let request = self.client
+            .post(self.url.clone())
+            .header(CONTENT_ENCODING, REMOTE_WRITE_ENCODING)
+            .header(CONTENT_TYPE, REMOTE_WRITE_CONTENT_TYPE)
+            .header(REMOTE_WRITE_VERSION_HEADER, REMOTE_WRITE_VERSION)
+            .body(body.to_vec());
+
+        match &self.credentials {
+            Some(credentials) => credentials.apply(request),
+            None              => request,
+        }
+    }
+
+    // Encodes the families as a snappy compressed write request and adds it
+    // to the queue, dropping the oldest queued request if the queue is full.
+    fn enqueue(&mut self, families: &[MetricFamily], timestamp: i64) {
+        let request = write_request(families, &self.external_labels, timestamp);
+        let body = snap::raw::Encoder::new()
+            .compress_vec(&request.encode_to_vec())
+            .expect("write request to fit in a snappy block");
+
+        if self.queue.len() >= self.queue_size {
+            warn!("remote_write queue is full, dropping the oldest collection");
+            self.queue.pop_front();
+        }
+
+        self.queue.push_back(body);
+    }
+
+    // Sends the queued write requests, oldest first, until the queue is
+    // empty or the endpoint stops accepting them. Requests that the endpoint
+    // rejects are dropped, since sending them again wouldn't help.
+    async fn flush(&mut self) {
+        while let Some(body) = self.queue.front() {
+            let result = send_with_retries(self.retries, || {
+                self.request(body)
+            }).await;
+
+            match result {
+                Ok(()) => {},
+                Err(e @ ExporterError::PushRejected(_)) => {
+                    error!("Dropping collection rejected by remote_write endpoint: {}", e);
+                },
+                Err(e) => {
+                    warn!(
+                        "remote_write endpoint unavailable, {} collections queued: {}",
+                        self.queue.len(),
+                        e,
+                    );
+
+                    break;
+                },
+            }
+
+            self.queue.pop_front();
+        }
+    }
+
+    /// Collects and sends the metrics every interval until we receive SIGINT
+    /// or SIGTERM. Collections still queued when we stop are lost.
+    pub async fn run(
+        &mut self,
+        exporter: &Arc<Exporter>,
+        interval: Duration,
+    ) -> Result<(), ExporterError> {
+        info!("Sending metrics to {} every {:?}", self.url, interval);
+
+        every("remote_write", interval, &mut *self, |remote_write| {
+            let exporter = Arc::clone(exporter);
+
+            Box::pin(async move {
+                match collect(exporter, Exporter::families).await {
+                    Ok(families) => remote_write.enqueue(&families, now_millis()),
+                    Err(e)       => error!("Failed to collect metrics: {}", e),
+                }
+
+                remote_write.flush().await;
+            })
+        }).await?;
+
+        if !self.queue.is_empty() {
+            warn!("Dropping {} unsent collections", self.queue.len());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{
+        Method,
+        StatusCode,
+    };
+    use crate::push::mock::{
+        MockReceiver,
+        Received,
+    };
+    use pretty_assertions::assert_eq;
+
+    fn families() -> Vec<MetricFamily> {
+        vec![
+            MetricFamily {
+                name:        "jail_cputime_seconds".into(),
+                help:        "CPU time, in seconds.".into(),
+                metric_type: "counter".into(),
+                unit:        Some("seconds".into()),
+                samples:     vec![
+                    MetricSample {
+                        name:   "jail_cputime_seconds_total".into(),
+                        labels: vec![
+                            ("name".into(), "web".into()),
+                            ("env".into(), "".into()),
+                        ],
+                        value:  12.5,
+                    },
+                ],
+            },
+        ]
+    }
+
+    fn label(name: &str, value: &str) -> Label {
+        Label {
+            name:  name.into(),
+            value: value.into(),
+        }
+    }
+
+    // Decodes the write request received by the mock receiver.
+    fn decode(received: &Received) -> WriteRequest {
+        let body = snap::raw::Decoder::new()
+            .decompress_vec(&received.body)
+            .unwrap();
+
+        WriteRequest::decode(body.as_slice()).unwrap()
+    }
+
+    // Returns the timestamp of each write request received.
+    fn timestamps(mock: &MockReceiver) -> Vec<i64> {
+        mock.received()
+            .iter()
+            .map(|received| decode(received).timeseries[0].samples[0].timestamp)
+            .collect()
+    }
+
+    #[test]
+    fn write_request_ok() {
+        let external_labels = vec![
+            ("cluster".into(), "edge".into()),
+            ("name".into(), "ignored".into()),
+        ];
+
+        let request = write_request(&families(), &external_labels, 1000);
+
+        assert_eq!(request, WriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels:  vec![
+                        label("__name__", "jail_cputime_seconds_total"),
+                        label("cluster", "edge"),
+                        label("name", "web"),
+                    ],
+                    samples: vec![
+                        Sample {
+                            value:     12.5,
+                            timestamp: 1000,
+                        },
+                    ],
+                },
+            ],
+        });
+    }
+
+    #[tokio::test]
+    async fn flush_ok() {
+        let (url, mock) = MockReceiver::start(vec![]).await;
+        let url = url.join("api/v1/write").unwrap();
+        let mut remote_write = RemoteWrite::new(url)
+            .credentials(Credentials::Bearer("token".into()));
+
+        remote_write.enqueue(&families(), 1000);
+        remote_write.flush().await;
+
+        let received = mock.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, Method::POST);
+        assert_eq!(received[0].path, "/api/v1/write");
+        assert_eq!(received[0].header("authorization"), Some("Bearer token"));
+        assert_eq!(received[0].header("content-encoding"), Some("snappy"));
+        assert_eq!(
+            received[0].header("content-type"),
+            Some("application/x-protobuf"),
+        );
+        assert_eq!(
+            received[0].header("x-prometheus-remote-write-version"),
+            Some("0.1.0"),
+        );
+        assert_eq!(
+            decode(&received[0]),
+            write_request(&families(), &[], 1000),
+        );
+        assert!(remote_write.queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn flush_queues_during_outage() {
+        let statuses = vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::SERVICE_UNAVAILABLE,
+        ];
+
+        let (url, mock) = MockReceiver::start(statuses).await;
+        let mut remote_write = RemoteWrite::new(url)
+            .retries(0);
+
+        remote_write.enqueue(&families(), 1000);
+        remote_write.flush().await;
+        remote_write.enqueue(&families(), 2000);
+        remote_write.flush().await;
+
+        assert_eq!(remote_write.queue.len(), 2);
+
+        // Once the endpoint is back, everything queued is sent in order.
+        remote_write.enqueue(&families(), 3000);
+        remote_write.flush().await;
+
+        assert!(remote_write.queue.is_empty());
+        assert_eq!(timestamps(&mock)[2..], [1000, 2000, 3000]);
+    }
+
+    #[tokio::test]
+    async fn flush_drops_oldest_when_full() {
+        let statuses = vec![StatusCode::INTERNAL_SERVER_ERROR; 3];
+
+        let (url, mock) = MockReceiver::start(statuses).await;
+        let mut remote_write = RemoteWrite::new(url)
+            .queue_size(2)
+            .retries(0);
+
+        for timestamp in [1000, 2000, 3000] {
+            remote_write.enqueue(&families(), timestamp);
+            remote_write.flush().await;
+        }
+
+        assert_eq!(remote_write.queue.len(), 2);
+
+        remote_write.flush().await;
+
+        assert_eq!(timestamps(&mock), [1000, 1000, 2000, 2000, 3000]);
+    }
+
+    #[tokio::test]
+    async fn flush_drops_rejected() {
+        let statuses = vec![StatusCode::BAD_REQUEST];
+
+        let (url, mock) = MockReceiver::start(statuses).await;
+        let mut remote_write = RemoteWrite::new(url);
+
+        remote_write.enqueue(&families(), 1000);
+        remote_write.enqueue(&families(), 2000);
+        remote_write.flush().await;
+
+        assert!(remote_write.queue.is_empty());
+        assert_eq!(timestamps(&mock), [1000, 2000]);
+    }
+}
@@ -17,11 +17,11 @@ pub fn is_available() -> Result<(), ExporterError> {
         },
         RctlState::Enabled => Ok(()),
         RctlState::Jailed => {
-            // This isn't strictly true. Jail exporter should be able to run
-            // within a jail, for situations where a user has jails within
-            // jails. It is just untested at the moment.
+            // Within a jail, we can only report on child jails, so there's
+            // nothing to do if the jail can't have any.
             Err(ExporterError::RctlUnavailable(
-                "Jail Exporter cannot run within a jail".to_owned()
+                "Jail Exporter can only run within a jail with \
+                 children.max > 0".to_owned()
             ))
         },
         RctlState::NotPresent => {
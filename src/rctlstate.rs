@@ -15,13 +15,15 @@ pub enum RctlState {
     NotPresent,
 }
 
-const CTL_KERN_RACCT_ENABLE: &str    = "kern.racct.enable";
-const CTL_SECURITY_JAIL_JAILED: &str = "security.jail.jailed";
+const CTL_KERN_RACCT_ENABLE: &str            = "kern.racct.enable";
+const CTL_SECURITY_JAIL_CHILDREN_MAX: &str   = "security.jail.children.max";
+const CTL_SECURITY_JAIL_JAILED: &str         = "security.jail.jailed";
 
 impl RctlState {
     pub fn check() -> Self {
-        // Quick check to see if we're in a jail
-        if Self::jailed() {
+        // Quick check to see if we're in a jail. Within a jail, we can only
+        // be of use if the jail may have child jails of its own.
+        if Self::jailed() && !Self::may_have_children() {
             return Self::Jailed;
         }
 
@@ -50,6 +52,18 @@ impl RctlState {
         }
     }
 
+    // Checks whether the jail we're running in may create child jails.
+    fn may_have_children() -> bool {
+        let res = Ctl::new(CTL_SECURITY_JAIL_CHILDREN_MAX);
+
+        // If any error occurs, assume we may not
+        let Ok(ctl) = res else {
+            return false;
+        };
+
+        matches!(ctl.value(), Ok(CtlValue::Int(max)) if max > 0)
+    }
+
     fn jailed() -> bool {
         let res = Ctl::new(CTL_SECURITY_JAIL_JAILED);
 
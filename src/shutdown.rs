@@ -0,0 +1,33 @@
+// shutdown: Waiting for the signals asking a long running mode to stop.
+#![forbid(unsafe_code)]
+#![deny(missing_docs)]
+use std::io;
+use tokio::signal::unix::{
+    signal,
+    Signal,
+    SignalKind,
+};
+
+// Listens for SIGINT and SIGTERM. Listening starts when this is created, so
+// signals received while busy elsewhere aren't missed.
+pub struct Shutdown {
+    sigint:  Signal,
+    sigterm: Signal,
+}
+
+impl Shutdown {
+    pub fn new() -> Result<Self, io::Error> {
+        Ok(Self {
+            sigint:  signal(SignalKind::interrupt())?,
+            sigterm: signal(SignalKind::terminate())?,
+        })
+    }
+
+    // Waits for SIGINT or SIGTERM, returning the name of the signal.
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.sigint.recv()  => "SIGINT",
+            _ = self.sigterm.recv() => "SIGTERM",
+        }
+    }
+}
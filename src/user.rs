@@ -2,7 +2,10 @@
 #![deny(missing_docs)]
 use crate::errors::ExporterError;
 use tracing::debug;
-use uzers::Users;
+use uzers::{
+    Groups,
+    Users,
+};
 
 // Checks that we're running as root.
 pub fn is_running_as_root<U: Users>(users: &mut U) -> Result<(), ExporterError> {
@@ -14,6 +17,28 @@ pub fn is_running_as_root<U: Users>(users: &mut U) -> Result<(), ExporterError>
     }
 }
 
+// Returns the uid of the named user. Names that aren't known users are
+// accepted if they're numeric, as with chown(8).
+pub fn uid_by_name<U: Users>(users: &U, name: &str) -> Option<u32> {
+    debug!("Looking up uid of user: {}", name);
+
+    match users.get_user_by_name(name) {
+        Some(user) => Some(user.uid()),
+        None       => name.parse::<u32>().ok(),
+    }
+}
+
+// Returns the gid of the named group. Names that aren't known groups are
+// accepted if they're numeric, as with chown(8).
+pub fn gid_by_name<G: Groups>(groups: &G, name: &str) -> Option<u32> {
+    debug!("Looking up gid of group: {}", name);
+
+    match groups.get_group_by_name(name) {
+        Some(group) => Some(group.gid()),
+        None        => name.parse::<u32>().ok(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -24,6 +49,15 @@ mod tests {
     };
     use uzers::os::unix::UserExt;
 
+    fn users() -> MockUsers {
+        let mut users = MockUsers::with_current_uid(0);
+        users.add_user(User::new(0, "root", 0));
+        users.add_user(User::new(52, "nodeexp", 52));
+        users.add_group(Group::new(0, "wheel"));
+        users.add_group(Group::new(52, "nodeexp"));
+        users
+    }
+
     #[test]
     fn is_running_as_root_ok() {
         let mut users = MockUsers::with_current_uid(0);
@@ -47,4 +81,30 @@ mod tests {
 
         assert!(is_root.is_err());
     }
+
+    #[test]
+    fn uid_by_name_ok() {
+        let users = users();
+
+        assert_eq!(uid_by_name(&users, "nodeexp"), Some(52));
+        assert_eq!(uid_by_name(&users, "1001"), Some(1001));
+    }
+
+    #[test]
+    fn uid_by_name_unknown() {
+        assert_eq!(uid_by_name(&users(), "nobody"), None);
+    }
+
+    #[test]
+    fn gid_by_name_ok() {
+        let users = users();
+
+        assert_eq!(gid_by_name(&users, "wheel"), Some(0));
+        assert_eq!(gid_by_name(&users, "1001"), Some(1001));
+    }
+
+    #[test]
+    fn gid_by_name_unknown() {
+        assert_eq!(gid_by_name(&users(), "nogroup"), None);
+    }
 }